@@ -1,4 +1,10 @@
-use std::sync::{Arc, RwLockReadGuard};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLockReadGuard,
+    },
+};
 
 use anyhow::{anyhow, Result};
 
@@ -10,9 +16,13 @@ use tokio::{
 };
 
 use crate::{
-    protocol::ethjson::EthClientObject,
-    proxy::{Job, Proxy},
-    util::config::Settings,
+    protocol::{
+        ethjson::EthClientObject, rpc::eth::handle_error, CLIENT_LOGIN,
+        CLIENT_SUBHASHRATE,
+    },
+    proxy::{FeeShare, Job, Proxy},
+    state::fee::FeeTargetStats,
+    util::config::{FeeDestination, Settings},
 };
 
 use crate::{
@@ -23,7 +33,7 @@ use crate::{
     },
 };
 
-use super::write_to_socket_byte;
+use super::{write_to_socket_byte, TCP};
 
 use tracing::{debug, info};
 
@@ -112,8 +122,49 @@ pub async fn develop_fee_ssl(
     Ok(())
 }
 
+// 已提交等待矿池结果的份额 只保留最近的
+const PENDING_SHARES: usize = 1000;
+// 份额编号从此开始 避开登录 提交算力等请求的固定编号
+const FIRST_SHARE_ID: u64 = 10000;
+
+struct PendingShares {
+    next_id: u64,
+    shares: VecDeque<(u64, Arc<AtomicU64>)>,
+}
+
+impl PendingShares {
+    fn new() -> Self {
+        Self {
+            next_id: FIRST_SHARE_ID,
+            shares: VecDeque::new(),
+        }
+    }
+
+    // 返回提交份额使用的编号
+    fn push(&mut self, accepted: Arc<AtomicU64>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.shares.len() >= PENDING_SHARES {
+            self.shares.pop_front();
+        }
+        self.shares.push_back((id, accepted));
+        id
+    }
+
+    // 矿池接受后计入提交份额的矿工
+    fn accept(&mut self, id: u64) {
+        if let Some(i) = self.shares.iter().position(|(i, _)| *i == id) {
+            if let Some((_, accepted)) = self.shares.remove(i) {
+                accepted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn reject(&mut self, id: u64) { self.shares.retain(|(i, _)| *i != id) }
+}
+
 pub async fn fee_ssl(
-    mut rx: Receiver<Vec<String>>, job: Job,
+    mut rx: Receiver<FeeShare>, job: Job,
     mut proxy_lines: Lines<
         BufReader<
             tokio::io::ReadHalf<
@@ -124,13 +175,9 @@ pub async fn fee_ssl(
     mut w: tokio::io::WriteHalf<
         tokio_native_tls::TlsStream<tokio::net::TcpStream>,
     >,
-    worker_name: String, proxy: Arc<Proxy>,
+    dest: FeeDestination, stats: Arc<FeeTargetStats>,
 ) -> Result<()> {
-    let mut config: Settings;
-    {
-        let rconfig = proxy.config.read().await;
-        config = rconfig.clone();
-    }
+    let worker_name = dest.get_worker_name()?;
     let mut get_work = EthClientRootObject {
         id: 6,
         method: "eth_getWork".into(),
@@ -146,7 +193,7 @@ pub async fn fee_ssl(
 
     let sleep = tokio::time::sleep(tokio::time::Duration::from_secs(20));
     tokio::pin!(sleep);
-    let mut share_job_idx: u64 = 0;
+    let mut pending = PendingShares::new();
 
     loop {
         select! {
@@ -157,7 +204,9 @@ pub async fn fee_ssl(
                         //return Err(anyhow!("抽水旷工掉线了a"));
                         // info!(worker_name =
             //       ?worker_name,"退出了。重新登录到池!!");
-             let (new_lines, dev_w) = crate::client::proxy_pool_login_with_ssl(&config,config.share_name.clone()).await?;
+                        stats.set_connected(false);
+             let (new_lines, dev_w) = crate::client::proxy_pool_login_with_ssl(&dest).await?;
+                        stats.set_connected(true);
                         //同时加2个值
                         w = dev_w;
                         proxy_lines = new_lines;
@@ -175,15 +224,24 @@ pub async fn fee_ssl(
             j.push_back(job_res)
             }
                 } else if let Ok(result_rpc) = serde_json::from_str::<EthServer>(&buffer) {
+                    if result_rpc.id != CLIENT_LOGIN && result_rpc.id != CLIENT_SUBHASHRATE {
+                        if result_rpc.result {
+                            FeeTargetStats::add(&stats.accepted);
+                            pending.accept(result_rpc.id);
+                        } else {
+                            pending.reject(result_rpc.id);
+                            let (reason, message) = handle_error(&worker_name, buffer.as_bytes());
+                            stats.reject(reason, &message);
+                        }
+                    }
                     if result_rpc.result == false {
                         tracing::debug!(worker_name = ?worker_name,rpc = ?buffer,"线程获得操作结果 {:?}",result_rpc.result);
                     }
                 }
             },
-            Some(params) = rx.recv() => {
-                share_job_idx+=1;
-                json_rpc.id = share_job_idx;
-        json_rpc.params = params;
+            Some(share) = rx.recv() => {
+                json_rpc.id = pending.push(share.accepted);
+        json_rpc.params = share.params;
                 write_to_socket_byte(&mut w, json_rpc.to_vec()?, &worker_name).await?;
             },
             () = &mut sleep  => {
@@ -196,18 +254,14 @@ pub async fn fee_ssl(
     Ok(())
 }
 pub async fn fee_tcp(
-    mut rx: Receiver<Vec<String>>, job: Job,
+    mut rx: Receiver<FeeShare>, job: Job,
     mut proxy_lines: Lines<
         BufReader<tokio::io::ReadHalf<tokio::net::TcpStream>>,
     >,
-    mut w: tokio::io::WriteHalf<tokio::net::TcpStream>, worker_name: String,
-    proxy: Arc<Proxy>,
+    mut w: tokio::io::WriteHalf<tokio::net::TcpStream>, dest: FeeDestination,
+    stats: Arc<FeeTargetStats>,
 ) -> Result<()> {
-    let mut config: Settings;
-    {
-        let rconfig = proxy.config.read().await;
-        config = rconfig.clone();
-    }
+    let worker_name = dest.get_worker_name()?;
     let mut get_work = EthClientRootObject {
         id: 6,
         method: "eth_getWork".into(),
@@ -223,7 +277,7 @@ pub async fn fee_tcp(
 
     let sleep = tokio::time::sleep(tokio::time::Duration::from_secs(20));
     tokio::pin!(sleep);
-    let mut share_job_idx: u64 = 0;
+    let mut pending = PendingShares::new();
 
     loop {
         select! {
@@ -232,7 +286,9 @@ pub async fn fee_tcp(
                     Ok(buf) => buf,
                     Err(_) => {
 
-             let (new_lines, dev_w) = crate::client::proxy_pool_login(&config,config.share_name.clone()).await?;
+                        stats.set_connected(false);
+             let (new_lines, dev_w) = crate::client::proxy_pool_login(&dest).await?;
+                        stats.set_connected(true);
                         //同时加2个值
                         w = dev_w;
                         proxy_lines = new_lines;
@@ -250,15 +306,24 @@ pub async fn fee_tcp(
             j.push_back(job_res)
             }
                 } else if let Ok(result_rpc) = serde_json::from_str::<EthServer>(&buffer) {
+                    if result_rpc.id != CLIENT_LOGIN && result_rpc.id != CLIENT_SUBHASHRATE {
+                        if result_rpc.result {
+                            FeeTargetStats::add(&stats.accepted);
+                            pending.accept(result_rpc.id);
+                        } else {
+                            pending.reject(result_rpc.id);
+                            let (reason, message) = handle_error(&worker_name, buffer.as_bytes());
+                            stats.reject(reason, &message);
+                        }
+                    }
                     if result_rpc.result == false {
                         tracing::debug!(worker_name = ?worker_name,rpc = ?buffer,"线程获得操作结果 {:?}",result_rpc.result);
                     }
                 }
             },
-            Some(params) = rx.recv() => {
-                share_job_idx+=1;
-                json_rpc.id = share_job_idx;
-        json_rpc.params = params;
+            Some(share) = rx.recv() => {
+                json_rpc.id = pending.push(share.accepted);
+        json_rpc.params = share.params;
                 write_to_socket_byte(&mut w, json_rpc.to_vec()?, &worker_name).await?;
             },
             () = &mut sleep  => {
@@ -271,6 +336,55 @@ pub async fn fee_tcp(
     Ok(())
 }
 
+// 登录抽水目标矿池并转发抽水份额
+pub async fn fee_pool(
+    rx: Receiver<FeeShare>, job: Job, dest: FeeDestination,
+    stats: Arc<FeeTargetStats>,
+) -> Result<()> {
+    let (stream_type, _) =
+        crate::client::get_pool_ip_and_type_from_vec(&dest.pool_address)?;
+
+    if stream_type == TCP {
+        let (proxy_lines, proxy_w) =
+            crate::client::proxy_pool_login(&dest).await?;
+        stats.set_connected(true);
+        fee_tcp(rx, job, proxy_lines, proxy_w, dest, stats).await
+    } else {
+        let (proxy_lines, proxy_w) =
+            crate::client::proxy_pool_login_with_ssl(&dest).await?;
+        stats.set_connected(true);
+        fee_ssl(rx, job, proxy_lines, proxy_w, dest, stats).await
+    }
+}
+
+// 每个抽水目标单独一个连接任务。任意一个任务出错时返回
+pub async fn fee_all(
+    targets: Vec<(
+        Receiver<FeeShare>,
+        Job,
+        FeeDestination,
+        Arc<FeeTargetStats>,
+    )>,
+) -> Result<()> {
+    let (err_tx, mut err_rx) = tokio::sync::mpsc::unbounded_channel();
+    for (rx, job, dest, stats) in targets {
+        let err_tx = err_tx.clone();
+        tokio::spawn(async move {
+            let wallet = dest.wallet.clone();
+            if let Err(e) = fee_pool(rx, job, dest, stats).await {
+                tracing::error!("抽水目标 {} 异常退出 {}", wallet, e);
+                let _ = err_tx.send(e);
+            }
+        });
+    }
+    drop(err_tx);
+
+    match err_rx.recv().await {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
 pub async fn fee<W: 'static, R: 'static>(
     rx: Receiver<Vec<String>>, job: Job,
     proxy_lines: Lines<BufReader<tokio::io::ReadHalf<R>>>, w: WriteHalf<W>,
@@ -523,3 +637,24 @@ where
 //         }
 //     }
 // }
+
+#[test]
+fn test_pending_share_ids() {
+    use crate::protocol::{CLIENT_GETWORK, CLIENT_LOGIN, CLIENT_SUBHASHRATE};
+
+    let mut pending = PendingShares::new();
+    let accepted = Arc::new(AtomicU64::new(0));
+    let ids: Vec<u64> = (0..PENDING_SHARES + 10)
+        .map(|_| pending.push(accepted.clone()))
+        .collect();
+    for reserved in [CLIENT_LOGIN, CLIENT_GETWORK, CLIENT_SUBHASHRATE] {
+        assert!(!ids.contains(&reserved));
+    }
+
+    // 超出数量的最早份额不再计入
+    pending.accept(ids[0]);
+    pending.accept(ids[PENDING_SHARES + 9]);
+    pending.reject(ids[PENDING_SHARES + 8]);
+    pending.accept(ids[PENDING_SHARES + 8]);
+    assert_eq!(accepted.load(Ordering::Relaxed), 1);
+}
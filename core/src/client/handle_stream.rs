@@ -1,5 +1,8 @@
 use anyhow::{bail, Result};
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 use tracing::{debug, info};
 
 use tokio::{
@@ -13,20 +16,60 @@ use crate::{
     client::*,
     protocol::{
         ethjson::{EthServerRoot, EthServerRootObject},
+        rpc::eth::handle_error_for_worker,
         CLIENT_LOGIN, CLIENT_SUBMITWORK,
     },
-    state::Worker,
-    util::{config::Settings, is_fee_random},
+    proxy::{FeeShare, SessionControl},
+    state::{
+        event::{EventKind, SessionEvent},
+        fee::FeeTargetStats,
+        Worker,
+    },
+    util::{config::Settings, is_fee_random, weighted_index},
 };
 
 use crate::{
     protocol::ethjson::{
-        login, new_eth_get_work, new_eth_submit_hashrate, new_eth_submit_work,
-        EthServer, EthServerRootObjectJsonRpc,
+        login, new_eth_get_work, new_eth_submit_hashrate, new_eth_submit_login,
+        new_eth_submit_work, EthClientObject, EthClientReconnect, EthServer,
+        EthServerRootObjectJsonRpc,
     },
     DEVELOP_FEE,
 };
 
+// 统一钱包模式下替换为收款钱包 保留矿工名
+async fn submit_login<W>(
+    worker: &mut Worker, w: &mut WriteHalf<W>,
+    rpc: &mut Box<dyn EthClientObject + Send + Sync>, worker_name: &mut String,
+    config: &Settings, proxy: &Proxy,
+) -> Result<()>
+where
+    W: AsyncWrite,
+{
+    let res = if config.is_unified_wallet() {
+        new_eth_submit_login(worker, w, rpc, worker_name, config).await
+    } else {
+        login(worker, w, rpc, worker_name, config).await.map(|_| ())
+    };
+
+    if let Err(e) = &res {
+        proxy.send_event(SessionEvent::new(
+            EventKind::LoginFailed,
+            worker,
+            e.to_string(),
+        ));
+    }
+    res
+}
+
+// mining.subscribe 第一个参数是钱包时才是登录请求
+fn is_wallet_login(rpc: &(dyn EthClientObject + Send + Sync)) -> bool {
+    match rpc.get_eth_wallet() {
+        Some(wallet) => wallet.starts_with("0x") || wallet.starts_with("0X"),
+        None => false,
+    }
+}
+
 pub async fn handle_stream<R, W, PR, PW>(
     worker: &mut Worker,
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
@@ -53,7 +96,8 @@ where
         result: vec![],
     };
 
-    let mut fee_job: Vec<String> = Vec::new();
+    // 已发送的抽水任务 及其所属的抽水目标
+    let mut fee_job: Vec<(String, usize)> = Vec::new();
     let mut dev_fee_job: Vec<String> = Vec::new();
 
     //最后一次发送的rpc_id
@@ -81,7 +125,8 @@ where
 
     // let mut chan = proxy.chan.subscribe();
     // let mut dev_chan = proxy.dev_chan.subscribe();
-    let tx = proxy.tx.clone();
+    let fee_weights: Vec<u32> =
+        proxy.fee_targets.iter().map(|t| t.weight).collect();
     let dev_tx = proxy.dev_tx.clone();
 
     // 当前Job高度。
@@ -94,12 +139,23 @@ where
     let mut wait_job: VecDeque<Vec<String>> = VecDeque::new();
     let mut wait_dev_job: VecDeque<Vec<String>> = VecDeque::new();
 
-    let config: Settings;
+    let mut config: Settings;
     {
         let rconfig = RwLockReadGuard::map(proxy.config.read().await, |s| s);
         config = rconfig.clone();
     }
 
+    // 当前矿工的抽水费率。登录后按抽水规则重新计算
+    let mut share_rate = config.share_rate;
+
+    // 抽水矿池接受的份额数 上报状态时计入 fee_accept_index
+    let fee_accepted = Arc::new(AtomicU64::new(0));
+
+    // 主控端可通过会话编号查询状态或断开连接
+    let (_session, mut control) = proxy.sessions.register(worker.session_id);
+    // EthereumStratum 协议的矿机 排空时可通知重连
+    let mut is_stratum = false;
+
     loop {
         select! {
             res = worker_lines.next_line() => {
@@ -111,7 +167,8 @@ where
                         let res = match json_rpc.get_method().as_str() {
                             "eth_submitLogin" => {
                                 eth_server_result.id = rpc_id;
-                                login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config).await?;
+                                submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config,&proxy).await?;
+                                share_rate = config.get_share_rate(worker);
                                 write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name).await?;
                                 Ok(())
                             },
@@ -129,12 +186,17 @@ where
                         debug!("开发者通道已满.{}",e);
                         },
                     }
-                                    } else if fee_job.contains(&job_id) {
+                                    } else if let Some((_, idx)) = fee_job.iter().find(|(id, _)| *id == job_id) {
                                         worker.fee_share_index_add();
-                                        worker.fee_share_accept();
-                    match tx.try_send(json_rpc.get_params()) {
-                        Ok(()) => {},
+                    let stats = &proxy.fee_targets[*idx].stats;
+                    let share = FeeShare {
+                        params: json_rpc.get_params(),
+                        accepted: fee_accepted.clone(),
+                    };
+                    match proxy.fee_targets[*idx].tx.try_send(share) {
+                        Ok(()) => FeeTargetStats::add(&stats.fee_shares),
                         Err(e) => {
+                        FeeTargetStats::add(&stats.dropped);
                         debug!("中转通道已满.{}",e);
                         },
                     }
@@ -167,7 +229,22 @@ where
                                 Ok(())
                             },
                             "mining.subscribe" =>{ //GMiner
-                                new_eth_get_work(&mut pool_w,&mut json_rpc,&worker_name).await?;
+                                is_stratum = true;
+                                // Stratum 在 subscribe 中携带钱包登录
+                                if is_wallet_login(json_rpc.as_ref()) {
+                                    submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config,&proxy).await?;
+                                    share_rate = config.get_share_rate(worker);
+                                } else {
+                                    new_eth_get_work(&mut pool_w,&mut json_rpc,&worker_name).await?;
+                                }
+                                eth_server_result.id = rpc_id;
+                                write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name).await?;
+                                Ok(())
+                            }
+                            "mining.authorize" => {
+                                is_stratum = true;
+                                submit_login(worker,&mut pool_w,&mut json_rpc,&mut worker_name,&config,&proxy).await?;
+                                share_rate = config.get_share_rate(worker);
                                 eth_server_result.id = rpc_id;
                                 write_rpc(is_encrypted,&mut worker_w,&eth_server_result,&worker_name).await?;
                                 Ok(())
@@ -199,6 +276,17 @@ where
                 if let Ok(rpc) = serde_json::from_str::<EthServerRootObject>(&buffer) {
                     // 增加索引
                     worker.send_job()?;
+                    // 配置重新加载后 从下一个任务开始生效
+                    {
+                        let rconfig = RwLockReadGuard::map(proxy.config.read().await, |s| s);
+                        if rconfig.fee_rules != config.fee_rules
+                            || rconfig.share_rate != config.share_rate
+                            || rconfig.hash_rate != config.hash_rate
+                        {
+                            config = rconfig.clone();
+                            share_rate = config.get_share_rate(worker);
+                        }
+                    }
                     if is_fee_random(*DEVELOP_FEE) {
                         #[cfg(debug_assertions)]
                         debug!("进入开发者抽水回合");
@@ -229,22 +317,26 @@ where
                         //     write_rpc(is_encrypted,&mut worker_w,&job_rpc,&worker_name).await?;
                         //     continue;
                         // }
-                    } else if is_fee_random(config.share_rate.into()) {
+                    } else if share_rate > 0.0 && is_fee_random(share_rate.into()) {
                         #[cfg(debug_assertions)]
                         debug!("进入普通抽水回合");
 
 
-			let fee = RwLockReadGuard::map(proxy.fee_job.read().await, |f| f);
+			// 按权重选择本回合的抽水目标
+			if let Some(idx) = weighted_index(&fee_weights) {
+			let fee = RwLockReadGuard::map(proxy.fee_targets[idx].job.read().await, |f| f);
 			if let Some(job_res) = fee.back() {
                             worker.send_fee_job()?;
                             job_rpc.result = job_res.clone();
                             let job_id = job_rpc.get_job_id().unwrap();
-                            fee_job.push(job_id.clone());
+                            fee_job.push((job_id.clone(), idx));
+                            FeeTargetStats::add(&proxy.fee_targets[idx].stats.fee_jobs);
                             #[cfg(debug_assertions)]
                             debug!("{} 发送抽水任务 #{:?}",worker_name, job_rpc);
                             write_rpc(is_encrypted,&mut worker_w,&job_rpc,&worker_name).await?;
                             continue;
                         }
+			}
 			//                        if let Some(job_res) = wait_job.pop_back() {
 			// if let Ok(job_res) = chan.recv().await {
                         //     worker.send_fee_job()?;
@@ -266,12 +358,16 @@ where
                     debug!("{} 发送普通任务 #{:?}",worker_name, job_rpc);
                     write_rpc(is_encrypted,&mut worker_w,&job_rpc,&worker_name).await?;
                 } else if let Ok(result_rpc) = serde_json::from_str::<EthServer>(&buffer) {
-                    if result_rpc.id == CLIENT_LOGIN {
+                    if result_rpc.id == CLIENT_LOGIN && result_rpc.result {
                         worker.logind();
+                        proxy.send_event(SessionEvent::new(EventKind::Login, worker, "".into()));
+                    } else if result_rpc.id == CLIENT_LOGIN {
+                        proxy.send_event(SessionEvent::new(EventKind::LoginFailed, worker, "矿池拒绝登录".into()));
                     } else if result_rpc.id == CLIENT_SUBMITWORK && result_rpc.result {
                         worker.share_accept();
                     } else if result_rpc.id == CLIENT_SUBMITWORK {
-                        worker.share_reject();
+                        let (reason, message) = handle_error_for_worker(&worker_name, buffer.as_bytes());
+                        worker.share_reject_with(reason, &message);
                     }
                 }
             },
@@ -298,6 +394,7 @@ where
 		    wait_job = wait_job.drain(900..).collect();
 		}
 		
+                worker.fee_accept_index += fee_accepted.swap(0, Ordering::Relaxed);
                 match workers_queue.send(worker.clone()) {
                     Ok(_) => {},
                     Err(_) => {
@@ -306,6 +403,152 @@ where
                 };
                 sleep.as_mut().reset(time::Instant::now() + time::Duration::from_secs(send_time));
             },
+            Some(ctl) = control.recv() => {
+                match ctl {
+                    SessionControl::Kick(reason) => {
+                        pool_w.shutdown().await?;
+                        worker_w.shutdown().await?;
+                        bail!("管理员断开 {}", reason);
+                    },
+                    SessionControl::Report(tx) => {
+                        let _ = tx.send(worker.clone());
+                    },
+                    // EthProxy 协议无法通知 等待矿机自行断开或排空超时
+                    SessionControl::Reconnect if is_stratum => {
+                        write_rpc(is_encrypted,&mut worker_w,&EthClientReconnect::default(),&worker_name).await?;
+                        pool_w.shutdown().await?;
+                        worker_w.shutdown().await?;
+                        bail!("中转排空 通知矿机重连");
+                    },
+                    SessionControl::Reconnect => {},
+                }
+            },
         }
     }
 }
+
+#[cfg(test)]
+fn test_proxy(
+    config: Settings,
+) -> (Proxy, tokio::sync::mpsc::UnboundedReceiver<SessionEvent>) {
+    use tokio::sync::{mpsc, watch, RwLock};
+    use tokio_rustls::rustls::{
+        server::ResolvesServerCertUsingSni, ServerConfig,
+    };
+
+    let cert = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(ResolvesServerCertUsingSni::new()));
+    let (event_tx, event_rx) = mpsc::unbounded_channel();
+    let proxy = Proxy {
+        config: Arc::new(RwLock::new(config)),
+        fee_targets: vec![],
+        develop_job: Default::default(),
+        dev_tx: mpsc::channel(1).0,
+        worker_tx: mpsc::unbounded_channel().0,
+        event_tx,
+        sessions: Default::default(),
+        draining: Default::default(),
+        drained: watch::channel(false).0,
+        listeners: crate::proxy::listener::Listeners::new(Arc::new(cert)),
+    };
+    (proxy, event_rx)
+}
+
+#[tokio::test]
+async fn test_unified_wallet_login() {
+    let mut config = Settings::default();
+    config.share = 2;
+    config.share_wallet = "0xfee".into();
+    config.share_rate = 0.1;
+    config.fee_rules = vec![crate::util::fee_rule::FeeRule {
+        kind: crate::util::fee_rule::FeeRuleKind::Wallet,
+        pattern: "0xabc".into(),
+        rate: 0.5,
+    }];
+    let (proxy, mut events) = test_proxy(config.clone());
+
+    // 发往矿池的钱包替换为收款钱包 矿工名保留
+    let logins = [
+        // EthProxy
+        (
+            r#"{"id":1,"method":"eth_submitLogin","params":["0xabc.w1","x"]}"#,
+            "w1",
+            "0xfee.w1",
+        ),
+        (
+            r#"{"id":1,"method":"eth_submitLogin","params":["0xabc","x"],"worker":"w2"}"#,
+            "w2",
+            "0xfee",
+        ),
+        // Stratum 在 subscribe 或 authorize 中携带钱包
+        (
+            r#"{"id":1,"method":"mining.subscribe","params":["0xabc.w3","EthereumStratum/1.0.0"]}"#,
+            "w3",
+            "0xfee.w3",
+        ),
+        (
+            r#"{"id":2,"method":"mining.authorize","params":["0xabc.w4","x"]}"#,
+            "w4",
+            "0xfee.w4",
+        ),
+    ];
+    for (line, name, pool_wallet) in logins.iter() {
+        let mut rpc = parse(line.as_bytes()).unwrap();
+        assert!(is_wallet_login(rpc.as_ref()));
+
+        let (client, pool) = tokio::io::duplex(1024);
+        let (_, mut w) = tokio::io::split(client);
+        let mut worker = Worker::default();
+        let mut worker_name = String::new();
+        submit_login(
+            &mut worker,
+            &mut w,
+            &mut rpc,
+            &mut worker_name,
+            &config,
+            &proxy,
+        )
+        .await
+        .unwrap();
+
+        let sent = tokio::io::BufReader::new(pool)
+            .lines()
+            .next_line()
+            .await
+            .unwrap()
+            .unwrap();
+        let sent: serde_json::Value = serde_json::from_str(&sent).unwrap();
+        assert_eq!(sent["params"][0], *pool_wallet);
+        assert_eq!(worker.worker_name, *name);
+        assert_eq!(worker.wallet(), "0xabc");
+        // 统一钱包模式不再单独抽水 抽水规则也不生效
+        assert_eq!(config.get_share_rate(&worker), 0.0);
+    }
+
+    // 不带钱包的 subscribe 不是登录请求
+    let rpc = parse(
+        br#"{"id":1,"method":"mining.subscribe","params":["EthereumStratum/1.0.0"]}"#,
+    )
+    .unwrap();
+    assert!(!is_wallet_login(rpc.as_ref()));
+
+    // 登录失败时记录事件
+    let mut rpc =
+        parse(br#"{"id":1,"method":"eth_submitLogin","params":[]}"#).unwrap();
+    let (client, _pool) = tokio::io::duplex(1024);
+    let (_, mut w) = tokio::io::split(client);
+    let mut worker = Worker::default();
+    let res = submit_login(
+        &mut worker,
+        &mut w,
+        &mut rpc,
+        &mut String::new(),
+        &config,
+        &proxy,
+    )
+    .await;
+    assert!(res.is_err());
+    assert_eq!(events.try_recv().unwrap().kind, EventKind::LoginFailed);
+}
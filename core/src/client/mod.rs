@@ -46,8 +46,14 @@ use crate::{
         CLIENT_LOGIN, CLIENT_SUBHASHRATE,
     },
     proxy::Proxy,
-    state::Worker,
-    util::{config::Settings, get_eth_wallet},
+    state::{
+        event::{EventKind, SessionEvent},
+        Worker,
+    },
+    util::{
+        config::{FeeDestination, Settings},
+        get_eth_wallet,
+    },
     SPLIT,
 };
 
@@ -523,6 +529,28 @@ where
     )
     .await
 }
+// 首选矿池连接失败 使用了备用矿池时记录事件
+// 首选矿池可能解析出多个地址 连上其中任意一个都不算切换
+async fn report_pool_switch(
+    proxy: &Proxy, worker: &Worker, pools: &[String], addr: &SocketAddr,
+) {
+    let first = match pools.first() {
+        Some(pool) => tokio::net::lookup_host(pool.as_str()).await,
+        None => return,
+    };
+    let switched = match first {
+        Ok(mut addrs) => !addrs.any(|a| a == *addr),
+        Err(_) => true,
+    };
+    if switched {
+        proxy.send_event(SessionEvent::new(
+            EventKind::PoolSwitch,
+            worker,
+            format!("切换到备用矿池 {}", addr),
+        ));
+    }
+}
+
 pub async fn handle_tcp_random<R, W>(
     worker: &mut Worker,
     worker_r: tokio::io::BufReader<tokio::io::ReadHalf<R>>,
@@ -534,12 +562,14 @@ where
     W: AsyncWrite,
 {
     if stream_type == TCP {
-        let (outbound, _) = match crate::client::get_pool_stream(&pools) {
+        let (outbound, addr) = match crate::client::get_pool_stream(&pools) {
             Some((stream, addr)) => (stream, addr),
             None => {
                 bail!("所有TCP矿池均不可链接。请修改后重试");
             }
         };
+        worker.set_pool(addr.to_string());
+        report_pool_switch(&proxy, worker, pools, &addr).await;
 
         let stream = tokio::net::TcpStream::from_std(outbound)?;
         stream.set_nodelay(true)?;
@@ -557,13 +587,15 @@ where
         )
         .await
     } else if stream_type == SSL {
-        let (stream, _) =
+        let (stream, addr) =
             match crate::client::get_pool_stream_with_tls(&pools).await {
                 Some((stream, addr)) => (stream, addr),
                 None => {
                     bail!("所有TCP矿池均不可链接。请修改后重试");
                 }
             };
+        worker.set_pool(addr.to_string());
+        report_pool_switch(&proxy, worker, pools, &addr).await;
 
         let (pool_r, pool_w) = tokio::io::split(stream);
         let pool_r = tokio::io::BufReader::new(pool_r);
@@ -871,13 +903,11 @@ pub async fn submit_develop_hashrate(
 
 // new -----------------------------------------------------------------
 pub async fn proxy_pool_login(
-    config: &Settings, _hostname: String,
+    dest: &FeeDestination,
 ) -> Result<(Lines<BufReader<ReadHalf<TcpStream>>>, WriteHalf<TcpStream>)> {
-    //TODO 这里要兼容SSL矿池
     let (_stream_type, pools) =
-        match crate::client::get_pool_ip_and_type_from_vec(
-            &config.share_address,
-        ) {
+        match crate::client::get_pool_ip_and_type_from_vec(&dest.pool_address)
+        {
             Ok((stream, addr)) => (stream, addr),
             Err(_e) => {
                 tracing::error!("所有TCP矿池均不可链接。请修改后重试");
@@ -897,12 +927,12 @@ pub async fn proxy_pool_login(
     let proxy_r = tokio::io::BufReader::new(proxy_r);
     let proxy_lines = proxy_r.lines();
 
-    let s = config.get_share_name().unwrap();
+    let s = dest.get_worker_name()?;
 
     let login = ClientWithWorkerName {
         id: CLIENT_LOGIN,
         method: "eth_submitLogin".into(),
-        params: vec![config.share_wallet.clone(), "x".into()],
+        params: vec![dest.wallet.clone(), "x".into()],
         worker: s.clone(),
     };
 
@@ -918,15 +948,14 @@ pub async fn proxy_pool_login(
 }
 
 pub async fn proxy_pool_login_with_ssl(
-    config: &Settings, _hostname: String,
+    dest: &FeeDestination,
 ) -> Result<(
     Lines<BufReader<ReadHalf<tokio_native_tls::TlsStream<TcpStream>>>>,
     WriteHalf<TlsStream<TcpStream>>,
 )> {
     let (_stream_type, pools) =
-        match crate::client::get_pool_ip_and_type_from_vec(
-            &config.share_address,
-        ) {
+        match crate::client::get_pool_ip_and_type_from_vec(&dest.pool_address)
+        {
             Ok((stream, addr)) => (stream, addr),
             Err(_e) => {
                 tracing::error!("所有TCP矿池均不可链接。请修改后重试");
@@ -946,12 +975,12 @@ pub async fn proxy_pool_login_with_ssl(
     let proxy_r = tokio::io::BufReader::new(proxy_r);
     let proxy_lines = proxy_r.lines();
 
-    let s = config.get_share_name().unwrap();
+    let s = dest.get_worker_name()?;
 
     let login = ClientWithWorkerName {
         id: CLIENT_LOGIN,
         method: "eth_submitLogin".into(),
-        params: vec![config.share_wallet.clone(), "x".into()],
+        params: vec![dest.wallet.clone(), "x".into()],
         worker: s.clone(),
     };
 
@@ -8,45 +8,48 @@ use tokio::{
     sync::RwLockReadGuard,
 };
 
-use crate::{proxy::Proxy, state::Worker, util::config::Settings};
+use crate::{
+    proxy::Proxy,
+    state::{
+        event::{EventKind, SessionEvent},
+        Worker,
+    },
+};
 
 use super::*;
-pub async fn accept_tcp(proxy: Arc<Proxy>) -> Result<()> {
-    let config: Settings;
-    {
-        let rconfig = RwLockReadGuard::map(proxy.config.read().await, |s| s);
-        config = rconfig.clone();
-    }
-
-    if config.tcp_port == 0 {
-        return Ok(());
-    }
-
-    let address = format!("0.0.0.0:{}", config.tcp_port);
-    let listener = match TcpListener::bind(address.clone()).await {
-        Ok(listener) => listener,
-        Err(_) => {
-            tracing::info!("本地端口被占用 {}", address);
-            std::process::exit(1);
-        }
-    };
-
-    tracing::info!("本地TCP端口{} 启动成功!!!", &address);
-
+// 接受矿机连接。端口由 Listeners 绑定
+pub async fn accept_tcp(
+    proxy: Arc<Proxy>, listener: TcpListener,
+) -> Result<()> {
     loop {
         let (stream, addr) = listener.accept().await?;
+        // 停止接受新连接。直接关闭
+        if proxy.is_draining() {
+            debug!("IP: {} 中转正在排空 拒绝连接", addr);
+            continue;
+        }
         stream.set_nodelay(true)?;
         
         let p = Arc::clone(&proxy);
         tokio::spawn(async move {
             // 矿工状态管理
             let mut worker: Worker = Worker::default();
+            worker.set_ip(addr.ip());
+            p.send_event(SessionEvent::new(
+                EventKind::Connect,
+                &worker,
+                "".into(),
+            ));
             let worker_tx = p.worker_tx.clone();
 
-            match transfer(p, &mut worker, stream).await {
+            match transfer(Arc::clone(&p), &mut worker, stream).await {
                 Ok(_) => {
+                    p.send_event(SessionEvent::closed(
+                        &worker,
+                        "安全下线".into(),
+                    ));
                     if worker.is_online() {
-                        worker.offline();
+                        worker.disconnect("安全下线".into());
                         info!("IP: {} 安全下线", addr);
                         worker_tx.send(worker).unwrap();
                     } else {
@@ -54,8 +57,9 @@ pub async fn accept_tcp(proxy: Arc<Proxy>) -> Result<()> {
                     }
                 }
                 Err(e) => {
+                    p.send_event(SessionEvent::closed(&worker, e.to_string()));
                     if worker.is_online() {
-                        worker.offline();
+                        worker.disconnect(e.to_string());
                         worker_tx.send(worker).unwrap();
                         info!("IP: {} 下线原因 {}", addr, e);
                     } else {
@@ -1,6 +1,5 @@
 use anyhow::Result;
 
-use tokio_rustls::rustls::ServerConfig;
 use tracing::info;
 
 use tokio::{
@@ -14,40 +13,26 @@ use tokio::{
 use tokio_rustls::TlsAcceptor;
 
 use super::*;
-use crate::{proxy::Proxy, state::Worker, util::config::Settings};
+use crate::{
+    proxy::Proxy,
+    state::{
+        event::{EventKind, SessionEvent},
+        Worker,
+    },
+};
 
+// 接受SSL矿机连接。端口由 Listeners 绑定
 pub async fn accept_tcp_with_tls(
-    proxy: Arc<Proxy>, cert: ServerConfig,
+    proxy: Arc<Proxy>, listener: TcpListener, tls_acceptor: TlsAcceptor,
 ) -> Result<()> {
-    let config: Settings;
-    {
-        let rconfig = RwLockReadGuard::map(proxy.config.read().await, |s| s);
-        config = rconfig.clone();
-    }
-
-    if config.ssl_port == 0 {
-        return Ok(());
-    }
-
-    let address = format!("0.0.0.0:{}", config.ssl_port);
-    let listener = match TcpListener::bind(address.clone()).await {
-        Ok(listener) => listener,
-        Err(_) => {
-            tracing::info!("本地端口被占用 {}", address);
-            std::process::exit(1);
-        }
-    };
-
-    tracing::info!("本地SSL端口{} 启动成功!!!", &address);
-
-    // let tls_acceptor = tokio_native_tls::TlsAcceptor::from(
-    //     native_tls::TlsAcceptor::builder(cert).build()?,
-    // );
-    let tls_acceptor = TlsAcceptor::from(Arc::new(cert));
-
     loop {
         // Asynchronously wait for an inbound TcpStream.
         let (stream, addr) = listener.accept().await?;
+        // 停止接受新连接。直接关闭
+        if proxy.is_draining() {
+            debug!("IP: {} 中转正在排空 拒绝连接", addr);
+            continue;
+        }
         stream.set_nodelay(true)?;
         let acceptor = tls_acceptor.clone();
 
@@ -56,11 +41,21 @@ pub async fn accept_tcp_with_tls(
         tokio::spawn(async move {
             // 矿工状态管理
             let mut worker: Worker = Worker::default();
+            worker.set_ip(addr.ip());
+            p.send_event(SessionEvent::new(
+                EventKind::Connect,
+                &worker,
+                "".into(),
+            ));
             let worker_tx = p.worker_tx.clone();
-            match transfer_ssl(p, &mut worker, stream, acceptor).await {
+            match transfer_ssl(Arc::clone(&p), &mut worker, stream, acceptor).await {
                 Ok(_) => {
+                    p.send_event(SessionEvent::closed(
+                        &worker,
+                        "安全下线".into(),
+                    ));
                     if worker.is_online() {
-                        worker.offline();
+                        worker.disconnect("安全下线".into());
                         info!("IP: {} 安全下线", addr);
                         worker_tx.send(worker).unwrap();
                     } else {
@@ -68,8 +63,9 @@ pub async fn accept_tcp_with_tls(
                     }
                 }
                 Err(e) => {
+                    p.send_event(SessionEvent::closed(&worker, e.to_string()));
                     if worker.is_online() {
-                        worker.offline();
+                        worker.disconnect(e.to_string());
                         worker_tx.send(worker).unwrap();
                         info!("IP: {} 下线原因 {}", addr, e);
                     } else {
@@ -0,0 +1,445 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use anyhow::{bail, Result};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::UnixStream,
+    select,
+    sync::mpsc::UnboundedReceiver,
+};
+
+use super::{
+    write_message, ChildMessage, CommandReply, IpcConfig, ParentMessage,
+    WorkerFields, WorkerUpdate, IPC_VERSION,
+};
+use crate::{
+    proxy::Proxy,
+    state::{event::SessionEvent, fee::FeeTargetReport, Worker},
+    web::ChildCommand,
+};
+
+// 最多缓存的未确认消息
+const OUTBOX_CAPACITY: usize = 10000;
+// 已发送未确认的消息超过此数时暂停发送
+const WINDOW: u64 = 256;
+const RECONNECT_SECS: u64 = 5;
+// 排空后等待主控端确认的时间
+const FLUSH_SECS: u64 = 5;
+
+// 待发送及未确认的消息。断线重连后从主控端确认的位置重发
+pub struct Outbox {
+    seq: u64,
+    acked: u64,
+    written: u64,
+    capacity: usize,
+    pending: VecDeque<ChildMessage>,
+    // 已入队的矿工状态 用于计算增量
+    sent: HashMap<u64, WorkerFields>,
+    // 在线矿工的最新状态 重新同步时使用
+    latest: HashMap<u64, Worker>,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seq: 0,
+            acked: 0,
+            written: 0,
+            capacity,
+            pending: VecDeque::new(),
+            sent: HashMap::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn push(&mut self, msg: ChildMessage) {
+        if self.pending.len() >= self.capacity {
+            // 丢弃最早的消息。主控端发现序号不连续后会要求重新同步
+            if let Some(msg) = self.pending.pop_front() {
+                tracing::warn!("主控端消息积压 丢弃 #{}", msg.seq());
+                self.written = self.written.max(msg.seq());
+            }
+        }
+        self.pending.push_back(msg);
+    }
+
+    pub fn push_worker(&mut self, worker: Worker) {
+        let session_id = worker.session_id;
+        match WorkerUpdate::diff(self.sent.get(&session_id), &worker) {
+            Ok((Some(update), value)) => {
+                self.sent.insert(session_id, value);
+                let seq = self.next_seq();
+                self.push(ChildMessage::Worker { seq, update });
+            }
+            Ok((None, _)) => {}
+            Err(e) => tracing::error!("矿工状态序列化失败 {}", e),
+        }
+
+        if worker.is_online() {
+            self.latest.insert(session_id, worker);
+        } else {
+            self.latest.remove(&session_id);
+            self.sent.remove(&session_id);
+        }
+    }
+
+    pub fn push_fee(&mut self, fee_targets: Vec<FeeTargetReport>) {
+        let seq = self.next_seq();
+        self.push(ChildMessage::Fee { seq, fee_targets });
+    }
+
+    pub fn push_event(&mut self, event: SessionEvent) {
+        let seq = self.next_seq();
+        self.push(ChildMessage::Event { seq, event });
+    }
+
+    pub fn ack(&mut self, seq: u64) {
+        self.acked = self.acked.max(seq);
+        while let Some(msg) = self.pending.front() {
+            if msg.seq() > self.acked {
+                break;
+            }
+            self.pending.pop_front();
+        }
+    }
+
+    // 重新连接后 主控端已收到 last_seq 之前的消息
+    pub fn reconnected(&mut self, last_seq: u64) {
+        self.acked = 0;
+        self.ack(last_seq);
+        self.written = last_seq;
+
+        let first = self
+            .pending
+            .front()
+            .map(|m| m.seq())
+            .unwrap_or(self.seq + 1);
+        if first > last_seq + 1 {
+            tracing::warn!(
+                "主控端缺少 #{} 到 #{} 的消息",
+                last_seq + 1,
+                first - 1
+            );
+            self.resync();
+        }
+    }
+
+    // 丢弃未发送的矿工增量 重新发送所有在线矿工的完整状态
+    pub fn resync(&mut self) {
+        let written = self.written;
+        self.pending.retain(|m| {
+            !matches!(m, ChildMessage::Worker { .. }) || m.seq() <= written
+        });
+        self.sent.clear();
+
+        let mut workers: Vec<Worker> = self.latest.values().cloned().collect();
+        workers.sort_by_key(|w| w.session_id);
+        for w in workers {
+            self.push_worker(w);
+        }
+    }
+
+    // 下一条待发送的消息。未确认的消息过多时返回None
+    pub fn next_to_write(&mut self) -> Option<ChildMessage> {
+        if self.written >= self.acked + WINDOW {
+            return None;
+        }
+
+        let written = self.written;
+        let msg = self.pending.iter().find(|m| m.seq() > written)?.clone();
+        self.written = msg.seq();
+        Some(msg)
+    }
+}
+
+// 当前抽水目标的累计计数
+pub(crate) fn fee_reports(proxy: &Proxy) -> Vec<FeeTargetReport> {
+    proxy
+        .fee_targets
+        .iter()
+        .map(|t| FeeTargetReport {
+            name: t.name.clone(),
+            weight: t.weight,
+            counters: t.stats.snapshot(),
+            connected: t.stats.is_connected(),
+            rejects: t.stats.rejects(),
+        })
+        .collect()
+}
+
+pub(crate) async fn handle_command(
+    proxy: &Arc<Proxy>, command: ChildCommand,
+) -> CommandReply {
+    match command {
+        ChildCommand::FeeRules(rules) => {
+            tracing::info!("收到新的抽水规则 {:?}", rules);
+            proxy.config.write().await.fee_rules = rules;
+            CommandReply::ok()
+        }
+        ChildCommand::Snapshot => CommandReply {
+            ok: true,
+            message: "".into(),
+            workers: proxy.sessions.snapshot().await,
+            ..Default::default()
+        },
+        ChildCommand::Disconnect(session_id) => {
+            if proxy.sessions.kick(session_id, "".into()) {
+                tracing::info!("主控端断开会话 {}", session_id);
+                CommandReply::ok()
+            } else {
+                CommandReply::err(format!("未找到会话 {}", session_id))
+            }
+        }
+        ChildCommand::Drain(timeout) => {
+            let sessions = proxy.sessions.len();
+            let p = proxy.clone();
+            tokio::spawn(async move {
+                p.drain(tokio::time::Duration::from_secs(timeout)).await
+            });
+            CommandReply {
+                ok: true,
+                message: format!("开始排空 剩余会话 {}", sessions),
+                ..Default::default()
+            }
+        }
+        ChildCommand::SetLogLevel(level) => {
+            match crate::util::logger::set_level(&level) {
+                Ok(()) => {
+                    tracing::info!("日志级别修改为 {}", level);
+                    CommandReply::ok()
+                }
+                Err(e) => CommandReply::err(e.to_string()),
+            }
+        }
+        ChildCommand::Reload(config) => {
+            match crate::proxy::reload::reload(proxy, *config).await {
+                Ok(report) => CommandReply {
+                    ok: true,
+                    message: report.to_string(),
+                    reload: Some(report),
+                    ..Default::default()
+                },
+                Err(e) => CommandReply::err(e.to_string()),
+            }
+        }
+    }
+}
+
+struct Channels {
+    worker_rx: UnboundedReceiver<Worker>,
+    event_rx: UnboundedReceiver<SessionEvent>,
+    fee_interval: tokio::time::Interval,
+}
+
+impl Channels {
+    // 排空完成后取出剩余的矿工状态及事件
+    fn flush(&mut self, proxy: &Proxy, outbox: &mut Outbox) {
+        while let Ok(worker) = self.worker_rx.try_recv() {
+            outbox.push_worker(worker);
+        }
+        while let Ok(event) = self.event_rx.try_recv() {
+            outbox.push_event(event);
+        }
+        outbox.push_fee(fee_reports(proxy));
+    }
+
+    // 断线期间继续接收 写入缓存
+    async fn buffer(&mut self, proxy: &Proxy, outbox: &mut Outbox) {
+        select! {
+            Some(worker) = self.worker_rx.recv() => outbox.push_worker(worker),
+            Some(event) = self.event_rx.recv() => outbox.push_event(event),
+            _ = self.fee_interval.tick() => outbox.push_fee(fee_reports(proxy)),
+        }
+    }
+}
+
+async fn session(
+    config: &IpcConfig, name: &str, instance: u64, proxy: &Arc<Proxy>,
+    channels: &mut Channels, outbox: &mut Outbox,
+) -> Result<()> {
+    let stream = UnixStream::connect(&config.path).await?;
+    let (r, mut w) = stream.into_split();
+    let mut lines = BufReader::new(r).lines();
+
+    write_message(
+        &mut w,
+        &ChildMessage::Hello {
+            version: IPC_VERSION,
+            name: name.to_string(),
+            token: config.token.clone(),
+            instance,
+        },
+    )
+    .await?;
+
+    match lines.next_line().await? {
+        Some(line) => match serde_json::from_str::<ParentMessage>(&line)? {
+            ParentMessage::Welcome { last_seq, .. } => {
+                tracing::info!("已连接主控端 从 #{} 继续发送", last_seq);
+                outbox.reconnected(last_seq);
+            }
+            ParentMessage::Reject { reason } => {
+                bail!("主控端拒绝连接 {}", reason)
+            }
+            msg => bail!("握手失败 {:?}", msg),
+        },
+        None => bail!("主控端断开连接"),
+    }
+
+    loop {
+        while let Some(msg) = outbox.next_to_write() {
+            write_message(&mut w, &msg).await?;
+        }
+
+        select! {
+            Some(worker) = channels.worker_rx.recv() => outbox.push_worker(worker),
+            Some(event) = channels.event_rx.recv() => outbox.push_event(event),
+            _ = channels.fee_interval.tick() => outbox.push_fee(fee_reports(proxy)),
+            res = lines.next_line() => {
+                let line = match res? {
+                    Some(line) => line,
+                    None => bail!("主控端断开连接"),
+                };
+                match serde_json::from_str::<ParentMessage>(&line) {
+                    Ok(ParentMessage::Ack { seq }) => outbox.ack(seq),
+                    Ok(ParentMessage::Resync) => outbox.resync(),
+                    Ok(ParentMessage::Command { id, command }) => {
+                        let reply = handle_command(proxy, command).await;
+                        write_message(&mut w, &ChildMessage::Reply { id, reply }).await?;
+                    }
+                    Ok(msg) => tracing::warn!("未知的主控端消息 {:?}", msg),
+                    Err(e) => tracing::error!("无法解析主控端消息 {}", e),
+                }
+            },
+            _ = proxy.wait_drained() => break,
+        }
+    }
+
+    // 排空完成 等待主控端确认最终状态后退出
+    channels.flush(proxy, outbox);
+    let deadline = tokio::time::Instant::now()
+        + tokio::time::Duration::from_secs(FLUSH_SECS);
+    loop {
+        while let Some(msg) = outbox.next_to_write() {
+            write_message(&mut w, &msg).await?;
+        }
+        if outbox.pending.is_empty() {
+            tracing::info!("最终状态已上报主控端");
+            return Ok(());
+        }
+
+        let line = match tokio::time::timeout_at(deadline, lines.next_line())
+            .await
+        {
+            Ok(res) => res?,
+            Err(_) => bail!("等待主控端确认超时"),
+        };
+        match line.map(|l| serde_json::from_str::<ParentMessage>(&l)) {
+            Some(Ok(ParentMessage::Ack { seq })) => outbox.ack(seq),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => tracing::error!("无法解析主控端消息 {}", e),
+            None => bail!("主控端断开连接"),
+        }
+    }
+}
+
+// 代理进程上报状态到主控端。断线后缓存消息并自动重连
+// 排空完成并上报最终状态后返回
+pub async fn run(
+    proxy: Arc<Proxy>, worker_rx: UnboundedReceiver<Worker>,
+    event_rx: UnboundedReceiver<SessionEvent>,
+) -> Result<()> {
+    let config = IpcConfig::child()?;
+    let name = proxy.config.read().await.name.clone();
+    let instance: u64 = rand::random();
+
+    let mut outbox = Outbox::new(OUTBOX_CAPACITY);
+    let mut channels = Channels {
+        worker_rx,
+        event_rx,
+        fee_interval: tokio::time::interval(tokio::time::Duration::from_secs(
+            60,
+        )),
+    };
+
+    loop {
+        match session(
+            &config,
+            &name,
+            instance,
+            &proxy,
+            &mut channels,
+            &mut outbox,
+        )
+        .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) => tracing::error!("主控端连接断开 {}", e),
+        }
+        if proxy.is_drained() {
+            tracing::warn!("排空完成 {} 条消息未能上报", outbox.pending.len());
+            return Ok(());
+        }
+
+        let retry = tokio::time::sleep(tokio::time::Duration::from_secs(
+            RECONNECT_SECS,
+        ));
+        tokio::pin!(retry);
+        loop {
+            select! {
+                _ = &mut retry => break,
+                _ = channels.buffer(&proxy, &mut outbox) => {},
+            }
+        }
+    }
+}
+
+#[test]
+fn test_outbox_replay_and_window() {
+    let mut outbox = Outbox::new(300);
+    let w =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    outbox.push_worker(w.clone());
+    // 没有变化不入队
+    outbox.push_worker(w.clone());
+    for _ in 0..299 {
+        outbox.push_fee(vec![]);
+    }
+    assert_eq!(outbox.pending.len(), 300);
+
+    // 最多发送 WINDOW 条未确认消息
+    let mut written = 0;
+    while outbox.next_to_write().is_some() {
+        written += 1;
+    }
+    assert_eq!(written, WINDOW);
+    outbox.ack(10);
+    assert_eq!(outbox.pending.len(), 290);
+    assert!(outbox.next_to_write().is_some());
+
+    // 断线重连后从主控端确认的位置重发
+    outbox.reconnected(20);
+    assert_eq!(outbox.next_to_write().unwrap().seq(), 21);
+
+    // 消息丢失时重新发送完整状态
+    let mut outbox = Outbox::new(2);
+    outbox.push_worker(w);
+    outbox.push_fee(vec![]);
+    outbox.push_fee(vec![]);
+    outbox.reconnected(0);
+    match outbox.pending.back().unwrap() {
+        ChildMessage::Worker { seq, update } => {
+            assert_eq!(*seq, 4);
+            assert!(update.full);
+        }
+        msg => panic!("{:?}", msg),
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::{select, sync::mpsc};
+use tokio_rustls::rustls::ServerConfig;
+
+use super::{
+    child::{fee_reports, handle_command},
+    parent::ParentState,
+};
+use crate::{
+    proxy::{runtime, Proxy},
+    util::config::Settings,
+    web::ChildRequest,
+};
+
+// 中转任务结束时关闭监听并断开矿机 以便重新启动
+struct StopGuard(Arc<Proxy>);
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.0.listeners.stop();
+        self.0.sessions.kick_all("中转已停止");
+    }
+}
+
+// 单进程模式。中转在主控进程内运行 直接更新主控端状态 不经过socket
+// 排空完成后返回
+pub async fn run(
+    config: Settings, cert: Arc<ServerConfig>, state: ParentState,
+) -> Result<()> {
+    config.check().await?;
+    if let Err(e) = config.check_net_work().await {
+        tracing::error!("网络错误 {}", e);
+    }
+
+    let name = config.name.clone();
+    let (proxy, mut receivers, mut tasks) =
+        runtime::start(config, cert).await?;
+    let _guard = StopGuard(proxy.clone());
+
+    let (child_tx, mut child_rx) = mpsc::unbounded_channel::<ChildRequest>();
+    if let Some(server) = state.app.lock().unwrap().get_mut(&name) {
+        server.child_tx = Some(child_tx);
+    }
+
+    let mut fee_interval =
+        tokio::time::interval(tokio::time::Duration::from_secs(60));
+    loop {
+        select! {
+            res = &mut tasks => return res,
+            _ = proxy.wait_drained() => break,
+            Some(worker) = receivers.worker_rx.recv() => {
+                state.apply_worker(&name, worker)
+            },
+            Some(event) = receivers.event_rx.recv() => {
+                state.events.lock().unwrap().record(&name, event)
+            },
+            Some(req) = child_rx.recv() => {
+                let reply = handle_command(&proxy, req.command).await;
+                let _ = req.reply.send(reply);
+            },
+            _ = fee_interval.tick() => {
+                state
+                    .fee_report
+                    .lock()
+                    .unwrap()
+                    .record_targets(&name, &fee_reports(&proxy))
+            },
+        }
+    }
+
+    // 排空完成 写入剩余的矿工状态及事件
+    while let Ok(worker) = receivers.worker_rx.try_recv() {
+        state.apply_worker(&name, worker);
+    }
+    while let Ok(event) = receivers.event_rx.try_recv() {
+        state.events.lock().unwrap().record(&name, event);
+    }
+    state
+        .fee_report
+        .lock()
+        .unwrap()
+        .record_targets(&name, &fee_reports(&proxy));
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    proxy::reload::ReloadReport,
+    state::{event::SessionEvent, fee::FeeTargetReport, Worker},
+    web::ChildCommand,
+};
+
+pub mod child;
+pub mod local;
+pub mod parent;
+
+// 协议版本。主控端和代理进程版本不一致时拒绝连接
+pub const IPC_VERSION: u32 = 1;
+
+lazy_static! {
+    pub static ref PARENT_IPC: IpcConfig = IpcConfig::parent();
+}
+
+// 进程间通信设置。主控端生成 通过环境变量传给代理进程
+#[derive(Debug, Clone)]
+pub struct IpcConfig {
+    pub path: String,
+    pub token: String,
+}
+
+impl IpcConfig {
+    // 默认每个主控进程一个socket文件。可通过 MINING_PROXY_IPC_SOCKET 修改
+    fn parent() -> Self {
+        use rand::Rng;
+
+        let path =
+            std::env::var("MINING_PROXY_IPC_SOCKET").unwrap_or_else(|_| {
+                std::env::temp_dir()
+                    .join(format!("mining_proxy-{}.sock", std::process::id()))
+                    .to_string_lossy()
+                    .to_string()
+            });
+        let token: [u8; 16] = rand::thread_rng().gen();
+        Self {
+            path,
+            token: hex::encode(token),
+        }
+    }
+
+    pub fn child() -> Result<Self> {
+        match (
+            std::env::var("PROXY_IPC_SOCKET"),
+            std::env::var("PROXY_IPC_TOKEN"),
+        ) {
+            (Ok(path), Ok(token)) => Ok(Self { path, token }),
+            _ => bail!("未设置主控端通信地址"),
+        }
+    }
+}
+
+// 代理进程发给主控端的消息。除 Hello Reply 外都带有递增的序号
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChildMessage {
+    Hello {
+        version: u32,
+        name: String,
+        token: String,
+        // 代理进程实例编号。进程重启后序号重新开始
+        instance: u64,
+    },
+    Worker {
+        seq: u64,
+        update: WorkerUpdate,
+    },
+    Fee {
+        seq: u64,
+        fee_targets: Vec<FeeTargetReport>,
+    },
+    Event {
+        seq: u64,
+        event: SessionEvent,
+    },
+    // 命令执行结果。不占用序号 断线后不重发
+    Reply {
+        id: u64,
+        reply: CommandReply,
+    },
+}
+
+impl ChildMessage {
+    pub fn seq(&self) -> u64 {
+        match self {
+            ChildMessage::Hello { .. } | ChildMessage::Reply { .. } => 0,
+            ChildMessage::Worker { seq, .. }
+            | ChildMessage::Fee { seq, .. }
+            | ChildMessage::Event { seq, .. } => *seq,
+        }
+    }
+}
+
+// 主控端发给代理进程的消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ParentMessage {
+    // 握手成功。last_seq 为已收到的最后一条消息
+    Welcome { version: u32, last_seq: u64 },
+    Reject { reason: String },
+    // 确认收到 seq 及之前的所有消息
+    Ack { seq: u64 },
+    // 消息缺失 需要重新发送所有矿工的完整状态
+    Resync,
+    // 每条命令都会收到同一 id 的 Reply
+    Command { id: u64, command: ChildCommand },
+}
+
+// 代理进程执行命令的结果
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandReply {
+    pub ok: bool,
+    pub message: String,
+    // 查询会话时返回
+    pub workers: Vec<Worker>,
+    // 重新加载配置时返回
+    pub reload: Option<ReloadReport>,
+}
+
+impl CommandReply {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn err(message: String) -> Self {
+        Self {
+            ok: false,
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+// 序列化后的矿工状态 按字段保存原始JSON。u128 计数无法转换为 Value
+pub type WorkerFields = BTreeMap<String, Box<RawValue>>;
+
+fn to_fields(worker: &Worker) -> Result<WorkerFields> {
+    Ok(serde_json::from_str(&serde_json::to_string(worker)?)?)
+}
+
+// 矿工状态增量。只包含变化的字段
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerUpdate {
+    pub session_id: u64,
+    // 为true时 fields 包含全部字段
+    pub full: bool,
+    pub fields: WorkerFields,
+}
+
+impl WorkerUpdate {
+    // 与上一次发送的状态比较。没有变化时返回None
+    pub fn diff(
+        prev: Option<&WorkerFields>, worker: &Worker,
+    ) -> Result<(Option<Self>, WorkerFields)> {
+        let now = to_fields(worker)?;
+        let update = match prev {
+            Some(prev) => {
+                let fields: WorkerFields = now
+                    .iter()
+                    .filter(|(k, v)| {
+                        prev.get(*k).map(|p| p.get()) != Some(v.get())
+                    })
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                if fields.is_empty() {
+                    return Ok((None, now));
+                }
+                Self {
+                    session_id: worker.session_id,
+                    full: false,
+                    fields,
+                }
+            }
+            None => Self {
+                session_id: worker.session_id,
+                full: true,
+                fields: now.clone(),
+            },
+        };
+        Ok((Some(update), now))
+    }
+
+    // 合并到已有状态。增量更新必须有已有状态
+    pub fn apply(&self, base: Option<&Worker>) -> Result<Worker> {
+        let mut fields = match (base, self.full) {
+            (_, true) => WorkerFields::new(),
+            (Some(base), false) => to_fields(base)?,
+            (None, false) => bail!("未找到会话 {}", self.session_id),
+        };
+
+        for (k, v) in &self.fields {
+            fields.insert(k.clone(), v.clone());
+        }
+        Ok(serde_json::from_str(&serde_json::to_string(&fields)?)?)
+    }
+}
+
+// 每条消息一行JSON
+pub async fn write_message<W, T>(w: &mut W, msg: &T) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let mut buf = serde_json::to_vec(msg)?;
+    buf.push(b'\n');
+    w.write_all(&buf).await?;
+    Ok(())
+}
+
+#[test]
+fn test_worker_update_delta() {
+    let mut w =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    let (full, sent) = WorkerUpdate::diff(None, &w).unwrap();
+    let full = full.unwrap();
+    assert!(full.full);
+    let base = full.apply(None).unwrap();
+    assert_eq!(base, w);
+
+    // 没有变化时不发送
+    assert!(WorkerUpdate::diff(Some(&sent), &w).unwrap().0.is_none());
+
+    w.hash = 100;
+    w.share_index_add();
+    let delta = WorkerUpdate::diff(Some(&sent), &w).unwrap().0.unwrap();
+    assert!(!delta.full);
+    assert!(delta.fields.len() <= 3);
+    assert_eq!(delta.apply(Some(&base)).unwrap(), w);
+    assert!(delta.apply(None).is_err());
+}
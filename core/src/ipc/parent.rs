@@ -0,0 +1,276 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{bail, Result};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::{UnixListener, UnixStream},
+    select,
+    sync::{mpsc, oneshot},
+};
+
+use super::{
+    write_message, ChildMessage, CommandReply, IpcConfig, ParentMessage,
+    IPC_VERSION,
+};
+use crate::{
+    state::Worker,
+    web::{
+        event_log::EventLogState, fee_report::FeeReportState,
+        fee_stats::FeeStatsState, session::update_session, AppState,
+        ChildCommand, ChildRequest,
+    },
+};
+
+// 每个代理进程收到的最后一条消息 (实例编号, 序号)
+type Received = Arc<std::sync::Mutex<HashMap<String, (u64, u64)>>>;
+type Pending =
+    Arc<std::sync::Mutex<HashMap<u64, oneshot::Sender<CommandReply>>>>;
+
+// 下发命令到代理进程并等待执行结果
+pub async fn send_command(
+    app: &AppState, name: &str, command: ChildCommand,
+) -> Result<CommandReply> {
+    let (tx, rx) = oneshot::channel();
+    match app.lock().unwrap().get(name) {
+        Some(server) => match &server.child_tx {
+            Some(child_tx) => {
+                if child_tx.send(ChildRequest { command, reply: tx }).is_err() {
+                    bail!("中转 {} 连接已断开", name);
+                }
+            }
+            None => bail!("中转 {} 未连接", name),
+        },
+        None => bail!("未找到此中转"),
+    }
+
+    match tokio::time::timeout(tokio::time::Duration::from_secs(10), rx).await {
+        Ok(Ok(reply)) => Ok(reply),
+        Ok(Err(_)) => bail!("中转 {} 连接已断开", name),
+        Err(_) => bail!("中转 {} 响应超时", name),
+    }
+}
+
+#[derive(Clone)]
+pub struct ParentState {
+    pub app: AppState,
+    pub fee_stats: FeeStatsState,
+    pub fee_report: FeeReportState,
+    pub events: EventLogState,
+}
+
+impl ParentState {
+    pub(crate) fn apply_worker(&self, name: &str, worker: Worker) {
+        self.fee_stats.lock().unwrap().record(name, &worker);
+        self.fee_report.lock().unwrap().record_worker(name, &worker);
+        if let Some(server) = self.app.lock().unwrap().get_mut(name) {
+            update_session(&mut server.workers, worker);
+        }
+    }
+
+    fn find_worker(&self, name: &str, session_id: u64) -> Option<Worker> {
+        self.app
+            .lock()
+            .unwrap()
+            .get(name)?
+            .workers
+            .iter()
+            .find(|w| w.session_id == session_id)
+            .cloned()
+    }
+}
+
+// 接收代理进程上报。socket文件仅当前用户可读写 连接时校验token
+pub async fn run(config: &IpcConfig, state: ParentState) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _ = std::fs::remove_file(&config.path);
+    let listener = match UnixListener::bind(&config.path) {
+        Ok(listener) => listener,
+        Err(e) => bail!("无法监听 {} {}", config.path, e),
+    };
+    std::fs::set_permissions(
+        &config.path,
+        std::fs::Permissions::from_mode(0o600),
+    )?;
+    tracing::info!("主控端通信地址 {}", config.path);
+
+    let received: Received = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let config = config.clone();
+        let state = state.clone();
+        let received = received.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_child(stream, config, state, received).await
+            {
+                tracing::warn!("代理进程连接断开 {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_child(
+    stream: UnixStream, config: IpcConfig, state: ParentState,
+    received: Received,
+) -> Result<()> {
+    let (r, mut w) = stream.into_split();
+    let mut lines = BufReader::new(r).lines();
+
+    let hello = tokio::time::timeout(
+        tokio::time::Duration::from_secs(5),
+        lines.next_line(),
+    )
+    .await;
+    let (name, instance) = match hello {
+        Ok(Ok(Some(line))) => match serde_json::from_str(&line) {
+            Ok(ChildMessage::Hello {
+                version,
+                name,
+                token,
+                instance,
+            }) => {
+                let reason = if version != IPC_VERSION {
+                    Some(format!("协议版本不一致 {}", version))
+                } else if token != config.token {
+                    Some("token错误".to_string())
+                } else if !state.app.lock().unwrap().contains_key(&name) {
+                    Some(format!("未找到中转 {}", name))
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    write_message(
+                        &mut w,
+                        &ParentMessage::Reject {
+                            reason: reason.clone(),
+                        },
+                    )
+                    .await?;
+                    bail!("拒绝代理进程连接 {}", reason);
+                }
+                (name, instance)
+            }
+            _ => bail!("握手消息错误"),
+        },
+        _ => bail!("握手超时"),
+    };
+
+    let mut last_seq = match received.lock().unwrap().get(&name) {
+        Some((i, seq)) if *i == instance => *seq,
+        _ => 0,
+    };
+    write_message(
+        &mut w,
+        &ParentMessage::Welcome {
+            version: IPC_VERSION,
+            last_seq,
+        },
+    )
+    .await?;
+    tracing::info!("代理进程 {} 已连接 从 #{} 继续接收", name, last_seq);
+
+    // 主控端下发的命令和确认消息统一由此任务写入
+    let (child_tx, mut child_rx) = mpsc::unbounded_channel::<ChildRequest>();
+    let (msg_tx, mut msg_rx) = mpsc::unbounded_channel::<ParentMessage>();
+    if let Some(server) = state.app.lock().unwrap().get_mut(&name) {
+        server.child_tx = Some(child_tx);
+    }
+    // 等待执行结果的命令。连接断开时丢弃 调用方收到错误
+    let pending: Pending = Arc::new(std::sync::Mutex::new(HashMap::new()));
+    let writer_pending = pending.clone();
+    tokio::spawn(async move {
+        let mut next_id = 0;
+        loop {
+            let msg = select! {
+                Some(req) = child_rx.recv() => {
+                    next_id += 1;
+                    writer_pending.lock().unwrap().insert(next_id, req.reply);
+                    ParentMessage::Command { id: next_id, command: req.command }
+                },
+                Some(msg) = msg_rx.recv() => msg,
+                else => break,
+            };
+            if let Err(e) = write_message(&mut w, &msg).await {
+                tracing::error!("下发消息到代理进程失败 {}", e);
+                break;
+            }
+        }
+    });
+
+    // 已请求重新同步 收到完整状态前不再重复请求
+    let mut resyncing = false;
+    while let Some(line) = lines.next_line().await? {
+        let msg = match serde_json::from_str::<ChildMessage>(&line) {
+            Ok(msg) => msg,
+            Err(e) => {
+                tracing::error!("无法解析代理进程消息 {}", e);
+                continue;
+            }
+        };
+
+        if let ChildMessage::Reply { id, reply } = msg {
+            if let Some(tx) = pending.lock().unwrap().remove(&id) {
+                let _ = tx.send(reply);
+            }
+            continue;
+        }
+
+        let seq = msg.seq();
+        if seq <= last_seq {
+            continue;
+        }
+        if seq > last_seq + 1 && !resyncing {
+            tracing::warn!(
+                "代理进程 {} 消息缺失 #{}-#{}",
+                name,
+                last_seq + 1,
+                seq - 1
+            );
+            resyncing = true;
+            let _ = msg_tx.send(ParentMessage::Resync);
+        }
+
+        match msg {
+            ChildMessage::Worker { update, .. } => {
+                let base = state.find_worker(&name, update.session_id);
+                match update.apply(base.as_ref()) {
+                    Ok(worker) => {
+                        if update.full {
+                            resyncing = false;
+                        }
+                        state.apply_worker(&name, worker)
+                    }
+                    Err(e) if !resyncing => {
+                        tracing::warn!("代理进程 {} {}", name, e);
+                        resyncing = true;
+                        let _ = msg_tx.send(ParentMessage::Resync);
+                    }
+                    Err(_) => {}
+                }
+            }
+            ChildMessage::Fee { fee_targets, .. } => {
+                state
+                    .fee_report
+                    .lock()
+                    .unwrap()
+                    .record_targets(&name, &fee_targets);
+            }
+            ChildMessage::Event { event, .. } => {
+                state.events.lock().unwrap().record(&name, event);
+            }
+            ChildMessage::Hello { .. } | ChildMessage::Reply { .. } => continue,
+        }
+
+        last_seq = seq;
+        received
+            .lock()
+            .unwrap()
+            .insert(name.clone(), (instance, seq));
+        // 读缓冲区没有更多消息时确认
+        if lines.get_ref().buffer().is_empty() {
+            let _ = msg_tx.send(ParentMessage::Ack { seq });
+        }
+    }
+
+    bail!("代理进程 {} 断开连接", name)
+}
@@ -38,6 +38,12 @@ lazy_static! {
     pub static ref RUNTIME: tokio::time::Instant = Instant::now();
 }
 
+lazy_static! {
+    // 启动时的可执行文件路径。升级替换文件后 current_exe 指向已删除的旧文件
+    pub static ref CURRENT_EXE: std::path::PathBuf =
+        std::env::current_exe().expect("无法获取当前可执行程序路径");
+}
+
 pub fn init() {
     let a = RUNTIME.elapsed().as_secs();
     a.to_string();
@@ -47,9 +53,12 @@ pub fn init() {
     jwt_secret.to_string();
     let dev_fee = &DEVELOP_FEE;
     dev_fee.to_string();
+    let exe = &CURRENT_EXE;
+    exe.display().to_string();
 }
 
 pub mod client;
+pub mod ipc;
 pub mod protocol;
 pub mod proxy;
 pub mod state;
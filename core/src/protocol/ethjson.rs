@@ -324,6 +324,24 @@ pub struct EthServerRoot {
     pub result: bool,
 }
 
+// 通知矿机断开后重新连接 EthereumStratum 协议支持。参数为空时连接原地址
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EthClientReconnect {
+    pub id: Option<u64>,
+    pub method: String,
+    pub params: Vec<String>,
+}
+
+impl Default for EthClientReconnect {
+    fn default() -> Self {
+        Self {
+            id: None,
+            method: "client.reconnect".into(),
+            params: vec![],
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EthServer {
@@ -398,7 +416,7 @@ where
                 wallet.clone(),
             );
             let temp_full_wallet =
-                config.share_wallet.clone() + "." + split[1].clone();
+                config.share_wallet.clone() + "." + split[1];
             // 抽取全部替换钱包
             //rpc.set_wallet(&temp_full_wallet);
             *worker_name = temp_worker;
@@ -440,9 +458,10 @@ where
                 wallet.clone(),
             );
             let temp_full_wallet =
-                config.share_wallet.clone() + "." + split[1].clone();
+                config.share_wallet.clone() + "." + split[1];
             // 抽取全部替换钱包
             rpc.set_wallet(&temp_full_wallet);
+            worker.set_pool_wallet(&temp_full_wallet);
             *worker_name = temp_worker;
         } else {
             temp_worker.push_str(".");
@@ -453,8 +472,9 @@ where
                 wallet.clone(),
             );
             *worker_name = temp_worker;
-            // 抽取全部替换钱包
+            // 抽取全部替换钱包 矿工名通过 worker 字段保留
             rpc.set_wallet(&config.share_wallet);
+            worker.set_pool_wallet(&config.share_wallet);
         }
 
         write_to_socket_byte(w, rpc.to_vec()?, &worker_name).await
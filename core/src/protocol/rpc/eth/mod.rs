@@ -1,4 +1,4 @@
-use crate::util::hex_to_int;
+use crate::{state::reject::RejectReason, util::hex_to_int};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -395,40 +395,36 @@ pub struct ServerRootErrorValue {
     pub error: String,
 }
 
-pub fn handle_error(worker_id: u64, buf: &[u8]) {
+// 矿池返回的拒绝原因。无法解析时返回原始内容
+pub fn reject_message(buf: &[u8]) -> String {
     if let Ok(rpc) =
-        serde_json::from_slice::<crate::protocol::rpc::eth::ServerError>(&buf)
+        serde_json::from_slice::<crate::protocol::rpc::eth::ServerError>(buf)
     {
-        tracing::warn!("抽水矿机 {} Share Reject: {}", worker_id, rpc.error);
-    } else if let Ok(_rpc) =
-        serde_json::from_slice::<crate::protocol::rpc::eth::ServerRoot>(&buf)
+        rpc.error.message
+    } else if let Ok(rpc) =
+        serde_json::from_slice::<crate::protocol::rpc::eth::ServerRoot>(buf)
     {
-        //tracing::warn!("抽水矿机 {} Share Reject: {}", worker_id, rpc.error);
+        rpc.error
     } else if let Ok(rpc) = serde_json::from_slice::<
         crate::protocol::rpc::eth::ServerRootError,
-    >(&buf)
+    >(buf)
     {
-        tracing::warn!("抽水矿机 {} Share Reject: {}", worker_id, rpc.error.1);
+        rpc.error.1
     } else {
-        tracing::warn!("抽水矿机 {} Share Reject: {:?}", worker_id, buf);
+        String::from_utf8_lossy(buf).trim().to_string()
     }
 }
 
-pub fn handle_error_for_worker(worker_name: &String, buf: &[u8]) {
-    if let Ok(rpc) =
-        serde_json::from_slice::<crate::protocol::rpc::eth::ServerError>(&buf)
-    {
-        tracing::warn!("矿机 {} Share Reject: {}", worker_name, rpc.error);
-    } else if let Ok(_rpc) =
-        serde_json::from_slice::<crate::protocol::rpc::eth::ServerRoot>(&buf)
-    {
-        //tracing::warn!("矿机 {} Share Reject: {}", worker_name, rpc.error);
-    } else if let Ok(rpc) = serde_json::from_slice::<
-        crate::protocol::rpc::eth::ServerRootError,
-    >(&buf)
-    {
-        tracing::warn!("矿机 {} Share Reject: {}", worker_name, rpc.error.1);
-    } else {
-        tracing::warn!("矿机 {} Share Reject: {:?}", worker_name, buf);
-    }
+pub fn handle_error(worker_name: &str, buf: &[u8]) -> (RejectReason, String) {
+    let message = reject_message(buf);
+    tracing::warn!("抽水矿机 {} Share Reject: {}", worker_name, message);
+    (RejectReason::from_message(&message), message)
+}
+
+pub fn handle_error_for_worker(
+    worker_name: &str, buf: &[u8],
+) -> (RejectReason, String) {
+    let message = reject_message(buf);
+    tracing::warn!("矿机 {} Share Reject: {}", worker_name, message);
+    (RejectReason::from_message(&message), message)
 }
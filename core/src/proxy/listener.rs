@@ -0,0 +1,134 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::{bail, Result};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+
+use super::Proxy;
+use crate::{
+    client::{encry::accept_en_tcp, tcp::accept_tcp, tls::accept_tcp_with_tls},
+    util::{bind_reuse_port, config::Settings},
+    web::upgrade,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ListenerKind {
+    Tcp,
+    Ssl,
+    Encrypt,
+}
+
+impl ListenerKind {
+    pub const ALL: [ListenerKind; 3] =
+        [ListenerKind::Tcp, ListenerKind::Ssl, ListenerKind::Encrypt];
+
+    pub fn port(self, config: &Settings) -> u32 {
+        match self {
+            ListenerKind::Tcp => config.tcp_port,
+            ListenerKind::Ssl => config.ssl_port,
+            ListenerKind::Encrypt => config.encrypt_port,
+        }
+    }
+
+    // 对应的配置项
+    pub fn field(self) -> &'static str {
+        match self {
+            ListenerKind::Tcp => "tcp_port",
+            ListenerKind::Ssl => "ssl_port",
+            ListenerKind::Encrypt => "encrypt_port",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ListenerKind::Tcp => "TCP",
+            ListenerKind::Ssl => "SSL",
+            ListenerKind::Encrypt => "TCP加密协议",
+        }
+    }
+}
+
+// 本地监听端口。修改端口时只重启对应的监听 已连接的矿机不受影响
+pub struct Listeners {
+    tls_acceptor: TlsAcceptor,
+    tasks: std::sync::Mutex<HashMap<ListenerKind, (u32, JoinHandle<()>)>>,
+}
+
+impl Listeners {
+    pub fn new(cert: Arc<ServerConfig>) -> Self {
+        Self {
+            tls_acceptor: TlsAcceptor::from(cert),
+            tasks: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn port(&self, kind: ListenerKind) -> u32 {
+        match self.tasks.lock().unwrap().get(&kind) {
+            Some((port, _)) => *port,
+            None => 0,
+        }
+    }
+
+    // 按配置启动全部监听
+    pub async fn start(&self, proxy: &Arc<Proxy>) -> Result<()> {
+        let config = proxy.config.read().await.clone();
+        for kind in ListenerKind::ALL {
+            self.restart(proxy, kind, kind.port(&config)).await?;
+        }
+        Ok(())
+    }
+
+    // 绑定新端口成功后才关闭旧端口。端口为0时关闭监听
+    pub async fn restart(
+        &self, proxy: &Arc<Proxy>, kind: ListenerKind, port: u32,
+    ) -> Result<()> {
+        let listener = if port == 0 {
+            None
+        } else {
+            let address = format!("0.0.0.0:{}", port);
+            match bind_reuse_port(&address, upgrade::handover()) {
+                Ok(listener) => Some(listener),
+                Err(e) => bail!("本地端口被占用 {} {}", address, e),
+            }
+        };
+
+        let mut tasks = self.tasks.lock().unwrap();
+        if let Some((old, task)) = tasks.remove(&kind) {
+            task.abort();
+            tracing::info!("本地{}端口{} 已关闭", kind.name(), old);
+        }
+
+        if let Some(listener) = listener {
+            let task = self.spawn(proxy.clone(), kind, listener);
+            tasks.insert(kind, (port, task));
+            tracing::info!("本地{}端口{} 启动成功!!!", kind.name(), port);
+        }
+        Ok(())
+    }
+
+    // 关闭全部监听
+    pub fn stop(&self) {
+        for (kind, (port, task)) in self.tasks.lock().unwrap().drain() {
+            task.abort();
+            tracing::info!("本地{}端口{} 已关闭", kind.name(), port);
+        }
+    }
+
+    fn spawn(
+        &self, proxy: Arc<Proxy>, kind: ListenerKind, listener: TcpListener,
+    ) -> JoinHandle<()> {
+        let tls_acceptor = self.tls_acceptor.clone();
+        tokio::spawn(async move {
+            let res = match kind {
+                ListenerKind::Tcp => accept_tcp(proxy, listener).await,
+                ListenerKind::Ssl => {
+                    accept_tcp_with_tls(proxy, listener, tls_acceptor).await
+                }
+                ListenerKind::Encrypt => accept_en_tcp(proxy, listener).await,
+            };
+            if let Err(e) = res {
+                tracing::error!("本地{}端口监听失败 {}", kind.name(), e);
+            }
+        })
+    }
+}
@@ -1,21 +1,254 @@
-use std::{sync::Arc, collections::VecDeque};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use tokio::sync::{broadcast::Sender, mpsc::UnboundedSender, RwLock, Mutex};
+use tokio::{
+    sync::{
+        broadcast::Sender,
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot, watch, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use crate::{state::Worker, util::config::Settings};
+use crate::{
+    state::{event::SessionEvent, fee::FeeTargetStats, Worker},
+    util::config::Settings,
+};
+
+use self::listener::Listeners;
+
+pub mod listener;
+pub mod reload;
+pub mod runtime;
 
 pub type Job =    Arc<RwLock<VecDeque<Vec<String>>>>;
 
+// 排空默认等待时间。可由环境变量 MINING_PROXY_DRAIN_TIMEOUT 修改
+const DRAIN_TIMEOUT_SECS: u64 = 60;
+
+
+// 发往抽水矿池的份额。矿池接受后计入提交矿工的 fee_accept_index
+pub struct FeeShare {
+    pub params: Vec<String>,
+    pub accepted: Arc<AtomicU64>,
+}
+
+// 抽水目标。每个目标有自己的任务缓存和份额提交通道
+pub struct FeeTarget {
+    // 钱包.矿工名 用于统计
+    pub name: String,
+    pub weight: u32,
+    pub job: Job,
+    pub tx: tokio::sync::mpsc::Sender<FeeShare>,
+    pub stats: Arc<FeeTargetStats>,
+}
 
 pub struct Proxy {
     pub config: Arc<RwLock<Settings>>,
     // pub chan: Sender<Vec<String>>,
     // pub dev_chan: Sender<Vec<String>>,
-    pub fee_job:Job,
+    pub fee_targets: Vec<FeeTarget>,
     pub develop_job:Job,
-    pub tx: tokio::sync::mpsc::Sender<Vec<String>>,
     pub dev_tx: tokio::sync::mpsc::Sender<Vec<String>>,
     pub worker_tx: UnboundedSender<Worker>,
+    // 矿机连接事件 发送到主控端
+    pub event_tx: UnboundedSender<SessionEvent>,
+    // 在线会话 主控端可查询或断开
+    pub sessions: Sessions,
+    // 停止接受新连接 已连接的矿机不受影响
+    pub draining: AtomicBool,
+    // 排空完成 所有会话已结束
+    pub drained: watch::Sender<bool>,
+    pub listeners: Listeners,
     // pub proxy_write: Arc<Mutex<Box<dyn AsyncWrite + Send + Sync + Unpin>>>,
     // pub dev_write: Arc<Mutex<Box<dyn AsyncWrite + Send + Sync + Unpin>>>,
 }
+
+impl Proxy {
+    pub fn send_event(&self, event: SessionEvent) {
+        if self.event_tx.send(event).is_err() {
+            tracing::warn!("发送矿机事件失败");
+        }
+    }
+
+    pub fn is_draining(&self) -> bool { self.draining.load(Ordering::Relaxed) }
+
+    // 停止接受新连接并通知Stratum矿机重连 其余会话等待自行结束
+    // 超时后全部断开。返回时矿工的最终状态已发出
+    pub async fn drain(&self, timeout: Duration) {
+        if self.draining.swap(true, Ordering::Relaxed) {
+            return self.wait_drained().await;
+        }
+        tracing::info!(
+            "开始排空 剩余会话 {} 最多等待 {} 秒",
+            self.sessions.len(),
+            timeout.as_secs()
+        );
+        // 关闭监听 升级时新连接全部由新进程接受
+        self.listeners.stop();
+        self.sessions.reconnect_all();
+        if !self.sessions.wait_empty(Instant::now() + timeout).await {
+            tracing::info!("排空超时 断开剩余 {} 个会话", self.sessions.len());
+            self.sessions.kick_all("中转排空超时");
+            self.sessions
+                .wait_empty(Instant::now() + Duration::from_secs(3))
+                .await;
+        }
+        // 会话结束后才发送最终状态 稍等片刻
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        tracing::info!("排空完成");
+        self.drained.send_replace(true);
+    }
+
+    pub fn is_drained(&self) -> bool { *self.drained.borrow() }
+
+    pub async fn wait_drained(&self) {
+        let mut rx = self.drained.subscribe();
+        while !*rx.borrow_and_update() {
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+pub fn drain_timeout() -> Duration {
+    let secs = std::env::var("MINING_PROXY_DRAIN_TIMEOUT")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DRAIN_TIMEOUT_SECS);
+    Duration::from_secs(secs)
+}
+
+// 主控端对单个会话的操作
+pub enum SessionControl {
+    // 断开连接 附带原因
+    Kick(String),
+    // 回传当前矿工状态
+    Report(oneshot::Sender<Worker>),
+    // 中转排空。Stratum矿机发送 client.reconnect 后断开
+    Reconnect,
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    inner: std::sync::Mutex<HashMap<u64, UnboundedSender<SessionControl>>>,
+}
+
+impl Sessions {
+    // 会话开始时注册。返回的守卫释放时自动移除
+    pub fn register(
+        &self, session_id: u64,
+    ) -> (SessionGuard<'_>, UnboundedReceiver<SessionControl>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.inner.lock().unwrap().insert(session_id, tx);
+        (
+            SessionGuard {
+                sessions: self,
+                session_id,
+            },
+            rx,
+        )
+    }
+
+    pub fn len(&self) -> usize { self.inner.lock().unwrap().len() }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    pub fn kick(&self, session_id: u64, reason: String) -> bool {
+        match self.inner.lock().unwrap().get(&session_id) {
+            Some(tx) => tx.send(SessionControl::Kick(reason)).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn kick_all(&self, reason: &str) {
+        for tx in self.inner.lock().unwrap().values() {
+            let _ = tx.send(SessionControl::Kick(reason.to_string()));
+        }
+    }
+
+    pub fn reconnect_all(&self) {
+        for tx in self.inner.lock().unwrap().values() {
+            let _ = tx.send(SessionControl::Reconnect);
+        }
+    }
+
+    // 等待所有会话结束。超时返回false
+    pub async fn wait_empty(&self, deadline: Instant) -> bool {
+        while !self.is_empty() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        true
+    }
+
+    // 收集所有会话的当前状态。3秒内未回复的会话忽略
+    pub async fn snapshot(&self) -> Vec<Worker> {
+        let replies: Vec<oneshot::Receiver<Worker>> = self
+            .inner
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|tx| {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                tx.send(SessionControl::Report(reply_tx)).ok()?;
+                Some(reply_rx)
+            })
+            .collect();
+
+        let deadline =
+            tokio::time::Instant::now() + tokio::time::Duration::from_secs(3);
+        let mut workers = vec![];
+        for rx in replies {
+            if let Ok(Ok(w)) = tokio::time::timeout_at(deadline, rx).await {
+                workers.push(w);
+            }
+        }
+        workers.sort_by_key(|w| w.session_id);
+        workers
+    }
+}
+
+pub struct SessionGuard<'a> {
+    sessions: &'a Sessions,
+    session_id: u64,
+}
+
+impl Drop for SessionGuard<'_> {
+    fn drop(&mut self) {
+        self.sessions.inner.lock().unwrap().remove(&self.session_id);
+    }
+}
+
+#[tokio::test]
+async fn test_sessions_control() {
+    let sessions = Sessions::default();
+    let w = Worker::default();
+    let (guard, mut rx) = sessions.register(w.session_id);
+    assert_eq!(sessions.len(), 1);
+
+    tokio::spawn(async move {
+        while let Some(ctl) = rx.recv().await {
+            match ctl {
+                SessionControl::Report(tx) => tx.send(w.clone()).unwrap(),
+                SessionControl::Kick(_) => break,
+                SessionControl::Reconnect => {}
+            }
+        }
+    });
+    assert_eq!(sessions.snapshot().await.len(), 1);
+    assert!(sessions.kick(guard.session_id, "".into()));
+    assert!(!sessions.kick(0, "".into()));
+
+    drop(guard);
+    assert!(sessions.is_empty());
+    assert!(sessions.wait_empty(Instant::now()).await);
+}
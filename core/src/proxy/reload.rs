@@ -0,0 +1,237 @@
+use std::{path::Path, sync::Arc, time::SystemTime};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{listener::ListenerKind, Proxy};
+use crate::{
+    util::{cert_path, config::Settings},
+    web::config_store::ConfigStore,
+};
+
+// 配置热更新结果
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReloadReport {
+    // 已生效的配置项
+    pub applied: Vec<String>,
+    // 需要重启代理进程才能生效的配置项 保持原值
+    pub restart_required: Vec<String>,
+    // 生效失败的配置项及原因 保持原值
+    pub failed: Vec<String>,
+}
+
+impl ReloadReport {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty()
+            && self.restart_required.is_empty()
+            && self.failed.is_empty()
+    }
+}
+
+impl std::fmt::Display for ReloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "配置没有变化");
+        }
+        write!(f, "已生效 {:?}", self.applied)?;
+        if !self.restart_required.is_empty() {
+            write!(f, " 需要重启 {:?}", self.restart_required)?;
+        }
+        if !self.failed.is_empty() {
+            write!(f, " 失败 {:?}", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+// 比较新旧配置。返回只包含可以直接生效的修改的配置
+// 新任务和新连接使用新配置 端口和日志级别由 reload 单独处理
+pub fn plan(old: &Settings, new: &Settings) -> (Settings, ReloadReport) {
+    let mut merged = old.clone();
+    let mut report = ReloadReport::default();
+
+    macro_rules! live {
+        ($($field:ident),*) => {$(
+            if old.$field != new.$field {
+                merged.$field = new.$field.clone();
+                report.applied.push(stringify!($field).to_string());
+            }
+        )*};
+    }
+    macro_rules! restart {
+        ($($field:ident),*) => {$(
+            if old.$field != new.$field {
+                report.restart_required.push(stringify!($field).to_string());
+            }
+        )*};
+    }
+
+    live!(
+        pool_address,
+        share_rate,
+        hash_rate,
+        fee_rules,
+        log_level,
+        tcp_port,
+        ssl_port,
+        encrypt_port
+    );
+    // 抽水矿池连接和证书在启动时建立
+    restart!(
+        name,
+        coin,
+        share,
+        share_alg,
+        share_address,
+        share_wallet,
+        share_name,
+        fee_destinations,
+        pem_path,
+        key_path
+    );
+    (merged, report)
+}
+
+// 校验并应用新配置。已连接的矿机不会断开
+pub async fn reload(proxy: &Arc<Proxy>, new: Settings) -> Result<ReloadReport> {
+    new.check().await?;
+
+    let old = proxy.config.read().await.clone();
+    let (mut merged, mut report) = plan(&old, &new);
+
+    for kind in ListenerKind::ALL {
+        let port = kind.port(&merged);
+        if port == kind.port(&old) {
+            continue;
+        }
+        if let Err(e) = proxy.listeners.restart(proxy, kind, port).await {
+            report.failed.push(format!("{} {}", kind.field(), e));
+            match kind {
+                ListenerKind::Tcp => merged.tcp_port = old.tcp_port,
+                ListenerKind::Ssl => merged.ssl_port = old.ssl_port,
+                ListenerKind::Encrypt => merged.encrypt_port = old.encrypt_port,
+            }
+        }
+    }
+
+    if merged.log_level != old.log_level {
+        if let Err(e) = crate::util::logger::set_level(&merged.log_level) {
+            report.failed.push(format!("log_level {}", e));
+            merged.log_level = old.log_level.clone();
+        }
+    }
+
+    let failed = &report.failed;
+    report
+        .applied
+        .retain(|field| !failed.iter().any(|f| f.starts_with(field.as_str())));
+    *proxy.config.write().await = merged;
+    tracing::info!("配置已重新加载 {}", report);
+    Ok(report)
+}
+
+// 重新加载时读取配置的位置
+pub enum ConfigSource {
+    // 独立运行 读取 --config 指定的文件和 PROXY_ 环境变量
+    File(String),
+    // 由后台启动 启动时的环境变量是旧配置 从 configs.yaml 读取同名配置项
+    Store(ConfigStore, String),
+}
+
+impl ConfigSource {
+    // 后台启动的子进程带有 PROXY_IPC_SOCKET 且没有指定配置文件
+    pub fn detect(config_file: Option<&str>, name: &str) -> Self {
+        match config_file {
+            None if std::env::var("PROXY_IPC_SOCKET").is_ok() => {
+                ConfigSource::Store(ConfigStore::from_env(), name.to_string())
+            }
+            _ => ConfigSource::File(
+                config_file.unwrap_or("default.yaml").to_string(),
+            ),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            ConfigSource::File(path) => Path::new(path),
+            ConfigSource::Store(store, _) => store.path(),
+        }
+    }
+
+    fn load(&self) -> Result<Settings> {
+        match self {
+            ConfigSource::File(path) => Ok(Settings::new(path, true)?),
+            ConfigSource::Store(store, name) => {
+                let mut config = store
+                    .load()?
+                    .into_iter()
+                    .find(|c| &c.name == name)
+                    .ok_or_else(|| anyhow!("configs.yaml 中没有 {}", name))?;
+                // 和启动子进程时一致
+                config.pem_path = cert_path(&config.pem_path);
+                config.key_path = cert_path(&config.key_path);
+                Ok(config)
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).ok()?.modified().ok()
+}
+
+// 收到 SIGHUP 或配置文件修改后重新加载
+pub async fn watch(proxy: Arc<Proxy>, source: ConfigSource) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut interval =
+        tokio::time::interval(tokio::time::Duration::from_secs(5));
+    let mut last_modified = modified(source.path());
+
+    loop {
+        tokio::select! {
+            _ = hangup.recv() => {
+                tracing::info!("收到 SIGHUP 重新加载配置");
+            },
+            _ = interval.tick() => {
+                let m = modified(source.path());
+                if m == last_modified {
+                    continue;
+                }
+                last_modified = m;
+                tracing::info!(
+                    "配置文件 {} 已修改 重新加载配置",
+                    source.path().display()
+                );
+            },
+        }
+
+        let res = match source.load() {
+            Ok(new) => reload(&proxy, new).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            tracing::error!("配置重新加载失败 保持原配置 {}", e);
+        }
+    }
+}
+
+#[test]
+fn test_reload_plan() {
+    let old = Settings::default();
+    let mut new = old.clone();
+    new.share_rate = 0.1;
+    new.tcp_port = 14445;
+    new.coin = "ETC".into();
+
+    let (merged, report) = plan(&old, &new);
+    assert_eq!(report.applied, vec!["share_rate", "tcp_port"]);
+    assert_eq!(report.restart_required, vec!["coin"]);
+    assert_eq!(merged.share_rate, 0.1);
+    assert_eq!(merged.coin, "ETH");
+
+    let (_, report) = plan(&old, &old);
+    assert!(report.is_empty());
+}
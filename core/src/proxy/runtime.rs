@@ -0,0 +1,199 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use anyhow::{bail, Result};
+use rustls_pemfile::{certs, rsa_private_keys};
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver},
+    watch, RwLock,
+};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+use super::{
+    drain_timeout, listener::Listeners, FeeShare, FeeTarget, Job, Proxy,
+    Sessions,
+};
+use crate::{
+    state::{event::SessionEvent, fee::FeeTargetStats, Worker},
+    util::config::Settings,
+};
+
+lazy_static! {
+    // 同一证书只加载一次 单进程模式下多个中转共用
+    static ref TLS_CONFIGS: std::sync::Mutex<HashMap<(String, String), Arc<ServerConfig>>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+fn load_certs(path: &Path) -> std::io::Result<Vec<Certificate>> {
+    certs(&mut std::io::BufReader::new(std::fs::File::open(path)?))
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid cert",
+            )
+        })
+        .map(|mut certs| certs.drain(..).map(Certificate).collect())
+}
+
+fn load_keys(path: &Path) -> std::io::Result<Vec<PrivateKey>> {
+    rsa_private_keys(&mut std::io::BufReader::new(std::fs::File::open(path)?))
+        .map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid key")
+        })
+        .map(|mut keys| keys.drain(..).map(PrivateKey).collect())
+}
+
+// 读取SSL证书及秘钥
+pub fn tls_config(config: &Settings) -> Result<Arc<ServerConfig>> {
+    let key = (config.pem_path.clone(), config.key_path.clone());
+    if let Some(cert) = TLS_CONFIGS.lock().unwrap().get(&key) {
+        return Ok(cert.clone());
+    }
+
+    let certs = match load_certs(Path::new(&config.pem_path)) {
+        Ok(certs) => certs,
+        Err(_) => bail!(
+            "自定义SSL证书 {} 读取失败。请设置证书。未设置程序将退出。。",
+            config.pem_path
+        ),
+    };
+    let mut keys = match load_keys(Path::new(&config.key_path)) {
+        Ok(keys) if !keys.is_empty() => keys,
+        _ => bail!(
+            "自定义秘钥key {} 读取失败。请设置证书。未设置程序将退出。。",
+            config.key_path
+        ),
+    };
+
+    let cert = match ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, keys.remove(0))
+    {
+        Ok(cert) => Arc::new(cert),
+        Err(e) => bail!("证书格式化失败。 请修改证书: {}", e),
+    };
+    TLS_CONFIGS.lock().unwrap().insert(key, cert.clone());
+    Ok(cert)
+}
+
+// 矿工状态及连接事件。由主控端通信任务接收
+pub struct ProxyReceivers {
+    pub worker_rx: UnboundedReceiver<Worker>,
+    pub event_rx: UnboundedReceiver<SessionEvent>,
+}
+
+// 抽水及开发者抽水任务。任一任务出错时返回
+pub type ProxyTasks = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+// 创建中转并启动本地监听
+pub async fn start(
+    config: Settings, cert: Arc<ServerConfig>,
+) -> Result<(Arc<Proxy>, ProxyReceivers, ProxyTasks)> {
+    let mode = if config.share == 0 {
+        "纯代理模式"
+    } else if config.share == 1 {
+        "抽水模式"
+    } else {
+        "统一钱包模式"
+    };
+    tracing::info!("名称 {} 当前启动模式为: {}", config.name, mode);
+
+    let fee_destinations = config.get_fee_destinations();
+    for dest in &fee_destinations {
+        if let Err(e) =
+            crate::client::get_pool_ip_and_type_from_vec(&dest.pool_address)
+        {
+            bail!("抽水矿池参数格式化失败。无法启动 {}", e);
+        }
+    }
+
+    let develop_job: Job = Arc::new(RwLock::new(VecDeque::new()));
+
+    // 每个抽水目标一个任务队列和份额提交通道
+    let mut fee_targets = vec![];
+    let mut fee_receivers = vec![];
+    for dest in fee_destinations {
+        let job: Job = Arc::new(RwLock::new(VecDeque::new()));
+        let (tx, rx) = mpsc::channel::<FeeShare>(15);
+        let stats = Arc::new(FeeTargetStats::default());
+        fee_targets.push(FeeTarget {
+            name: format!("{}.{}", dest.wallet, dest.get_worker_name()?),
+            weight: dest.weight,
+            job: job.clone(),
+            tx,
+            stats: stats.clone(),
+        });
+        fee_receivers.push((rx, job, dest, stats));
+    }
+
+    let (dev_tx, dev_rx) = mpsc::channel::<Vec<String>>(15);
+    tracing::debug!("创建矿工队列");
+    // 旷工状态发送队列
+    let (worker_tx, worker_rx) = mpsc::unbounded_channel::<Worker>();
+    let (event_tx, event_rx) = mpsc::unbounded_channel::<SessionEvent>();
+
+    let proxy = Arc::new(Proxy {
+        config: Arc::new(RwLock::new(config)),
+        worker_tx,
+        event_tx,
+        fee_targets,
+        dev_tx,
+        develop_job: develop_job.clone(),
+        sessions: Sessions::default(),
+        draining: AtomicBool::new(false),
+        drained: watch::channel(false).0,
+        listeners: Listeners::new(cert),
+    });
+    let (dev_lines, dev_w) = crate::client::dev_pool_ssl_login(
+        crate::DEVELOP_WORKER_NAME.to_string(),
+    )
+    .await?;
+
+    // 其余准备都成功后才监听 出错时关闭已打开的端口 避免重启时端口被占用
+    if let Err(e) = proxy.listeners.start(&proxy).await {
+        proxy.listeners.stop();
+        return Err(e);
+    }
+
+    let p = proxy.clone();
+    let tasks: ProxyTasks = Box::pin(async move {
+        tokio::try_join!(
+            crate::client::fee::fee_all(fee_receivers),
+            crate::client::fee::develop_fee_ssl(
+                dev_rx,
+                develop_job,
+                dev_lines,
+                dev_w,
+                crate::DEVELOP_WORKER_NAME.to_string(),
+                p,
+            ),
+        )?;
+        Ok(())
+    });
+
+    Ok((
+        proxy,
+        ProxyReceivers {
+            worker_rx,
+            event_rx,
+        },
+        tasks,
+    ))
+}
+
+// 收到 SIGTERM 后排空。最终状态由主控端通信任务上报后退出
+pub async fn drain_on_terminate(proxy: Arc<Proxy>) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut term = signal(SignalKind::terminate())?;
+    term.recv().await;
+    tracing::info!("收到 SIGTERM 开始排空");
+    proxy.drain(drain_timeout()).await;
+    Ok(())
+}
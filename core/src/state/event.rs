@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+
+use super::Worker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Connect,
+    Login,
+    LoginFailed,
+    // 首选矿池不可用 使用了备用矿池
+    PoolSwitch,
+    Disconnect,
+    // 协议不正确或管理员操作 被中转主动断开
+    Kicked,
+}
+
+// 断开原因分类
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectCategory {
+    #[default]
+    None,
+    // 矿机主动断开
+    WorkerClosed,
+    // 矿池主动断开
+    PoolClosed,
+    // 读写错误 连接超时等
+    Network,
+    // 所有矿池都无法连接
+    PoolUnavailable,
+    // 协议错误或恶意连接
+    Protocol,
+    // 管理员在后台断开
+    Admin,
+    // 中转排空 通知矿机重连
+    Drain,
+    Other,
+}
+
+impl DisconnectCategory {
+    // 按错误信息归类。错误信息见 lines_unwrap 等函数
+    pub fn from_reason(reason: &str) -> Self {
+        if reason.is_empty() {
+            DisconnectCategory::None
+        } else if reason.starts_with("管理员断开") {
+            DisconnectCategory::Admin
+        } else if reason.starts_with("中转排空") {
+            DisconnectCategory::Drain
+        } else if reason == "安全下线" {
+            DisconnectCategory::WorkerClosed
+        } else if reason.contains("主动断开") {
+            if reason.starts_with("矿池") {
+                DisconnectCategory::PoolClosed
+            } else {
+                DisconnectCategory::WorkerClosed
+            }
+        } else if reason.contains("不可链接") {
+            DisconnectCategory::PoolUnavailable
+        } else if reason.contains("攻击")
+            || reason.contains("恶意")
+            || reason.contains("协议")
+        {
+            DisconnectCategory::Protocol
+        } else if reason.contains("读取错误")
+            || reason.contains("写入")
+            || reason.contains("os error")
+            || reason.contains("timed out")
+        {
+            DisconnectCategory::Network
+        } else {
+            DisconnectCategory::Other
+        }
+    }
+}
+
+// 矿机连接事件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionEvent {
+    // 秒级时间戳
+    pub time: i64,
+    pub kind: EventKind,
+    pub session_id: u64,
+    pub worker: String,
+    pub wallet: String,
+    pub ip: String,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub category: DisconnectCategory,
+}
+
+impl SessionEvent {
+    pub fn new(kind: EventKind, worker: &Worker, reason: String) -> Self {
+        let category = match kind {
+            EventKind::Disconnect | EventKind::Kicked => {
+                DisconnectCategory::from_reason(&reason)
+            }
+            _ => DisconnectCategory::None,
+        };
+
+        Self {
+            time: chrono::Utc::now().timestamp(),
+            kind,
+            session_id: worker.session_id,
+            worker: worker.worker.clone(),
+            wallet: worker.wallet().to_string(),
+            ip: worker.ip.clone(),
+            reason,
+            category,
+        }
+    }
+
+    // 连接结束事件。协议错误或管理员断开视为被踢下线
+    pub fn closed(worker: &Worker, reason: String) -> Self {
+        let kind = match DisconnectCategory::from_reason(&reason) {
+            DisconnectCategory::Protocol | DisconnectCategory::Admin => {
+                EventKind::Kicked
+            }
+            _ => EventKind::Disconnect,
+        };
+        Self::new(kind, worker, reason)
+    }
+}
+
+#[test]
+fn test_disconnect_category() {
+    assert_eq!(
+        DisconnectCategory::from_reason("矿池：w1 矿池主动断开 "),
+        DisconnectCategory::PoolClosed
+    );
+    assert_eq!(
+        DisconnectCategory::from_reason("矿机：w1 矿池主动断开 "),
+        DisconnectCategory::WorkerClosed
+    );
+    assert_eq!(
+        DisconnectCategory::from_reason("矿机：w1 读取错误: reset "),
+        DisconnectCategory::Network
+    );
+    assert_eq!(
+        DisconnectCategory::from_reason("所有TCP矿池均不可链接。请修改后重试"),
+        DisconnectCategory::PoolUnavailable
+    );
+
+    let w = Worker::default();
+    assert_eq!(
+        SessionEvent::closed(&w, "非法攻击".into()).kind,
+        EventKind::Kicked
+    );
+    assert_eq!(
+        SessionEvent::closed(&w, "管理员断开 ".into()).category,
+        DisconnectCategory::Admin
+    );
+    assert_eq!(
+        SessionEvent::closed(&w, "中转排空 通知矿机重连".into()).kind,
+        EventKind::Disconnect
+    );
+    assert_eq!(
+        SessionEvent::closed(&w, "安全下线".into()).kind,
+        EventKind::Disconnect
+    );
+}
@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    reject::{RejectCounters, RejectReason},
+    Worker,
+};
+
+// 抽水相关计数。代理进程上报累计值，主控端按时间段计算增量
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeeCounters {
+    // 下发给矿机的任务总数
+    pub jobs: u64,
+    // 其中的抽水任务
+    pub fee_jobs: u64,
+    // 矿机提交的普通份额
+    pub shares: u64,
+    // 矿机提交的抽水份额
+    pub fee_shares: u64,
+    // 抽水矿池接受的份额
+    pub accepted: u64,
+    // 抽水矿池拒绝的份额
+    pub rejected: u64,
+    // 抽水通道已满被丢弃的份额
+    pub dropped: u64,
+}
+
+impl FeeCounters {
+    // 矿工连接的累计值
+    pub fn from_worker(worker: &Worker) -> Self {
+        FeeCounters {
+            jobs: worker.total_send_idx as u64,
+            fee_jobs: worker.total_fee_idx as u64,
+            shares: worker.share_index,
+            fee_shares: worker.fee_share_index,
+            ..Default::default()
+        }
+    }
+
+    // 与上一次累计值的差。某一项变小说明进程或矿工重连过，该项直接使用当前值
+    pub fn delta(&self, last: &FeeCounters) -> FeeCounters {
+        fn sub(now: u64, last: u64) -> u64 {
+            if now >= last {
+                now - last
+            } else {
+                now
+            }
+        }
+
+        FeeCounters {
+            jobs: sub(self.jobs, last.jobs),
+            fee_jobs: sub(self.fee_jobs, last.fee_jobs),
+            shares: sub(self.shares, last.shares),
+            fee_shares: sub(self.fee_shares, last.fee_shares),
+            accepted: sub(self.accepted, last.accepted),
+            rejected: sub(self.rejected, last.rejected),
+            dropped: sub(self.dropped, last.dropped),
+        }
+    }
+
+    pub fn add(&mut self, other: &FeeCounters) {
+        self.jobs += other.jobs;
+        self.fee_jobs += other.fee_jobs;
+        self.shares += other.shares;
+        self.fee_shares += other.fee_shares;
+        self.accepted += other.accepted;
+        self.rejected += other.rejected;
+        self.dropped += other.dropped;
+    }
+
+    pub fn is_empty(&self) -> bool { *self == FeeCounters::default() }
+}
+
+// 单个抽水目标的计数。在矿工连接和抽水连接之间共享
+#[derive(Debug, Default)]
+pub struct FeeTargetStats {
+    pub fee_jobs: AtomicU64,
+    pub fee_shares: AtomicU64,
+    pub accepted: AtomicU64,
+    pub rejected: AtomicU64,
+    pub dropped: AtomicU64,
+    // 抽水矿池是否已连接
+    pub connected: AtomicBool,
+    pub rejects: std::sync::Mutex<RejectCounters>,
+}
+
+impl FeeTargetStats {
+    pub fn snapshot(&self) -> FeeCounters {
+        FeeCounters {
+            fee_jobs: self.fee_jobs.load(Ordering::Relaxed),
+            fee_shares: self.fee_shares.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(counter: &AtomicU64) { counter.fetch_add(1, Ordering::Relaxed); }
+
+    // 抽水矿池拒绝份额 按原因计数
+    pub fn reject(&self, reason: RejectReason, message: &str) {
+        Self::add(&self.rejected);
+        self.rejects.lock().unwrap().add(reason, message);
+    }
+
+    pub fn rejects(&self) -> RejectCounters {
+        self.rejects.lock().unwrap().clone()
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    pub fn is_connected(&self) -> bool { self.connected.load(Ordering::Relaxed) }
+}
+
+// 代理进程上报的抽水目标计数
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeeTargetReport {
+    pub name: String,
+    pub weight: u32,
+    pub counters: FeeCounters,
+    #[serde(default)]
+    pub connected: bool,
+    #[serde(default)]
+    pub rejects: RejectCounters,
+}
+
+#[test]
+fn test_fee_counters_delta() {
+    let last = FeeCounters {
+        jobs: 10,
+        fee_jobs: 2,
+        ..Default::default()
+    };
+    let now = FeeCounters {
+        jobs: 15,
+        fee_jobs: 3,
+        ..Default::default()
+    };
+    assert_eq!(now.delta(&last).jobs, 5);
+    assert_eq!(now.delta(&last).fee_jobs, 1);
+
+    let restarted = FeeCounters {
+        jobs: 4,
+        fee_jobs: 3,
+        ..Default::default()
+    };
+    assert_eq!(restarted.delta(&last).jobs, 4);
+    assert_eq!(restarted.delta(&last).fee_jobs, 1);
+}
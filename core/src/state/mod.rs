@@ -1,25 +1,59 @@
 use std::u128;
 
-extern crate serde_millis;
-
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::time::Instant;
 use tracing::{debug, info};
 
 use crate::protocol::PROTOCOL;
 
+use self::reject::{RejectCounters, RejectReason};
+
+pub mod event;
+pub mod fee;
+pub mod reject;
+
+lazy_static! {
+    // 高位为进程启动时间 代理进程重启后会话编号也不会重复
+    static ref SESSION_ID: std::sync::atomic::AtomicU64 =
+        std::sync::atomic::AtomicU64::new(
+            (chrono::Utc::now().timestamp() as u64) << 20
+        );
+}
+
+fn now() -> i64 { chrono::Utc::now().timestamp() }
+
+// 每个矿机连接一个唯一的会话编号
+pub fn next_session_id() -> u64 {
+    SESSION_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Worker {
+    #[serde(default)]
+    pub session_id: u64,
     pub worker: String,
     pub online: bool,
     pub worker_name: String,
     pub worker_wallet: String,
+    #[serde(default)]
+    pub ip: String,
+    // 统一钱包模式下矿池看到的钱包。为空表示未替换
+    #[serde(default)]
+    pub pool_wallet: String,
+    // 下线时间 秒级时间戳。在线时为0
+    #[serde(default)]
+    pub offline_at: i64,
+    // 下线原因
+    #[serde(default)]
+    pub disconnect_reason: String,
+    // 当前连接的矿池地址
+    #[serde(default)]
+    pub pool: String,
     pub protocol: PROTOCOL,
-    #[serde(with = "serde_millis")]
-    pub login_time: Instant,
-    #[serde(with = "serde_millis")]
-    pub last_subwork_time: Instant,
+    // 连接时间 秒级时间戳。跨进程传递 不能使用Instant
+    pub login_time: i64,
+    // 最后一次提交份额时间 秒级时间戳
+    pub last_subwork_time: i64,
     pub rpc_id: u64,
     pub hash: u64,
     pub total_send_idx: u128,
@@ -32,6 +66,9 @@ pub struct Worker {
     pub fee_share_index: u64,
     pub fee_accept_index: u64,
     pub fee_invalid_index: u64,
+    // 拒绝份额按原因分类
+    #[serde(default)]
+    pub rejects: RejectCounters,
 }
 
 impl Worker {
@@ -40,12 +77,18 @@ impl Worker {
         online: bool,
     ) -> Self {
         Self {
+            session_id: next_session_id(),
             worker,
             online,
             worker_wallet,
             worker_name,
-            login_time: Instant::now(),
-            last_subwork_time: Instant::now(),
+            ip: "".into(),
+            pool_wallet: "".into(),
+            offline_at: 0,
+            disconnect_reason: "".into(),
+            pool: "".into(),
+            login_time: now(),
+            last_subwork_time: now(),
             protocol: PROTOCOL::KNOWN,
             hash: 0,
             total_send_idx: 0,
@@ -58,18 +101,25 @@ impl Worker {
             fee_accept_index: 0,
             fee_invalid_index: 0,
             rpc_id: 0,
+            rejects: RejectCounters::default(),
         }
     }
 
     pub fn default() -> Self {
         Self {
+            session_id: next_session_id(),
             worker: "".into(),
             online: false,
             worker_name: "".into(),
             worker_wallet: "".into(),
+            ip: "".into(),
+            pool_wallet: "".into(),
+            offline_at: 0,
+            disconnect_reason: "".into(),
+            pool: "".into(),
             protocol: PROTOCOL::KNOWN,
-            login_time: Instant::now(),
-            last_subwork_time: Instant::now(),
+            login_time: now(),
+            last_subwork_time: now(),
             hash: 0,
             share_index: 0,
             accept_index: 0,
@@ -81,6 +131,7 @@ impl Worker {
             fee_accept_index: 0,
             fee_invalid_index: 0,
             rpc_id: 0,
+            rejects: RejectCounters::default(),
         }
     }
 
@@ -129,13 +180,54 @@ impl Worker {
         true
     }
 
+    // 连接断开 记录下线时间及原因
+    pub fn disconnect(&mut self, reason: String) -> bool {
+        self.offline_at = now();
+        self.disconnect_reason = reason;
+        self.offline()
+    }
+
+    // 记录连接的矿池
+    pub fn set_pool(&mut self, pool: String) { self.pool = pool; }
+
+    // 记录矿机来源IP
+    pub fn set_ip(&mut self, ip: std::net::IpAddr) { self.ip = ip.to_string(); }
+
+    // 统一钱包模式 记录替换后的钱包
+    pub fn set_pool_wallet(&mut self, wallet: &str) {
+        self.pool_wallet = wallet.to_string();
+    }
+
+    // 钱包地址 不包含矿工名
+    pub fn wallet(&self) -> &str {
+        match self.worker_wallet.split_once('.') {
+            Some((wallet, _)) => wallet,
+            None => self.worker_wallet.as_str(),
+        }
+    }
+
     // 设置当前链接协议
     pub fn set_protocol(&mut self, p: PROTOCOL) { self.protocol = p; }
 
+    // 在线时长(秒)。已下线时计算到下线时间
+    pub fn online_secs(&self, now: i64) -> u64 {
+        let end = if self.is_online() || self.offline_at == 0 {
+            now
+        } else {
+            self.offline_at
+        };
+        (end - self.login_time).max(0) as u64
+    }
+
+    // 距最后一次提交份额的秒数
+    pub fn idle_secs(&self, now: i64) -> u64 {
+        (now - self.last_subwork_time).max(0) as u64
+    }
+
     // 判断是否在线
     pub fn is_online(&self) -> bool { self.online }
 
-    // 每十分钟清空份额调用方法
+    // 登录成功时清空份额及拒绝原因统计
     pub fn clear_state(&mut self) {
         // info!(
         //     "✅ worker {} 清空所有数据。清空时有如下数据 {} {} {}",
@@ -144,12 +236,13 @@ impl Worker {
         self.share_index = 0;
         self.accept_index = 0;
         self.invalid_index = 0;
+        self.rejects = RejectCounters::default();
         //self.login_time = Instant::now();
     }
 
     // 总份额增加
     pub fn share_index_add(&mut self) {
-        self.last_subwork_time = Instant::now();
+        self.last_subwork_time = now();
 
         self.share_index += 1;
         debug!("矿工: {} Share #{}", self.worker, self.share_index);
@@ -167,6 +260,12 @@ impl Worker {
         debug!("矿工: {} Share Reject #{}", self.worker, self.share_index);
     }
 
+    // 拒绝的份额 并记录拒绝原因
+    pub fn share_reject_with(&mut self, reason: RejectReason, message: &str) {
+        self.share_reject();
+        self.rejects.add(reason, message);
+    }
+
     // 总份额增加
     pub fn fee_share_index_add(&mut self) {
         //self.last_subwork_time = Instant::now();
@@ -238,3 +337,30 @@ fn test_share_reject() {
     assert_eq!(w.accept_index, 0);
     assert_eq!(w.invalid_index, 1);
 }
+
+#[test]
+fn test_clear_state() {
+    let mut w = Worker::default();
+    w.share_index_add();
+    w.share_reject_with(RejectReason::Stale, "stale");
+    w.clear_state();
+    assert_eq!(w.share_index, 0);
+    assert_eq!(w.invalid_index, 0);
+    assert_eq!(w.rejects, RejectCounters::default());
+}
+
+#[test]
+fn test_worker_times_survive_ipc() {
+    let mut w = Worker::default();
+    w.login_time = 1000;
+    w.last_subwork_time = 1500;
+    let mut w: Worker =
+        serde_json::from_str(&serde_json::to_string(&w).unwrap()).unwrap();
+    assert_eq!(w.online_secs(1600), 600);
+    assert_eq!(w.idle_secs(1600), 100);
+
+    w.offline_at = 1200;
+    assert_eq!(w.online_secs(1600), 200);
+    // 时钟回拨时不出现负数
+    assert_eq!(w.idle_secs(1000), 0);
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+// 其他原因最多保留几条矿池原始信息
+const MAX_SAMPLES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    Stale,
+    Duplicate,
+    LowDifficulty,
+    Invalid,
+    UnknownJob,
+    Other,
+}
+
+impl RejectReason {
+    // 按矿池返回的错误信息归类
+    pub fn from_message(message: &str) -> Self {
+        let msg = message.to_lowercase();
+        let has = |keys: &[&str]| keys.iter().any(|k| msg.contains(k));
+
+        if has(&["duplicate", "already submitted", "already found"]) {
+            RejectReason::Duplicate
+        } else if has(&["stale", "expired", "too old", "obsolete"]) {
+            RejectReason::Stale
+        } else if has(&[
+            "job not found",
+            "unknown job",
+            "no such job",
+            "invalid job",
+        ]) {
+            RejectReason::UnknownJob
+        } else if has(&[
+            "low difficulty",
+            "low diff",
+            "lowdiff",
+            "above target",
+            "high-hash",
+            "high hash",
+        ]) {
+            RejectReason::LowDifficulty
+        } else if has(&["invalid", "bad nonce", "malformed", "incorrect"]) {
+            RejectReason::Invalid
+        } else {
+            RejectReason::Other
+        }
+    }
+}
+
+// 拒绝份额按原因计数
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RejectCounters {
+    pub stale: u64,
+    pub duplicate: u64,
+    pub low_difficulty: u64,
+    pub invalid: u64,
+    pub unknown_job: u64,
+    pub other: u64,
+    // 归为其他原因的矿池原始信息 去重后保留最近几条
+    pub other_samples: Vec<String>,
+}
+
+impl RejectCounters {
+    pub fn add(&mut self, reason: RejectReason, message: &str) {
+        match reason {
+            RejectReason::Stale => self.stale += 1,
+            RejectReason::Duplicate => self.duplicate += 1,
+            RejectReason::LowDifficulty => self.low_difficulty += 1,
+            RejectReason::Invalid => self.invalid += 1,
+            RejectReason::UnknownJob => self.unknown_job += 1,
+            RejectReason::Other => {
+                self.other += 1;
+                self.sample(message);
+            }
+        }
+    }
+
+    fn sample(&mut self, message: &str) {
+        if message.is_empty() {
+            return;
+        }
+        self.other_samples.retain(|m| m != message);
+        self.other_samples.push(message.chars().take(200).collect());
+        if self.other_samples.len() > MAX_SAMPLES {
+            self.other_samples.remove(0);
+        }
+    }
+
+    pub fn merge(&mut self, other: &RejectCounters) {
+        self.stale += other.stale;
+        self.duplicate += other.duplicate;
+        self.low_difficulty += other.low_difficulty;
+        self.invalid += other.invalid;
+        self.unknown_job += other.unknown_job;
+        self.other += other.other;
+        for m in &other.other_samples {
+            self.sample(m);
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.stale
+            + self.duplicate
+            + self.low_difficulty
+            + self.invalid
+            + self.unknown_job
+            + self.other
+    }
+}
+
+#[test]
+fn test_reject_reason() {
+    use RejectReason::*;
+
+    assert_eq!(RejectReason::from_message("Job not found (=stale)"), Stale);
+    assert_eq!(RejectReason::from_message("Duplicate share"), Duplicate);
+    assert_eq!(RejectReason::from_message("Invalid job id"), UnknownJob);
+    assert_eq!(
+        RejectReason::from_message("Low difficulty share"),
+        LowDifficulty
+    );
+    assert_eq!(RejectReason::from_message("Invalid share"), Invalid);
+    assert_eq!(RejectReason::from_message("Banned"), Other);
+
+    let mut c = RejectCounters::default();
+    for i in 0..7 {
+        c.add(Other, &format!("msg {}", i % 6));
+    }
+    c.add(Stale, "stale");
+    assert_eq!(c.total(), 8);
+    assert_eq!(c.other_samples.len(), 5);
+    assert_eq!(c.other_samples.last().unwrap(), "msg 0");
+}
@@ -1,13 +1,19 @@
 use anyhow::{bail, Result};
 use config::{Config, ConfigError, Environment, File};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{env, net::TcpListener};
 
-use crate::client::{SSL, TCP};
+use crate::{
+    client::{SSL, TCP},
+    state::Worker,
+};
 
-use super::get_develop_fee;
+use super::{
+    fee_rule::{FeeRule, FeeRules, FeeRulesCache},
+    get_develop_fee,
+};
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Settings {
     pub coin: String,
     pub name: String,
@@ -25,6 +31,64 @@ pub struct Settings {
     pub share_alg: u32,
     pub pem_path: String,
     pub key_path: String,
+    #[serde(default)]
+    pub fee_rules: Vec<FeeRule>,
+    // 编译后的 fee_rules 不写入配置文件
+    #[serde(skip)]
+    pub compiled_fee_rules: FeeRulesCache,
+    #[serde(default)]
+    pub fee_destinations: Vec<FeeDestination>,
+    // 在后台停止 主控端启动时不运行
+    #[serde(default)]
+    pub stopped: bool,
+}
+
+// 抽水目标。每个目标单独连接矿池，按权重分配抽水回合
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct FeeDestination {
+    pub pool_address: Vec<String>,
+    pub wallet: String,
+    #[serde(default)]
+    pub worker_name: String,
+    pub weight: u32,
+}
+
+impl FeeDestination {
+    pub fn get_worker_name(&self) -> Result<String> {
+        default_worker_name(&self.worker_name)
+    }
+}
+
+fn default_worker_name(name: &str) -> Result<String> {
+    let mut hostname = name.to_string();
+    if hostname.is_empty() {
+        let name = hostname::get()?;
+        if name.is_empty() {
+            hostname = "proxy_wallet_mine".into();
+        } else {
+            hostname = hostname + name.to_str().unwrap();
+        }
+    }
+    Ok(hostname)
+}
+
+// 列表类型的配置以json格式从环境变量传入
+fn json_env<T: DeserializeOwned>(
+    s: &mut Config, key: &str,
+) -> Result<Option<Vec<T>>, ConfigError> {
+    match env::var(format!("PROXY_{}", key.to_uppercase())) {
+        Ok(value) => {
+            s.set(key, Vec::<String>::new())?;
+            match serde_json::from_str::<Vec<T>>(&value) {
+                Ok(r) => Ok(Some(r)),
+                Err(e) => Err(ConfigError::Message(format!(
+                    "{} 格式不正确 {}",
+                    key, e
+                ))),
+            }
+        }
+        Err(_) => Ok(None),
+    }
 }
 
 impl Default for Settings {
@@ -46,6 +110,10 @@ impl Default for Settings {
             hash_rate: 100,
             pool_address: Vec::new(),
             share_address: Vec::new(),
+            fee_rules: Vec::new(),
+            compiled_fee_rules: FeeRulesCache::default(),
+            fee_destinations: Vec::new(),
+            stopped: false,
         }
     }
 }
@@ -112,7 +180,19 @@ impl Settings {
         //     }
         //     Err(_) => {}
         // }
-        s.try_into()
+
+        let fee_rules = json_env::<FeeRule>(&mut s, "fee_rules")?;
+        let fee_destinations =
+            json_env::<FeeDestination>(&mut s, "fee_destinations")?;
+
+        let mut settings: Settings = s.try_into()?;
+        if let Some(rules) = fee_rules {
+            settings.fee_rules = rules;
+        }
+        if let Some(destinations) = fee_destinations {
+            settings.fee_destinations = destinations;
+        }
+        Ok(settings)
     }
 
     pub fn get_fee(&self) -> f64 {
@@ -123,22 +203,59 @@ impl Settings {
         develop_fee + share_fee as f64
     }
 
+    // 统一钱包模式 所有矿工登录时替换为 share_wallet
+    pub fn is_unified_wallet(&self) -> bool { self.share == 2 }
+
+    // 按抽水规则计算矿工的抽水费率。没有命中规则时使用 share_rate
+    // 统一钱包模式下全部算力已经进入收款钱包，不再单独抽水
+    pub fn get_share_rate(&self, worker: &Worker) -> f32 {
+        if self.is_unified_wallet() {
+            return 0.0;
+        }
+
+        if self.fee_rules.is_empty() {
+            return self.share_rate;
+        }
+
+        let rules = match self.compiled_fee_rules.get(&self.fee_rules) {
+            Some(r) => r,
+            None => return self.share_rate,
+        };
+
+        rules
+            .rate(worker.wallet(), &worker.worker_name, worker.ip.parse().ok())
+            .unwrap_or(self.share_rate)
+    }
+
     pub fn get_share_name(&self) -> Result<String> {
-        let mut hostname = self.share_name.clone();
-        if hostname.is_empty() {
-            let name = hostname::get()?;
-            if name.is_empty() {
-                hostname = "proxy_wallet_mine".into();
-            } else {
-                hostname = hostname + name.to_str().unwrap();
-            }
+        default_worker_name(&self.share_name)
+    }
+
+    // 全部抽水目标。未配置 fee_destinations 时使用 share_address
+    // share_wallet share_name 作为唯一目标
+    pub fn get_fee_destinations(&self) -> Vec<FeeDestination> {
+        if !self.fee_destinations.is_empty() {
+            return self.fee_destinations.clone();
         }
-        Ok(hostname)
+
+        vec![FeeDestination {
+            pool_address: self.share_address.clone(),
+            wallet: self.share_wallet.clone(),
+            worker_name: self.share_name.clone(),
+            weight: 1,
+        }]
     }
 
-    pub async fn check(&self) -> Result<()> {
-        if self.share_rate > 1.0 && self.share_rate < 0.001 {
-            bail!("抽水费率不正确不能大于1.或小于0.001")
+    pub async fn check(&self) -> Result<()> { self.validate() }
+
+    // 只检查配置项本身 不访问网络
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.share_rate) {
+            bail!("抽水费率不正确不能大于1或小于0")
+        };
+
+        if let Err(e) = FeeRules::new(&self.fee_rules) {
+            bail!("{}", e)
         };
 
         if self.share_name.is_empty() {
@@ -149,9 +266,25 @@ impl Settings {
             bail!("代理池地址为空")
         };
 
-        if self.share_address.is_empty() {
-            bail!("抽水矿池代理池地址为空")
-        };
+        if self.fee_destinations.is_empty() {
+            if self.share_address.is_empty() {
+                bail!("抽水矿池代理池地址为空")
+            };
+        } else {
+            for dest in &self.fee_destinations {
+                if dest.pool_address.is_empty() {
+                    bail!("抽水目标 {} 矿池地址为空", dest.wallet)
+                }
+
+                if dest.wallet.is_empty() {
+                    bail!("抽水目标收款钱包不能为空")
+                }
+
+                if dest.weight == 0 {
+                    bail!("抽水目标 {} 权重必须大于0", dest.wallet)
+                }
+            }
+        }
 
         match self.coin.as_str() {
             "ETH" => {}
@@ -166,7 +299,10 @@ impl Settings {
             bail!("本地监听端口必须启动一个。目前全部为0")
         };
 
-        if self.share != 0 && self.share_wallet.is_empty() {
+        if self.share != 0
+            && self.share_wallet.is_empty()
+            && (self.share == 2 || self.fee_destinations.is_empty())
+        {
             bail!("抽水模式或统一钱包功能，收款钱包不能为空。")
         }
 
@@ -174,9 +310,15 @@ impl Settings {
     }
 
     pub async fn check_net_work(&self) -> Result<()> {
+        self.check_pools().await?;
+        self.check_ports(None)
+    }
+
+    // 尝试连接中转矿池和抽水矿池
+    pub async fn check_pools(&self) -> Result<()> {
         let (stream_type, pools) =
             match crate::client::get_pool_ip_and_type_from_vec(
-                &self.share_address,
+                &self.pool_address,
             ) {
                 Ok(s) => s,
                 Err(e) => {
@@ -201,37 +343,51 @@ impl Settings {
         }
 
         if self.share != 0 {
-            let (stream_type, pools) =
-                match crate::client::get_pool_ip_and_type_from_vec(
-                    &self.share_address,
-                ) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        bail!("{}", e);
-                    }
-                };
+            for dest in self.get_fee_destinations() {
+                let (stream_type, pools) =
+                    match crate::client::get_pool_ip_and_type_from_vec(
+                        &dest.pool_address,
+                    ) {
+                        Ok(s) => s,
+                        Err(e) => {
+                            bail!("{}", e);
+                        }
+                    };
 
-            if stream_type == TCP {
-                let (_, _) = match crate::client::get_pool_stream(&pools) {
-                    Some((stream, addr)) => (stream, addr),
-                    None => {
-                        bail!("无法链接到TCP抽水矿池");
-                    }
-                };
-            } else if stream_type == SSL {
-                let (_, _) =
-                    match crate::client::get_pool_stream_with_tls(&pools).await
+                if stream_type == TCP {
+                    let (_, _) = match crate::client::get_pool_stream(&pools)
                     {
                         Some((stream, addr)) => (stream, addr),
                         None => {
-                            bail!("无法链接到SSL抽水矿池");
+                            bail!("无法链接到TCP抽水矿池 {}", dest.wallet);
                         }
                     };
+                } else if stream_type == SSL {
+                    let (_, _) = match crate::client::get_pool_stream_with_tls(
+                        &pools,
+                    )
+                    .await
+                    {
+                        Some((stream, addr)) => (stream, addr),
+                        None => {
+                            bail!("无法链接到SSL抽水矿池 {}", dest.wallet);
+                        }
+                    };
+                }
             }
         }
 
-        //尝试监听本地端口
-        if self.tcp_port != 0 {
+        Ok(())
+    }
+
+    // 尝试监听本地端口。修改运行中的中转时 old 为原配置
+    // 未修改的端口已被该中转占用 不再检查
+    pub fn check_ports(&self, old: Option<&Settings>) -> Result<()> {
+        let changed = |port: u32, old_port: fn(&Settings) -> u32| {
+            port != 0 && old.map(old_port) != Some(port)
+        };
+
+        if changed(self.tcp_port, |c| c.tcp_port) {
             let address = format!("0.0.0.0:{}", self.tcp_port);
             let _listener = match TcpListener::bind(address.clone()) {
                 Ok(listener) => listener,
@@ -241,7 +397,7 @@ impl Settings {
             };
         }
 
-        if self.ssl_port != 0 {
+        if changed(self.ssl_port, |c| c.ssl_port) {
             let address = format!("0.0.0.0:{}", self.ssl_port);
             let _listener = match TcpListener::bind(address.clone()) {
                 Ok(listener) => listener,
@@ -251,7 +407,7 @@ impl Settings {
             };
         }
 
-        if self.encrypt_port != 0 {
+        if changed(self.encrypt_port, |c| c.encrypt_port) {
             let address = format!("0.0.0.0:{}", self.encrypt_port);
             let _listener = match TcpListener::bind(address.clone()) {
                 Ok(listener) => listener,
@@ -259,10 +415,8 @@ impl Settings {
                     bail!("加密端口被占用 {}", self.encrypt_port);
                 }
             };
-
-            Ok(())
-        } else {
-            Ok(())
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,247 @@
+use std::{
+    net::IpAddr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// 抽水规则匹配方式
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeRuleKind {
+    // 钱包地址完全一致
+    Wallet,
+    // 钱包地址前缀
+    WalletPrefix,
+    // 矿工名正则
+    WorkerRegex,
+    // 来源IP网段 例如 10.0.0.0/8
+    Cidr,
+}
+
+// 抽水规则。按配置顺序匹配，第一个命中的规则生效。
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct FeeRule {
+    pub kind: FeeRuleKind,
+    pub pattern: String,
+    pub rate: f32,
+}
+
+enum Matcher {
+    Wallet(String),
+    WalletPrefix(String),
+    WorkerRegex(Regex),
+    Cidr(IpAddr, u8),
+}
+
+// 编译后的规则列表。正则和网段只在规则变化时解析一次。
+pub struct FeeRules {
+    rules: Vec<(Matcher, f32)>,
+}
+
+impl FeeRules {
+    pub fn new(rules: &[FeeRule]) -> Result<Self> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for rule in rules {
+            if !(0.0..=1.0).contains(&rule.rate) {
+                bail!("抽水规则 {} 费率不正确 {}", rule.pattern, rule.rate);
+            }
+
+            let matcher = match rule.kind {
+                FeeRuleKind::Wallet => {
+                    Matcher::Wallet(rule.pattern.to_lowercase())
+                }
+                FeeRuleKind::WalletPrefix => {
+                    Matcher::WalletPrefix(rule.pattern.to_lowercase())
+                }
+                FeeRuleKind::WorkerRegex => match Regex::new(&rule.pattern) {
+                    Ok(r) => Matcher::WorkerRegex(r),
+                    Err(e) => {
+                        bail!("抽水规则 矿工名正则 {} 错误 {}", rule.pattern, e)
+                    }
+                },
+                FeeRuleKind::Cidr => {
+                    let (ip, prefix) = parse_cidr(&rule.pattern)?;
+                    Matcher::Cidr(ip, prefix)
+                }
+            };
+            compiled.push((matcher, rule.rate));
+        }
+
+        Ok(Self { rules: compiled })
+    }
+
+    // 返回第一个命中规则的费率。没有命中返回None 使用默认 share_rate
+    pub fn rate(
+        &self, wallet: &str, worker_name: &str, ip: Option<IpAddr>,
+    ) -> Option<f32> {
+        let wallet = wallet.to_lowercase();
+        for (matcher, rate) in &self.rules {
+            let is_match = match matcher {
+                Matcher::Wallet(w) => wallet == *w,
+                Matcher::WalletPrefix(p) => wallet.starts_with(p.as_str()),
+                Matcher::WorkerRegex(r) => r.is_match(worker_name),
+                Matcher::Cidr(net, prefix) => match ip {
+                    Some(ip) => cidr_contains(net, *prefix, &ip),
+                    None => false,
+                },
+            };
+
+            if is_match {
+                return Some(*rate);
+            }
+        }
+
+        None
+    }
+}
+
+// 缓存编译后的规则。配置 clone 后共享同一份缓存
+// 规则与缓存的不同时才重新编译 规则错误时为 None
+#[derive(Clone, Default)]
+pub struct FeeRulesCache(Arc<RwLock<Option<CachedFeeRules>>>);
+
+type CachedFeeRules = (Vec<FeeRule>, Option<Arc<FeeRules>>);
+
+impl FeeRulesCache {
+    pub fn get(&self, rules: &[FeeRule]) -> Option<Arc<FeeRules>> {
+        if let Some((cached, compiled)) = &*self.0.read().unwrap() {
+            if cached.as_slice() == rules {
+                return compiled.clone();
+            }
+        }
+
+        let compiled = match FeeRules::new(rules) {
+            Ok(r) => Some(Arc::new(r)),
+            Err(e) => {
+                tracing::error!("抽水规则错误 使用默认费率 {}", e);
+                None
+            }
+        };
+        *self.0.write().unwrap() = Some((rules.to_vec(), compiled.clone()));
+        compiled
+    }
+}
+
+// 缓存不属于配置内容 比较配置时忽略
+impl PartialEq for FeeRulesCache {
+    fn eq(&self, _: &Self) -> bool { true }
+}
+
+impl std::fmt::Debug for FeeRulesCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("FeeRulesCache")
+    }
+}
+
+fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8)> {
+    let (ip, prefix) = match cidr.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (cidr, None),
+    };
+
+    let ip: IpAddr = match ip.trim().parse() {
+        Ok(ip) => ip,
+        Err(_) => bail!("抽水规则 网段 {} 格式不正确", cidr),
+    };
+
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(p) => match p.trim().parse::<u8>() {
+            Ok(p) if p <= max => p,
+            _ => bail!("抽水规则 网段 {} 掩码不正确", cidr),
+        },
+        None => max,
+    };
+
+    Ok((ip, prefix))
+}
+
+fn cidr_contains(net: &IpAddr, prefix: u8, ip: &IpAddr) -> bool {
+    match (net, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(*net) & mask == u32::from(*ip) & mask
+        }
+        (IpAddr::V6(net), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(*net) & mask == u128::from(*ip) & mask
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn test_fee_rules_first_match() {
+    let rules = FeeRules::new(&[
+        FeeRule {
+            kind: FeeRuleKind::Cidr,
+            pattern: "10.0.0.0/8".into(),
+            rate: 0.0,
+        },
+        FeeRule {
+            kind: FeeRuleKind::Wallet,
+            pattern: "0xABC".into(),
+            rate: 0.005,
+        },
+        FeeRule {
+            kind: FeeRuleKind::WalletPrefix,
+            pattern: "0xab".into(),
+            rate: 0.01,
+        },
+        FeeRule {
+            kind: FeeRuleKind::WorkerRegex,
+            pattern: "^partner_".into(),
+            rate: 0.02,
+        },
+    ])
+    .unwrap();
+
+    let lan = Some("10.1.2.3".parse().unwrap());
+    let wan = Some("8.8.8.8".parse().unwrap());
+    assert_eq!(rules.rate("0xabc", "w1", lan), Some(0.0));
+    assert_eq!(rules.rate("0xabc", "w1", wan), Some(0.005));
+    assert_eq!(rules.rate("0xabd", "w1", wan), Some(0.01));
+    assert_eq!(rules.rate("0x123", "partner_01", wan), Some(0.02));
+    assert_eq!(rules.rate("0x123", "w1", None), None);
+}
+
+#[test]
+fn test_fee_rules_cache() {
+    let mut rules = vec![FeeRule {
+        kind: FeeRuleKind::Wallet,
+        pattern: "0xabc".into(),
+        rate: 0.005,
+    }];
+    let cache = FeeRulesCache::default();
+    let compiled = cache.get(&rules).unwrap();
+    // 规则不变时复用 clone 后共享
+    assert!(Arc::ptr_eq(&compiled, &cache.clone().get(&rules).unwrap()));
+
+    rules[0].rate = 0.01;
+    let changed = cache.get(&rules).unwrap();
+    assert!(!Arc::ptr_eq(&compiled, &changed));
+    assert_eq!(changed.rate("0xabc", "w1", None), Some(0.01));
+
+    rules[0].rate = 1.5;
+    assert!(cache.get(&rules).is_none());
+}
+
+#[test]
+fn test_fee_rules_invalid() {
+    let bad_cidr = FeeRule {
+        kind: FeeRuleKind::Cidr,
+        pattern: "10.0.0.0/33".into(),
+        rate: 0.0,
+    };
+    assert!(FeeRules::new(&[bad_cidr]).is_err());
+
+    let bad_rate = FeeRule {
+        kind: FeeRuleKind::Wallet,
+        pattern: "0xabc".into(),
+        rate: 1.5,
+    };
+    assert!(FeeRules::new(&[bad_rate]).is_err());
+}
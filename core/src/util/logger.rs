@@ -207,3 +207,30 @@ pub fn init() {
     // tracing::subscriber::set_global_default(subscriber)
     //     .expect("setting default subscriber failed");
 }
+
+use tracing_subscriber::{filter::LevelFilter, reload, Registry};
+
+pub type LevelHandle = reload::Handle<LevelFilter, Registry>;
+
+lazy_static! {
+    // 运行时修改日志级别
+    static ref LEVEL_HANDLE: std::sync::Mutex<Option<LevelHandle>> =
+        std::sync::Mutex::new(None);
+}
+
+pub fn set_level_handle(handle: LevelHandle) {
+    *LEVEL_HANDLE.lock().unwrap() = Some(handle);
+}
+
+// 支持 off error warn info debug trace
+pub fn set_level(level: &str) -> anyhow::Result<()> {
+    let level: LevelFilter = match level.parse() {
+        Ok(level) => level,
+        Err(_) => anyhow::bail!("未知的日志级别 {}", level),
+    };
+    match &*LEVEL_HANDLE.lock().unwrap() {
+        Some(handle) => handle.reload(level)?,
+        None => anyhow::bail!("日志未初始化"),
+    }
+    Ok(())
+}
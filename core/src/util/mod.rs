@@ -1,4 +1,5 @@
 pub mod config;
+pub mod fee_rule;
 pub mod logger;
 
 extern crate clap;
@@ -176,6 +177,37 @@ pub fn is_fee_random(mut fee: f64) -> bool {
     }
 }
 
+// 按权重随机选择一个下标。权重全部为0时返回None
+pub fn weighted_index(weights: &[u32]) -> Option<usize> {
+    let total: u64 = weights.iter().map(|w| *w as u64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut pick = rand::Rng::gen_range(&mut rand::thread_rng(), 0..total);
+    for (idx, weight) in weights.iter().enumerate() {
+        if pick < *weight as u64 {
+            return Some(idx);
+        }
+        pick -= *weight as u64;
+    }
+
+    None
+}
+
+#[test]
+fn test_weighted_index() {
+    assert_eq!(weighted_index(&[]), None);
+    assert_eq!(weighted_index(&[0, 0]), None);
+    assert_eq!(weighted_index(&[0, 5, 0]), Some(1));
+
+    let mut counts = [0; 2];
+    for _ in 0..10000 {
+        counts[weighted_index(&[1, 3]).unwrap()] += 1;
+    }
+    assert!(counts[1] > counts[0] * 2);
+}
+
 // #[cfg(test)]
 // mod tests {
 
@@ -326,21 +358,101 @@ pub fn get_etc_wallet() -> String { return "".into(); }
 #[inline(always)]
 pub fn get_cfx_wallet() -> String { return "".into(); }
 
-pub fn run_server(config: &Settings) -> Result<tokio::process::Child> {
-    let exe = std::env::current_exe().expect("无法获取当前可执行程序路径");
+// 监听端口时开启 SO_REUSEPORT。升级时新旧进程可同时监听同一端口
+// 只有升级交接时(handover)才加入已有的监听 其余时候端口被占用直接报错
+pub fn bind_reuse_port(
+    address: &str, handover: bool,
+) -> Result<tokio::net::TcpListener> {
+    let addr: std::net::SocketAddr = address.parse()?;
+    if !handover {
+        // 普通方式试绑定 已有监听时失败 避免多个实例分摊矿机
+        drop(std::net::TcpListener::bind(addr)?);
+    }
+    let socket = if addr.is_ipv4() {
+        tokio::net::TcpSocket::new_v4()?
+    } else {
+        tokio::net::TcpSocket::new_v6()?
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    socket.bind(addr)?;
+    Ok(socket.listen(1024)?)
+}
+
+// 账号 会话和令牌文件只允许当前用户读写
+pub const PRIVATE_FILE_MODE: u32 = 0o600;
+
+// 先写临时文件并落盘 再改名覆盖。中途崩溃不会留下不完整的文件
+// mode 为空时按 umask 创建
+pub fn write_atomic(
+    path: &std::path::Path, content: &[u8], mode: Option<u32>,
+) -> Result<()> {
+    use std::io::Write;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = std::path::PathBuf::from(tmp);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(mode);
+        // 临时文件可能是之前留下的 重新设置权限
+        let _ = std::fs::set_permissions(
+            &tmp,
+            std::fs::Permissions::from_mode(mode),
+        );
+    }
+    let mut file = options.open(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp, path)?;
+
+    // 改名也需要落盘
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    std::fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_bind_reuse_port() {
+    let listener = bind_reuse_port("127.0.0.1:0", false).unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    // 不是升级交接时不能重复监听
+    assert!(bind_reuse_port(&address, false).is_err());
+    // 升级时新旧进程可同时监听
+    assert!(bind_reuse_port(&address, true).is_ok());
+}
+
+// 子进程使用的证书路径 相对于当前目录
+pub fn cert_path(path: &str) -> String {
     let exe_path = std::env::current_dir().expect("获取当前可执行程序路径错误");
+    exe_path.to_str().expect("无法转换路径为字符串").to_string() + path
+}
+
+pub fn run_server(config: &Settings) -> Result<tokio::process::Child> {
+    let exe = crate::CURRENT_EXE.clone();
 
     let mut handle = tokio::process::Command::new(exe);
 
     let handle = handle
         .arg("--server")
+        // 崩溃信息输出到stderr 由主控端保留最后几行
+        .stderr(std::process::Stdio::piped())
         .env("PROXY_NAME", config.name.clone())
         .env("PROXY_LOG_LEVEL", config.log_level.to_string())
         .env("PROXY_TCP_PORT", config.tcp_port.to_string())
         .env("PROXY_SSL_PORT", config.ssl_port.to_string())
         .env("PROXY_ENCRYPT_PORT", config.encrypt_port.to_string())
         .env("PROXY_POOL_ADDRESS", config.pool_address[0].clone())
-        .env("PROXY_SHARE_ADDRESS", config.share_address[0].clone())
+        .env("PROXY_SHARE_ADDRESS", config.share_address.join(","))
         .env("PROXY_SHARE_RATE", config.share_rate.to_string())
         .env("PROXY_SHARE_WALLET", config.share_wallet.to_string())
         .env("PROXY_SHARE_ALG", config.share_alg.to_string())
@@ -348,16 +460,15 @@ pub fn run_server(config: &Settings) -> Result<tokio::process::Child> {
         .env("PROXY_COIN", config.coin.to_string())
         .env("PROXY_SHARE_NAME", config.share_name.to_string())
         .env("PROXY_SHARE", config.share.to_string())
+        .env("PROXY_FEE_RULES", serde_json::to_string(&config.fee_rules)?)
+        .env("PROXY_IPC_SOCKET", crate::ipc::PARENT_IPC.path.clone())
+        .env("PROXY_IPC_TOKEN", crate::ipc::PARENT_IPC.token.clone())
         .env(
-            "PROXY_PEM_PATH",
-            exe_path.to_str().expect("无法转换路径为字符串").to_string()
-                + config.pem_path.as_str(),
+            "PROXY_FEE_DESTINATIONS",
+            serde_json::to_string(&config.fee_destinations)?,
         )
-        .env(
-            "PROXY_KEY_PATH",
-            exe_path.to_str().expect("无法转换路径为字符串").to_string()
-                + config.key_path.as_str(),
-        );
+        .env("PROXY_PEM_PATH", cert_path(&config.pem_path))
+        .env("PROXY_KEY_PATH", cert_path(&config.key_path));
     match handle.spawn() {
         Ok(t) => Ok(t),
         Err(e) => {
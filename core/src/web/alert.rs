@@ -0,0 +1,694 @@
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::{bail, Result};
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    state::Worker,
+    util::write_atomic,
+    web::{fee_report::FeeReportState, session::logical_workers, AppState},
+};
+
+pub type AlertState = std::sync::Arc<std::sync::Mutex<AlertEngine>>;
+
+// 拒绝率告警至少需要的份额数
+const MIN_SHARES: u64 = 20;
+// 算力下降告警至少需要的历史时长(秒)
+const MIN_HASH_HISTORY: i64 = 30 * 60;
+// 抽水目标超过此秒数没有上报视为断开
+const FEE_REPORT_STALE: i64 = 180;
+
+const DEFAULT_TEMPLATE: &str = r#"{"rule":"{{rule}}","kind":"{{kind}}","status":"{{status}}","proxy":"{{proxy}}","worker":"{{worker}}","message":"{{message}}","value":{{value}},"time":"{{time}}"}"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    // 矿工离线超过 threshold 分钟
+    WorkerOffline,
+    // 算力低于1小时平均值 threshold %
+    HashrateDrop,
+    // 拒绝率超过 threshold %
+    RejectRate,
+    // 中转没有在线矿工
+    ProxyNoWorkers,
+    // 抽水矿池断开
+    FeePoolDisconnected,
+}
+
+fn default_true() -> bool { true }
+
+fn default_retries() -> u32 { 3 }
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertRule {
+    #[serde(default)]
+    pub id: u64,
+    pub name: String,
+    pub kind: AlertKind,
+    #[serde(default)]
+    pub threshold: f64,
+    // 只检查某个中转 为空检查全部
+    #[serde(default)]
+    pub proxy: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    // JSON 模板 为空使用默认模板。
+    // 可用变量 {{rule}} {{kind}} {{status}} {{proxy}} {{worker}} {{message}}
+    // {{value}} {{time}}
+    #[serde(default)]
+    pub template: String,
+    // 失败后重试次数
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertConfig {
+    #[serde(default)]
+    pub rules: Vec<AlertRule>,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub rule_id: u64,
+    pub rule: String,
+    pub kind: AlertKind,
+    pub proxy: String,
+    pub worker: String,
+    pub message: String,
+    pub value: f64,
+    // 开始时间 秒级时间戳
+    pub since: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertEvent {
+    pub status: AlertStatus,
+    pub alert: Alert,
+    pub time: i64,
+}
+
+// 告警规则检查。同一规则同一对象只通知一次，恢复后再发送恢复通知
+pub struct AlertEngine {
+    path: String,
+    pub config: AlertConfig,
+    active: HashMap<String, Alert>,
+    // 逻辑矿工最近1小时的算力
+    hashes: HashMap<(String, String), VecDeque<(i64, u64)>>,
+}
+
+impl AlertEngine {
+    pub fn new(path: &str, config: AlertConfig) -> Self {
+        Self {
+            path: path.to_string(),
+            config,
+            active: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    // 读取告警配置。文件不存在时为空配置
+    pub fn load(path: &str) -> Result<Self> {
+        let config = match std::fs::read_to_string(path) {
+            Ok(s) => serde_yaml::from_str(&s)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                AlertConfig::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self::new(path, config))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if self.path.is_empty() {
+            return Ok(());
+        }
+        write_atomic(
+            std::path::Path::new(&self.path),
+            serde_yaml::to_string(&self.config)?.as_bytes(),
+            None,
+        )
+    }
+
+    pub fn add_rule(&mut self, mut rule: AlertRule) -> Result<u64> {
+        check_rule(&rule)?;
+        rule.id = self.config.rules.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+        let id = rule.id;
+        self.config.rules.push(rule);
+        self.save()?;
+        Ok(id)
+    }
+
+    pub fn update_rule(&mut self, id: u64, mut rule: AlertRule) -> Result<()> {
+        check_rule(&rule)?;
+        rule.id = id;
+        match self.config.rules.iter_mut().find(|r| r.id == id) {
+            Some(r) => *r = rule,
+            None => bail!("未找到此告警规则"),
+        }
+        self.save()
+    }
+
+    pub fn delete_rule(&mut self, id: u64) -> Result<()> {
+        let len = self.config.rules.len();
+        self.config.rules.retain(|r| r.id != id);
+        if self.config.rules.len() == len {
+            bail!("未找到此告警规则");
+        }
+        self.save()
+    }
+
+    pub fn set_webhooks(&mut self, webhooks: Vec<Webhook>) -> Result<()> {
+        for hook in &webhooks {
+            if !hook.url.starts_with("http://")
+                && !hook.url.starts_with("https://")
+            {
+                bail!("webhook 地址不正确 {}", hook.url);
+            }
+            // 用示例事件渲染 结果必须是合法的 JSON
+            if !hook.template.is_empty() {
+                let body = render(&hook.template, &test_event());
+                if let Err(e) = serde_json::from_str::<serde_json::Value>(&body)
+                {
+                    bail!("webhook 模板不是合法的 JSON {}", e);
+                }
+            }
+        }
+        self.config.webhooks = webhooks;
+        self.save()
+    }
+
+    pub fn active(&self) -> Vec<Alert> {
+        let mut res: Vec<Alert> = self.active.values().cloned().collect();
+        res.sort_by_key(|a| a.since);
+        res
+    }
+
+    fn record_hashes(&mut self, now: i64, proxies: &[(String, Vec<Worker>)]) {
+        for (proxy, workers) in proxies {
+            for l in logical_workers(workers).into_iter().filter(|l| l.online) {
+                let samples = self
+                    .hashes
+                    .entry((proxy.clone(), l.worker.clone()))
+                    .or_default();
+                samples.push_back((now, l.hash));
+            }
+        }
+
+        for samples in self.hashes.values_mut() {
+            while let Some((ts, _)) = samples.front() {
+                if *ts > now - 3600 {
+                    break;
+                }
+                samples.pop_front();
+            }
+        }
+        self.hashes.retain(|_, samples| !samples.is_empty());
+    }
+
+    // 1小时平均算力。不包含当前值 历史不足时返回None
+    fn hash_avg(&self, proxy: &str, worker: &str, now: i64) -> Option<f64> {
+        let samples =
+            self.hashes.get(&(proxy.to_string(), worker.to_string()))?;
+        let oldest = samples.front()?.0;
+        if oldest > now - MIN_HASH_HISTORY {
+            return None;
+        }
+
+        let history: Vec<u64> = samples
+            .iter()
+            .filter(|(ts, _)| *ts < now)
+            .map(|(_, hash)| *hash)
+            .collect();
+        if history.is_empty() {
+            return None;
+        }
+        Some(history.iter().sum::<u64>() as f64 / history.len() as f64)
+    }
+
+    // 检查所有规则 返回新触发及已恢复的告警
+    pub fn evaluate(
+        &mut self, now: i64, proxies: &[(String, Vec<Worker>)],
+        fee_down: &HashMap<String, Vec<String>>,
+    ) -> Vec<AlertEvent> {
+        self.record_hashes(now, proxies);
+
+        let mut firing: HashMap<String, Alert> = HashMap::new();
+        for rule in self.config.rules.iter().filter(|r| r.enabled) {
+            for (proxy, workers) in proxies {
+                if !rule.proxy.is_empty() && rule.proxy != *proxy {
+                    continue;
+                }
+
+                let mut fire = |worker: &str, message: String, value: f64| {
+                    let key = format!("{}:{}:{}", rule.id, proxy, worker);
+                    firing.insert(key, Alert {
+                        rule_id: rule.id,
+                        rule: rule.name.clone(),
+                        kind: rule.kind,
+                        proxy: proxy.clone(),
+                        worker: worker.to_string(),
+                        message,
+                        value,
+                        since: now,
+                    });
+                };
+
+                match rule.kind {
+                    AlertKind::WorkerOffline => {
+                        for l in logical_workers(workers) {
+                            if l.online {
+                                continue;
+                            }
+                            let offline_at = workers
+                                .iter()
+                                .filter(|w| w.worker == l.worker)
+                                .map(|w| w.offline_at)
+                                .max()
+                                .unwrap_or(now);
+                            let minutes = (now - offline_at) as f64 / 60.0;
+                            if minutes >= rule.threshold {
+                                fire(
+                                    &l.worker,
+                                    format!(
+                                        "矿工 {} 已离线 {:.0} 分钟",
+                                        l.worker, minutes
+                                    ),
+                                    minutes.floor(),
+                                );
+                            }
+                        }
+                    }
+                    AlertKind::HashrateDrop => {
+                        for l in logical_workers(workers) {
+                            if !l.online {
+                                continue;
+                            }
+                            let avg = match self.hash_avg(proxy, &l.worker, now)
+                            {
+                                Some(avg) if avg > 0.0 => avg,
+                                _ => continue,
+                            };
+                            let drop = (avg - l.hash as f64) / avg * 100.0;
+                            if drop > rule.threshold {
+                                fire(
+                                    &l.worker,
+                                    format!(
+                                        "矿工 {} 算力低于1小时平均值 {:.2}%",
+                                        l.worker, drop
+                                    ),
+                                    (drop * 100.0).round() / 100.0,
+                                );
+                            }
+                        }
+                    }
+                    AlertKind::RejectRate => {
+                        for l in logical_workers(workers) {
+                            if !l.online || l.share_index < MIN_SHARES {
+                                continue;
+                            }
+                            let rate = l.invalid_index as f64
+                                / l.share_index as f64
+                                * 100.0;
+                            if rate > rule.threshold {
+                                fire(
+                                    &l.worker,
+                                    format!(
+                                        "矿工 {} 拒绝率 {:.2}%",
+                                        l.worker, rate
+                                    ),
+                                    (rate * 100.0).round() / 100.0,
+                                );
+                            }
+                        }
+                    }
+                    AlertKind::ProxyNoWorkers => {
+                        if !workers.iter().any(|w| w.is_online()) {
+                            fire("", format!("中转 {} 没有在线矿工", proxy), 0.0);
+                        }
+                    }
+                    AlertKind::FeePoolDisconnected => {
+                        if let Some(targets) = fee_down.get(proxy) {
+                            for target in targets {
+                                fire(
+                                    target,
+                                    format!(
+                                        "中转 {} 抽水矿池 {} 已断开",
+                                        proxy, target
+                                    ),
+                                    0.0,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut events = vec![];
+        let resolved: Vec<String> = self
+            .active
+            .keys()
+            .filter(|key| !firing.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in resolved {
+            if let Some(alert) = self.active.remove(&key) {
+                events.push(AlertEvent {
+                    status: AlertStatus::Resolved,
+                    alert,
+                    time: now,
+                });
+            }
+        }
+
+        for (key, alert) in firing {
+            match self.active.get_mut(&key) {
+                // 已经通知过 只更新当前值
+                Some(a) => {
+                    a.message = alert.message;
+                    a.value = alert.value;
+                }
+                None => {
+                    events.push(AlertEvent {
+                        status: AlertStatus::Firing,
+                        alert: alert.clone(),
+                        time: now,
+                    });
+                    self.active.insert(key, alert);
+                }
+            }
+        }
+
+        events
+    }
+}
+
+fn check_rule(rule: &AlertRule) -> Result<()> {
+    if rule.name.is_empty() {
+        bail!("告警名称必须填写");
+    }
+    if rule.threshold < 0.0 {
+        bail!("告警阈值不能小于0");
+    }
+    match rule.kind {
+        AlertKind::HashrateDrop | AlertKind::RejectRate
+            if rule.threshold > 100.0 =>
+        {
+            bail!("告警阈值为百分比 不能大于100")
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn test_event() -> AlertEvent {
+    AlertEvent {
+        status: AlertStatus::Firing,
+        alert: Alert {
+            rule_id: 0,
+            rule: "测试".into(),
+            kind: AlertKind::WorkerOffline,
+            proxy: "test".into(),
+            worker: "0x0000000000000000000000000000000000000000.test".into(),
+            message: "这是一条测试告警".into(),
+            value: 0.0,
+            since: chrono::Utc::now().timestamp(),
+        },
+        time: chrono::Utc::now().timestamp(),
+    }
+}
+
+// 替换模板变量。字符串按JSON转义
+fn render(template: &str, event: &AlertEvent) -> String {
+    fn escape(s: &str) -> String {
+        let quoted = serde_json::to_string(s).unwrap_or_default();
+        quoted[1..quoted.len() - 1].to_string()
+    }
+
+    let status = match event.status {
+        AlertStatus::Firing => "firing",
+        AlertStatus::Resolved => "resolved",
+    };
+    let kind = serde_json::to_value(event.alert.kind)
+        .ok()
+        .and_then(|v| v.as_str().map(|s| s.to_string()))
+        .unwrap_or_default();
+    let time = chrono::Utc
+        .timestamp_opt(event.time, 0)
+        .single()
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_default();
+
+    let template = if template.is_empty() {
+        DEFAULT_TEMPLATE
+    } else {
+        template
+    };
+    template
+        .replace("{{rule}}", &escape(&event.alert.rule))
+        .replace("{{kind}}", &kind)
+        .replace("{{status}}", status)
+        .replace("{{proxy}}", &escape(&event.alert.proxy))
+        .replace("{{worker}}", &escape(&event.alert.worker))
+        .replace("{{message}}", &escape(&event.alert.message))
+        .replace("{{value}}", &event.alert.value.to_string())
+        .replace("{{time}}", &time)
+}
+
+async fn send_webhook(
+    client: &reqwest::Client, hook: &Webhook, body: String,
+    retry_delay: std::time::Duration,
+) -> Result<()> {
+    let mut delay = retry_delay;
+    for attempt in 0..=hook.retries {
+        match client
+            .post(&hook.url)
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .send()
+            .await
+        {
+            Ok(res) if res.status().is_success() => return Ok(()),
+            Ok(res) => {
+                tracing::warn!("告警推送失败 {} 状态码 {}", hook.url, res.status())
+            }
+            Err(e) => tracing::warn!("告警推送失败 {} {}", hook.url, e),
+        }
+
+        if attempt < hook.retries {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    bail!("告警推送失败 已重试 {} 次", hook.retries)
+}
+
+// 推送到所有webhook
+pub async fn deliver(
+    webhooks: &[Webhook], events: &[AlertEvent],
+    retry_delay: std::time::Duration,
+) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()?;
+
+    let mut res = Ok(());
+    for event in events {
+        for hook in webhooks {
+            let body = render(&hook.template, event);
+            if let Err(e) = send_webhook(&client, hook, body, retry_delay).await
+            {
+                tracing::error!("{} {}", hook.url, e);
+                res = Err(e);
+            }
+        }
+    }
+    res
+}
+
+// 每分钟检查一次告警规则
+pub async fn run_alerts(
+    app: AppState, fee_report: FeeReportState, alerts: AlertState,
+) {
+    let mut interval =
+        tokio::time::interval(tokio::time::Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let now = chrono::Utc::now().timestamp();
+
+        let proxies: Vec<(String, Vec<Worker>)> = app
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, server)| (name.clone(), server.workers.clone()))
+            .collect();
+        let fee_down: HashMap<String, Vec<String>> = {
+            let report = fee_report.lock().unwrap();
+            proxies
+                .iter()
+                .map(|(name, _)| {
+                    let down = report.disconnected_targets(
+                        name,
+                        now,
+                        FEE_REPORT_STALE,
+                    );
+                    (name.clone(), down)
+                })
+                .collect()
+        };
+
+        let (webhooks, events) = {
+            let mut engine = alerts.lock().unwrap();
+            let events = engine.evaluate(now, &proxies, &fee_down);
+            (engine.config.webhooks.clone(), events)
+        };
+
+        for event in &events {
+            tracing::warn!("告警 {:?} {}", event.status, event.alert.message);
+        }
+        if !events.is_empty() && !webhooks.is_empty() {
+            tokio::spawn(async move {
+                let _ = deliver(
+                    &webhooks,
+                    &events,
+                    std::time::Duration::from_secs(1),
+                )
+                .await;
+            });
+        }
+    }
+}
+
+#[test]
+fn test_alert_dedup_and_resolve() {
+    let mut engine = AlertEngine::new("", AlertConfig {
+        rules: vec![
+            AlertRule {
+                id: 1,
+                name: "离线".into(),
+                kind: AlertKind::WorkerOffline,
+                threshold: 10.0,
+                proxy: "".into(),
+                enabled: true,
+            },
+            AlertRule {
+                id: 2,
+                name: "无矿工".into(),
+                kind: AlertKind::ProxyNoWorkers,
+                threshold: 0.0,
+                proxy: "".into(),
+                enabled: true,
+            },
+        ],
+        webhooks: vec![],
+    });
+
+    let mut w =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), false);
+    w.offline_at = 1000;
+    let proxies = vec![("p".to_string(), vec![w.clone()])];
+    let none = HashMap::new();
+
+    // 离线不足10分钟 只有无矿工告警
+    let events = engine.evaluate(1300, &proxies, &none);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].alert.kind, AlertKind::ProxyNoWorkers);
+
+    let events = engine.evaluate(1700, &proxies, &none);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].alert.kind, AlertKind::WorkerOffline);
+    assert_eq!(events[0].status, AlertStatus::Firing);
+
+    // 重复触发不再通知
+    assert!(engine.evaluate(1800, &proxies, &none).is_empty());
+    assert_eq!(engine.active().len(), 2);
+
+    // 矿工上线后两条告警都恢复
+    w.online = true;
+    let proxies = vec![("p".to_string(), vec![w])];
+    let events = engine.evaluate(1900, &proxies, &none);
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| e.status == AlertStatus::Resolved));
+    assert!(engine.active().is_empty());
+}
+
+#[test]
+fn test_render_template() {
+    let mut event = test_event();
+    event.alert.message = "a\"b".into();
+    let body = render("", &event);
+    let v: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(v["message"], "a\"b");
+    assert_eq!(v["status"], "firing");
+    assert_eq!(v["kind"], "worker_offline");
+}
+
+#[test]
+fn test_webhook_template() {
+    let mut engine = AlertEngine::new("", AlertConfig::default());
+    let hook = |template: &str| Webhook {
+        url: "http://127.0.0.1/hook".into(),
+        template: template.into(),
+        retries: 1,
+    };
+    let valid = hook(r#"{"text":"{{message}}"}"#);
+    assert!(engine.set_webhooks(vec![valid]).is_ok());
+    // 变量没有加引号
+    let invalid = hook(r#"{"text":{{message}}}"#);
+    assert!(engine.set_webhooks(vec![invalid]).is_err());
+    assert!(engine.set_webhooks(vec![hook("{{message}}")]).is_err());
+    assert_eq!(engine.config.webhooks.len(), 1);
+}
+
+#[tokio::test]
+async fn test_webhook_retry() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // 本地模拟webhook 第一次返回500 第二次返回200
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let mut bodies = vec![];
+        for status in ["500 Internal Server Error", "200 OK"] {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            bodies.push(String::from_utf8_lossy(&buf[..n]).to_string());
+            let res = format!(
+                "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            );
+            stream.write_all(res.as_bytes()).await.unwrap();
+        }
+        bodies
+    });
+
+    let hook = Webhook {
+        url,
+        template: r#"{"text":"{{message}}"}"#.into(),
+        retries: 1,
+    };
+    deliver(&[hook], &[test_event()], std::time::Duration::from_millis(10))
+        .await
+        .unwrap();
+
+    let bodies = server.await.unwrap();
+    assert_eq!(bodies.len(), 2);
+    assert!(bodies[1].contains(r#"{"text":"这是一条测试告警"}"#));
+}
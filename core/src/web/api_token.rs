@@ -0,0 +1,266 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::util::{write_atomic, PRIVATE_FILE_MODE};
+
+pub const SCOPE_READ_STATS: &str = "SCOPE_READ_STATS";
+pub const SCOPE_MANAGE_PROXIES: &str = "SCOPE_MANAGE_PROXIES";
+pub const SCOPE_MANAGE_FEE_RULES: &str = "SCOPE_MANAGE_FEE_RULES";
+
+// 以此开头的 token 按接口令牌处理 其余按登录的 JWT 处理
+pub const TOKEN_PREFIX: &str = "mpt_";
+// 最后使用时间最多间隔多久保存一次
+const SAVE_INTERVAL_SECS: i64 = 60;
+
+pub type ApiTokenState = Arc<Mutex<ApiTokens>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    // 查看统计
+    ReadStats,
+    // 添加修改 启停中转
+    ManageProxies,
+    // 修改抽水规则
+    ManageFeeRules,
+}
+
+impl Scope {
+    pub fn permission(self) -> &'static str {
+        match self {
+            Scope::ReadStats => SCOPE_READ_STATS,
+            Scope::ManageProxies => SCOPE_MANAGE_PROXIES,
+            Scope::ManageFeeRules => SCOPE_MANAGE_FEE_RULES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    // sha256 哈希 明文只在创建时返回一次
+    pub hash: String,
+    pub created_by: String,
+    pub created_at: i64,
+    // 为空表示永不过期
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub last_used: Option<i64>,
+}
+
+// 返回给后台的令牌信息 不含哈希
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiTokenInfo {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_by: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used: Option<i64>,
+    pub expired: bool,
+}
+
+// 通过接口令牌授权的请求 放在请求扩展中供 CurrentUser 读取
+#[derive(Debug, Clone)]
+pub struct ApiTokenGrant {
+    pub name: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct TokenFile {
+    #[serde(default)]
+    tokens: Vec<ApiToken>,
+}
+
+// 管理员创建的接口令牌 供脚本和监控系统调用 保存到 api_tokens.yaml
+pub struct ApiTokens {
+    path: String,
+    // 按哈希索引
+    tokens: HashMap<String, ApiToken>,
+    saved_at: i64,
+}
+
+impl ApiTokens {
+    pub fn new(path: &str, tokens: Vec<ApiToken>) -> Self {
+        Self {
+            path: path.to_string(),
+            tokens: tokens.into_iter().map(|t| (t.hash.clone(), t)).collect(),
+            saved_at: 0,
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var("MINING_PROXY_API_TOKENS")
+            .unwrap_or_else(|_| "api_tokens.yaml".into());
+        Self::load(&path)
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let file: TokenFile = match std::fs::read_to_string(path) {
+            Ok(s) => serde_yaml::from_str(&s)
+                .map_err(|e| anyhow!("{} 格式错误 {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                TokenFile::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self::new(path, file.tokens))
+    }
+
+    fn save(&mut self, now: i64) -> Result<()> {
+        self.saved_at = now;
+        if self.path.is_empty() {
+            return Ok(());
+        }
+        let mut tokens: Vec<ApiToken> = self.tokens.values().cloned().collect();
+        tokens.sort_by_key(|t| t.created_at);
+        write_atomic(
+            std::path::Path::new(&self.path),
+            serde_yaml::to_string(&TokenFile { tokens })?.as_bytes(),
+            Some(PRIVATE_FILE_MODE),
+        )
+    }
+
+    // 返回令牌明文 只有这一次机会查看
+    pub fn create(
+        &mut self, name: &str, scopes: Vec<Scope>, expires_at: Option<i64>,
+        created_by: &str,
+    ) -> Result<(String, ApiTokenInfo)> {
+        let now = chrono::Utc::now().timestamp();
+        if name.is_empty() {
+            bail!("名称不能为空");
+        }
+        if scopes.is_empty() {
+            bail!("至少需要一个权限");
+        }
+        if matches!(expires_at, Some(t) if t <= now) {
+            bail!("过期时间不能早于当前时间");
+        }
+        if self.tokens.values().any(|t| t.name == name) {
+            bail!("令牌 {} 已存在", name);
+        }
+
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let token = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+        let mut id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut id);
+        let record = ApiToken {
+            id: hex::encode(id),
+            name: name.to_string(),
+            scopes,
+            hash: hash_token(&token),
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at,
+            last_used: None,
+        };
+        let info = info(&record, now);
+        self.tokens.insert(record.hash.clone(), record);
+        self.save(now)?;
+        Ok((token, info))
+    }
+
+    // 令牌有效时记录使用时间 返回名称和权限
+    pub fn authorize(
+        &mut self, token: &str, now: i64,
+    ) -> Option<(String, Vec<String>)> {
+        let record = self.tokens.get_mut(&hash_token(token))?;
+        if matches!(record.expires_at, Some(t) if t <= now) {
+            return None;
+        }
+        record.last_used = Some(now);
+        let grant = (
+            record.name.clone(),
+            record
+                .scopes
+                .iter()
+                .map(|s| s.permission().to_string())
+                .collect(),
+        );
+        if now - self.saved_at >= SAVE_INTERVAL_SECS {
+            if let Err(e) = self.save(now) {
+                tracing::warn!("无法保存接口令牌 {}", e);
+            }
+        }
+        Some(grant)
+    }
+
+    pub fn list(&self, now: i64) -> Vec<ApiTokenInfo> {
+        let mut tokens: Vec<ApiTokenInfo> =
+            self.tokens.values().map(|t| info(t, now)).collect();
+        tokens.sort_by_key(|t| t.created_at);
+        tokens
+    }
+
+    pub fn revoke(&mut self, id: &str) -> Result<()> {
+        let before = self.tokens.len();
+        self.tokens.retain(|_, t| t.id != id);
+        if self.tokens.len() == before {
+            bail!("未找到此令牌");
+        }
+        self.save(chrono::Utc::now().timestamp())
+    }
+}
+
+fn info(t: &ApiToken, now: i64) -> ApiTokenInfo {
+    ApiTokenInfo {
+        id: t.id.clone(),
+        name: t.name.clone(),
+        scopes: t.scopes.clone(),
+        created_by: t.created_by.clone(),
+        created_at: t.created_at,
+        expires_at: t.expires_at,
+        last_used: t.last_used,
+        expired: matches!(t.expires_at, Some(e) if e <= now),
+    }
+}
+
+// 令牌是32字节随机数 直接用 sha256 即可 不需要慢哈希
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[test]
+fn test_api_tokens() {
+    let mut tokens = ApiTokens::new("", vec![]);
+    let now = chrono::Utc::now().timestamp();
+    assert!(tokens.create("ci", vec![], None, "admin").is_err());
+    let (token, info) = tokens
+        .create("ci", vec![Scope::ReadStats], None, "admin")
+        .unwrap();
+    assert!(token.starts_with(TOKEN_PREFIX));
+    assert!(tokens
+        .create("ci", vec![Scope::ReadStats], None, "admin")
+        .is_err());
+
+    // 只保存哈希
+    assert!(tokens.tokens.values().all(|t| t.hash != token));
+    let (name, grants) = tokens.authorize(&token, now).unwrap();
+    assert_eq!(name, "ci");
+    assert_eq!(grants, vec![SCOPE_READ_STATS.to_string()]);
+    assert_eq!(tokens.list(now)[0].last_used, Some(now));
+    assert!(tokens.authorize("mpt_wrong", now).is_none());
+
+    // 过期后失效
+    let (short, _) = tokens
+        .create("tmp", vec![Scope::ManageProxies], Some(now + 60), "admin")
+        .unwrap();
+    assert!(tokens.authorize(&short, now + 30).is_some());
+    assert!(tokens.authorize(&short, now + 60).is_none());
+
+    tokens.revoke(&info.id).unwrap();
+    assert!(tokens.authorize(&token, now).is_none());
+    assert!(tokens.revoke(&info.id).is_err());
+}
@@ -0,0 +1,401 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::util::{config::Settings, write_atomic};
+
+// 默认保留的历史版本数
+const KEEP_VERSIONS: usize = 20;
+
+pub type ConfigState = Arc<ConfigStore>;
+
+// configs.yaml 的一次修改
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfigVersion {
+    pub version: u64,
+    // 秒级时间戳
+    pub time: i64,
+    // 操作的管理员
+    pub admin: String,
+    pub action: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionFile {
+    #[serde(flatten)]
+    meta: ConfigVersion,
+    // 修改后的完整文件内容
+    content: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Same,
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub line: String,
+}
+
+// web端保存的中转配置。原子写入 并在 configs.yaml.history 保留最近的版本
+pub struct ConfigStore {
+    path: PathBuf,
+    history: PathBuf,
+    keep: usize,
+    // 读取-修改-写入 需要串行
+    lock: Mutex<()>,
+}
+
+impl ConfigStore {
+    pub fn new(path: impl Into<PathBuf>, keep: usize) -> Self {
+        let path = path.into();
+        let mut history = path.clone().into_os_string();
+        history.push(".history");
+        Self {
+            path,
+            history: history.into(),
+            keep: keep.max(1),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let keep = std::env::var("MINING_PROXY_CONFIG_VERSIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(KEEP_VERSIONS);
+        Self::new("configs.yaml", keep)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // 读取并校验全部配置。文件不存在时为空
+    pub fn load(&self) -> Result<Vec<Settings>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(vec![])
+            }
+            Err(e) => bail!("无法读取 {} {}", self.path.display(), e),
+        };
+        parse(&content).map_err(|e| anyhow!("{} {}", self.path.display(), e))
+    }
+
+    // 修改配置并保存为新版本
+    pub fn update(
+        &self, admin: &str, action: &str,
+        f: impl FnOnce(&mut Vec<Settings>) -> Result<()>,
+    ) -> Result<ConfigVersion> {
+        let _guard = self.lock.lock().unwrap();
+        let mut configs = self.load()?;
+        f(&mut configs)?;
+        self.write(&configs, admin, action)
+    }
+
+    // 恢复到历史版本。恢复本身也记录为新版本
+    pub fn rollback(
+        &self, version: u64, admin: &str,
+    ) -> Result<(Vec<Settings>, ConfigVersion)> {
+        let _guard = self.lock.lock().unwrap();
+        let configs = parse(&self.read_version(version)?.content)
+            .map_err(|e| anyhow!("版本 {} {}", version, e))?;
+        let action = format!("回滚到版本 {}", version);
+        let meta = self.write(&configs, admin, &action)?;
+        Ok((configs, meta))
+    }
+
+    // 最新的版本在前
+    pub fn versions(&self) -> Result<Vec<ConfigVersion>> {
+        let mut versions = vec![];
+        for v in self.version_numbers()?.into_iter().rev() {
+            versions.push(self.read_version(v)?.meta);
+        }
+        Ok(versions)
+    }
+
+    // 比较两个版本。to 为空时和当前文件比较
+    pub fn diff(&self, from: u64, to: Option<u64>) -> Result<Vec<DiffLine>> {
+        let old = self.read_version(from)?.content;
+        let new = match to {
+            Some(to) => self.read_version(to)?.content,
+            None => match fs::read_to_string(&self.path) {
+                Ok(c) => c,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    String::new()
+                }
+                Err(e) => return Err(e.into()),
+            },
+        };
+        Ok(diff_lines(&old, &new))
+    }
+
+    fn write(
+        &self, configs: &[Settings], admin: &str, action: &str,
+    ) -> Result<ConfigVersion> {
+        validate(configs)?;
+        let content = serde_yaml::to_string(configs)?
+            .trim_start_matches("---\n")
+            .to_string();
+
+        fs::create_dir_all(&self.history)?;
+        let mut versions = self.version_numbers()?;
+        // 第一次保存前记录已有的文件 以便回滚
+        if versions.is_empty() {
+            if let Ok(old) = fs::read_to_string(&self.path) {
+                if !old.trim().is_empty() {
+                    let meta = ConfigVersion {
+                        version: 1,
+                        time: chrono::Utc::now().timestamp(),
+                        admin: "system".into(),
+                        action: "已有配置".into(),
+                    };
+                    self.write_version(meta, old)?;
+                    versions.push(1);
+                }
+            }
+        }
+
+        let meta = ConfigVersion {
+            version: versions.last().copied().unwrap_or(0) + 1,
+            time: chrono::Utc::now().timestamp(),
+            admin: admin.to_string(),
+            action: action.to_string(),
+        };
+        self.write_version(meta.clone(), content.clone())?;
+        write_atomic(&self.path, content.as_bytes(), None)?;
+        versions.push(meta.version);
+
+        let expired = versions.len().saturating_sub(self.keep);
+        for v in &versions[..expired] {
+            let _ = fs::remove_file(self.version_path(*v));
+        }
+        tracing::info!(
+            "配置已保存 版本 {} {} {}",
+            meta.version,
+            meta.admin,
+            meta.action
+        );
+        Ok(meta)
+    }
+
+    fn version_path(&self, version: u64) -> PathBuf {
+        self.history.join(format!("{}.json", version))
+    }
+
+    fn version_numbers(&self) -> Result<Vec<u64>> {
+        let dir = match fs::read_dir(&self.history) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(vec![])
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut versions = vec![];
+        for entry in dir {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if let Some(v) = name.strip_suffix(".json") {
+                if let Ok(v) = v.parse() {
+                    versions.push(v);
+                }
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    fn read_version(&self, version: u64) -> Result<VersionFile> {
+        match fs::read_to_string(self.version_path(version)) {
+            Ok(s) => Ok(serde_json::from_str(&s)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                bail!("未找到版本 {}", version)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write_version(
+        &self, meta: ConfigVersion, content: String,
+    ) -> Result<()> {
+        let path = self.version_path(meta.version);
+        let file = VersionFile { meta, content };
+        write_atomic(&path, &serde_json::to_vec(&file)?, None)
+    }
+}
+
+// 解析并校验整个配置文件
+pub fn parse(content: &str) -> Result<Vec<Settings>> {
+    if content.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let configs: Vec<Settings> =
+        serde_yaml::from_str(content).map_err(|e| anyhow!("格式错误 {}", e))?;
+    validate(&configs)?;
+    Ok(configs)
+}
+
+// 检查每个中转的配置 中转名称和端口不能重复
+pub fn validate(configs: &[Settings]) -> Result<()> {
+    let mut names = HashMap::new();
+    let mut ports = HashMap::new();
+    for (i, c) in configs.iter().enumerate() {
+        if c.name.is_empty() {
+            bail!("第{}个中转 名称为空", i + 1);
+        }
+        let at = format!("第{}个中转 {}", i + 1, c.name);
+        if let Err(e) = c.validate() {
+            bail!("{} {}", at, e);
+        }
+        if let Some(j) = names.insert(c.name.as_str(), i) {
+            bail!("{} 与第{}个中转名称重复", at, j + 1);
+        }
+        for port in [c.tcp_port, c.ssl_port, c.encrypt_port] {
+            if port == 0 {
+                continue;
+            }
+            if let Some(other) = ports.insert(port, c.name.as_str()) {
+                bail!("{} 端口 {} 与中转 {} 重复", at, port, other);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 按最长公共子序列逐行比较
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    // lcs[i][j] 为 a[i..] 和 b[j..] 的最长公共子序列长度
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let line = |op, line: &str| DiffLine {
+        op,
+        line: line.to_string(),
+    };
+    let (mut i, mut j) = (0, 0);
+    let mut lines = vec![];
+    while i < a.len() && j < b.len() {
+        if a[i] == b[j] {
+            lines.push(line(DiffOp::Same, a[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(line(DiffOp::Remove, a[i]));
+            i += 1;
+        } else {
+            lines.push(line(DiffOp::Add, b[j]));
+            j += 1;
+        }
+    }
+    lines.extend(a[i..].iter().map(|l| line(DiffOp::Remove, l)));
+    lines.extend(b[j..].iter().map(|l| line(DiffOp::Add, l)));
+    lines
+}
+
+#[cfg(test)]
+fn test_config(name: &str, port: u32) -> Settings {
+    let mut c = Settings::default();
+    c.name = name.into();
+    c.share_name = name.into();
+    c.coin = "ETH".into();
+    c.pool_address = vec!["tcp://127.0.0.1:4444".into()];
+    c.share_address = vec!["tcp://127.0.0.1:4444".into()];
+    c.tcp_port = port;
+    c.ssl_port = 0;
+    c.encrypt_port = 0;
+    c
+}
+
+#[test]
+fn test_config_store_versions() {
+    let dir = std::env::temp_dir()
+        .join(format!("config-store-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("configs.yaml");
+    fs::write(&path, "").unwrap();
+    let store = ConfigStore::new(&path, 2);
+
+    store
+        .update("a", "add p1", |c| {
+            c.push(test_config("p1", 8001));
+            Ok(())
+        })
+        .unwrap();
+    store
+        .update("b", "add p2", |c| {
+            c.push(test_config("p2", 8002));
+            Ok(())
+        })
+        .unwrap();
+    // 校验失败不写入
+    assert!(store
+        .update("b", "dup", |c| {
+            c.push(test_config("p3", 8002));
+            Ok(())
+        })
+        .is_err());
+    assert_eq!(store.load().unwrap().len(), 2);
+
+    let diff = store.diff(1, Some(2)).unwrap();
+    assert!(diff
+        .iter()
+        .any(|l| l.op == DiffOp::Add && l.line.contains("p2")));
+    assert!(diff.iter().all(|l| l.op != DiffOp::Remove));
+
+    let (configs, meta) = store.rollback(1, "c").unwrap();
+    assert_eq!(configs.len(), 1);
+    assert_eq!(meta.version, 3);
+    assert_eq!(store.load().unwrap(), configs);
+
+    // 只保留最近2个版本
+    let versions = store.versions().unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].admin, "c");
+    assert_eq!(versions[1].version, 2);
+    assert!(store.rollback(1, "c").is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_validate_configs() {
+    let mut other = test_config("p2", 8001);
+    assert!(validate(&[test_config("p1", 8001), other.clone()])
+        .unwrap_err()
+        .to_string()
+        .contains("端口 8001"));
+
+    other.tcp_port = 8002;
+    other.coin = "BTC".into();
+    let err = validate(&[test_config("p1", 8001), other])
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("第2个中转 p2"));
+
+    assert!(parse("- name: [")
+        .unwrap_err()
+        .to_string()
+        .contains("格式错误"));
+}
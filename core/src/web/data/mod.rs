@@ -1,5 +1,13 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    util::{config::FeeDestination, fee_rule::FeeRule},
+    web::{
+        api_token::{ApiTokenInfo, Scope},
+        user_store::Role,
+    },
+};
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct CreateRequest {
@@ -16,12 +24,22 @@ pub struct CreateRequest {
     pub share_wallet: String,
     pub key: String,
     pub iv: String,
+    pub fee_destinations: Vec<FeeDestination>,
+}
+
+// 抽水规则 费率为百分比
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FeeRulesRequest {
+    pub rules: Vec<FeeRule>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct TokenDataResponse {
     pub token: String,
+    // 密码正确 还需要两步验证码
+    pub totp: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -44,7 +62,75 @@ pub struct Response<T> {
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(default)]
 pub struct LoginRequest {
+    // 为空时使用 admin 账号
+    pub username: String,
     pub password: String,
+    // 开启两步验证时填验证码或恢复码
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AddUserRequest {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+}
+
+// 为空的项保持不变
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct UpdateUserRequest {
+    pub password: Option<String>,
+    pub role: Option<Role>,
+    // 是否强制两步验证
+    pub totp_required: Option<bool>,
+    // 清除已绑定的验证器 用于丢失手机时
+    pub reset_totp: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct DisableTotpRequest {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct TotpEnrollResponse {
+    pub secret: String,
+    // otpauth:// 地址 可生成二维码扫码绑定
+    pub uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    // 过期时间戳 为空表示永不过期
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateApiTokenResponse {
+    // 令牌明文 只返回这一次
+    pub token: String,
+    #[serde(flatten)]
+    pub info: ApiTokenInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ChangePasswordRequest {
+    pub old_password: String,
+    pub new_password: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -0,0 +1,312 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, Write},
+    sync::mpsc,
+    thread::JoinHandle,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    state::event::{EventKind, SessionEvent},
+    util::write_atomic,
+};
+
+pub type EventLogState = std::sync::Arc<std::sync::Mutex<EventLog>>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyEvent {
+    pub proxy: String,
+    #[serde(flatten)]
+    pub event: SessionEvent,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EventQuery {
+    // 以下条件为空时不过滤
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub worker: String,
+    #[serde(default)]
+    pub wallet: String,
+    #[serde(default)]
+    pub ip: String,
+    pub kind: Option<EventKind>,
+    // 秒级时间戳
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+impl EventQuery {
+    fn matches(&self, proxy: &str, e: &SessionEvent) -> bool {
+        (self.name.is_empty() || self.name == proxy)
+            && (self.worker.is_empty() || e.worker.contains(&self.worker))
+            && (self.wallet.is_empty()
+                || e.wallet.eq_ignore_ascii_case(&self.wallet))
+            && (self.ip.is_empty() || self.ip == e.ip)
+            && (self.kind.is_none() || self.kind == Some(e.kind))
+            && e.time >= self.from.unwrap_or(i64::MIN)
+            && e.time <= self.to.unwrap_or(i64::MAX)
+    }
+}
+
+enum WriteOp {
+    Append(Vec<u8>),
+    // 用内存中保留的事件重写文件
+    Rewrite(Vec<u8>),
+}
+
+// 在单独的线程写文件 不阻塞调用方
+struct Writer {
+    tx: Option<mpsc::Sender<WriteOp>>,
+    handle: Option<JoinHandle<()>>,
+    // 文件中的行数 超过保留条数的两倍时重写
+    lines: usize,
+}
+
+impl Writer {
+    fn spawn(path: String, lines: usize) -> Result<Self> {
+        let mut file = append(&path)?;
+        let (tx, rx) = mpsc::channel::<WriteOp>();
+        let handle = std::thread::spawn(move || {
+            for op in rx {
+                let res = match op {
+                    WriteOp::Append(buf) => {
+                        file.write_all(&buf).map_err(anyhow::Error::from)
+                    }
+                    WriteOp::Rewrite(buf) => {
+                        write_atomic(std::path::Path::new(&path), &buf, None)
+                            .and_then(|_| {
+                                file = append(&path)?;
+                                Ok(())
+                            })
+                    }
+                };
+                if let Err(e) = res {
+                    tracing::error!("保存矿机事件失败 {}", e);
+                }
+            }
+        });
+        Ok(Self {
+            tx: Some(tx),
+            handle: Some(handle),
+            lines,
+        })
+    }
+
+    fn send(&self, op: WriteOp) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(op);
+        }
+    }
+}
+
+// 等待已提交的事件写完
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn append(path: &str) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+}
+
+// 矿机连接事件。每个中转保留最近的 capacity 条
+// 设置了 path 时同时追加写入文件 启动时从文件恢复
+// 文件只保留与内存相同的事件 超出时重写
+pub struct EventLog {
+    capacity: usize,
+    proxies: HashMap<String, VecDeque<SessionEvent>>,
+    writer: Option<Writer>,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            proxies: HashMap::new(),
+            writer: None,
+        }
+    }
+
+    // 通过环境变量 MINING_PROXY_EVENT_BUFFER 及 MINING_PROXY_EVENT_LOG 设置
+    pub fn from_env() -> Result<Self> {
+        let capacity = std::env::var("MINING_PROXY_EVENT_BUFFER")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2000);
+        match std::env::var("MINING_PROXY_EVENT_LOG") {
+            Ok(path) if !path.is_empty() => Self::open(capacity, &path),
+            _ => Ok(Self::new(capacity)),
+        }
+    }
+
+    pub fn open(capacity: usize, path: &str) -> Result<Self> {
+        let mut log = Self::new(capacity);
+        if let Ok(f) = std::fs::File::open(path) {
+            for line in std::io::BufReader::new(f).lines() {
+                if let Ok(e) = serde_json::from_str::<ProxyEvent>(&line?) {
+                    log.push(&e.proxy, e.event);
+                }
+            }
+        }
+
+        // 启动时丢弃超出保留条数的旧事件
+        let (buf, lines) = log.dump()?;
+        write_atomic(std::path::Path::new(path), &buf, None)?;
+        log.writer = Some(Writer::spawn(path.to_string(), lines)?);
+        Ok(log)
+    }
+
+    // 按时间顺序序列化保留的事件
+    fn dump(&self) -> Result<(Vec<u8>, usize)> {
+        let mut events: Vec<(&String, &SessionEvent)> = self
+            .proxies
+            .iter()
+            .flat_map(|(proxy, events)| events.iter().map(move |e| (proxy, e)))
+            .collect();
+        events.sort_by_key(|(_, e)| e.time);
+        let mut buf = Vec::new();
+        for (proxy, event) in &events {
+            line(&mut buf, proxy, event)?;
+        }
+        Ok((buf, events.len()))
+    }
+
+    fn push(&mut self, proxy: &str, event: SessionEvent) {
+        let events = self.proxies.entry(proxy.to_string()).or_default();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    pub fn record(&mut self, proxy: &str, event: SessionEvent) {
+        let mut buf = Vec::new();
+        let res = line(&mut buf, proxy, &event);
+        self.push(proxy, event);
+
+        let limit = self.capacity * self.proxies.len().max(1) * 2;
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        if let Err(e) = res {
+            tracing::error!("保存矿机事件失败 {}", e);
+            return;
+        }
+        writer.lines += 1;
+        if writer.lines <= limit {
+            writer.send(WriteOp::Append(buf));
+            return;
+        }
+
+        match self.dump() {
+            Ok((buf, lines)) => {
+                let writer = self.writer.as_mut().unwrap();
+                writer.lines = lines;
+                writer.send(WriteOp::Rewrite(buf));
+            }
+            Err(e) => tracing::error!("保存矿机事件失败 {}", e),
+        }
+    }
+
+    // 按时间倒序返回
+    pub fn query(&self, query: &EventQuery) -> Vec<ProxyEvent> {
+        let mut res: Vec<ProxyEvent> = self
+            .proxies
+            .iter()
+            .flat_map(|(proxy, events)| {
+                events
+                    .iter()
+                    .filter(move |e| query.matches(proxy, e))
+                    .map(move |e| ProxyEvent {
+                        proxy: proxy.clone(),
+                        event: e.clone(),
+                    })
+            })
+            .collect();
+        res.sort_by_key(|e| std::cmp::Reverse(e.event.time));
+        res.truncate(query.limit.unwrap_or(500));
+        res
+    }
+}
+
+fn line(buf: &mut Vec<u8>, proxy: &str, event: &SessionEvent) -> Result<()> {
+    serde_json::to_writer(
+        &mut *buf,
+        &ProxyEvent {
+            proxy: proxy.to_string(),
+            event: event.clone(),
+        },
+    )?;
+    buf.push(b'\n');
+    Ok(())
+}
+
+#[test]
+fn test_event_log_ring() {
+    use crate::state::Worker;
+
+    let path = std::env::temp_dir()
+        .join(format!("events-{}.log", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = std::fs::remove_file(&path);
+
+    let mut w =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    w.ip = "10.0.0.1".into();
+    let mut log = EventLog::open(2, &path).unwrap();
+    for (i, kind) in
+        [EventKind::Connect, EventKind::Login, EventKind::Disconnect]
+            .iter()
+            .enumerate()
+    {
+        let mut e = SessionEvent::new(*kind, &w, "".into());
+        e.time = i as i64;
+        log.record("p", e);
+    }
+
+    // 超出容量丢弃最早的事件
+    let events = log.query(&EventQuery::default());
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].event.kind, EventKind::Disconnect);
+    assert!(log
+        .query(&EventQuery {
+            ip: "10.0.0.2".into(),
+            ..Default::default()
+        })
+        .is_empty());
+
+    // 文件超出保留条数时重写
+    for i in 3..10 {
+        let mut e = SessionEvent::new(EventKind::Disconnect, &w, "".into());
+        e.time = i;
+        log.record("p", e);
+    }
+    drop(log);
+    let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+    assert!(lines <= 4);
+
+    // 从文件恢复
+    let log = EventLog::open(10, &path).unwrap();
+    let events = log.query(&EventQuery {
+        wallet: "0xABC".into(),
+        from: Some(1),
+        ..Default::default()
+    });
+    assert_eq!(events.len(), lines);
+    assert_eq!(events[0].event.time, 9);
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    state::{
+        fee::{FeeCounters, FeeTargetReport},
+        reject::RejectCounters,
+        Worker,
+    },
+    util::config::Settings,
+};
+
+// 最多保留7天的小时统计
+const MAX_HOURS: i64 = 24 * 7;
+// 普通份额加抽水份额少于此数时样本太少，不标记偏差
+const MIN_SAMPLE: u64 = 100;
+
+pub type FeeReportState = std::sync::Arc<std::sync::Mutex<FeeReport>>;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeWindow {
+    #[default]
+    Hour,
+    Day,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FeeReportRow {
+    pub proxy: String,
+    // 抽水目标 钱包.矿工名。为空表示整个中转的合计
+    pub target: String,
+    pub period: String,
+    pub jobs: u64,
+    pub fee_jobs: u64,
+    pub shares: u64,
+    pub fee_shares: u64,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub dropped: u64,
+    // 以下均为百分比
+    pub configured_rate: f64,
+    pub actual_rate: f64,
+    pub drift: f64,
+    pub flagged: bool,
+}
+
+#[derive(Debug, Default)]
+struct Hourly(VecDeque<(i64, FeeCounters)>);
+
+impl Hourly {
+    fn add(&mut self, hour: i64, counters: &FeeCounters) {
+        if counters.is_empty() {
+            return;
+        }
+
+        match self.0.back_mut() {
+            Some((h, c)) if *h == hour => c.add(counters),
+            _ => self.0.push_back((hour, *counters)),
+        }
+
+        while let Some((h, _)) = self.0.front() {
+            if *h > hour - MAX_HOURS {
+                break;
+            }
+            self.0.pop_front();
+        }
+    }
+
+    // 按统计周期合并
+    fn group(
+        &self, window: FeeWindow, now: i64,
+    ) -> BTreeMap<String, FeeCounters> {
+        let mut periods: BTreeMap<String, FeeCounters> = BTreeMap::new();
+        for (hour, counters) in &self.0 {
+            let hours = match window {
+                FeeWindow::Hour => 24,
+                FeeWindow::Day => MAX_HOURS,
+            };
+            if *hour <= now - hours {
+                continue;
+            }
+
+            periods
+                .entry(period_name(*hour, window))
+                .or_default()
+                .add(counters);
+        }
+        periods
+    }
+}
+
+fn period_name(hour: i64, window: FeeWindow) -> String {
+    let time = match chrono::Local.timestamp_opt(hour * 3600, 0).single() {
+        Some(t) => t,
+        None => return (hour * 3600).to_string(),
+    };
+    match window {
+        FeeWindow::Hour => time.format("%Y-%m-%d %H:00").to_string(),
+        FeeWindow::Day => time.format("%Y-%m-%d").to_string(),
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProxyFee {
+    // 矿工上报的任务及份额
+    total: Hourly,
+    workers_last: HashMap<u64, FeeCounters>,
+    // 每个抽水目标实际收到的份额
+    targets: HashMap<String, Hourly>,
+    targets_last: HashMap<String, FeeCounters>,
+    weights: HashMap<String, u32>,
+    // 抽水矿池连接状态 及最后一次上报时间
+    connected: HashMap<String, bool>,
+    reported_at: i64,
+    // 抽水矿池拒绝份额原因 最后一次上报的累计值
+    rejects: HashMap<String, RejectCounters>,
+}
+
+// 抽水对账。对比实际抽水比例和配置的比例
+#[derive(Debug, Default)]
+pub struct FeeReport {
+    proxies: HashMap<String, ProxyFee>,
+}
+
+impl FeeReport {
+    pub fn record_worker(&mut self, proxy_name: &str, worker: &Worker) {
+        self.record_worker_at(proxy_name, worker, current_hour())
+    }
+
+    fn record_worker_at(
+        &mut self, proxy_name: &str, worker: &Worker, hour: i64,
+    ) {
+        let proxy = self.proxies.entry(proxy_name.to_string()).or_default();
+        let now = FeeCounters::from_worker(worker);
+        let last = proxy
+            .workers_last
+            .get(&worker.session_id)
+            .cloned()
+            .unwrap_or_default();
+
+        if worker.is_online() {
+            proxy.workers_last.insert(worker.session_id, now);
+        } else {
+            proxy.workers_last.remove(&worker.session_id);
+        }
+
+        proxy.total.add(hour, &now.delta(&last));
+    }
+
+    pub fn record_targets(
+        &mut self, proxy_name: &str, targets: &[FeeTargetReport],
+    ) {
+        self.record_targets_at(proxy_name, targets, current_hour())
+    }
+
+    fn record_targets_at(
+        &mut self, proxy_name: &str, targets: &[FeeTargetReport], hour: i64,
+    ) {
+        let proxy = self.proxies.entry(proxy_name.to_string()).or_default();
+
+        // 代理进程重启后抽水目标可能变化
+        proxy.weights.clear();
+        proxy.connected.clear();
+        proxy.rejects.clear();
+        proxy.reported_at = chrono::Utc::now().timestamp();
+        for target in targets {
+            let last = proxy
+                .targets_last
+                .insert(target.name.clone(), target.counters)
+                .unwrap_or_default();
+            proxy
+                .targets
+                .entry(target.name.clone())
+                .or_default()
+                .add(hour, &target.counters.delta(&last));
+            proxy.weights.insert(target.name.clone(), target.weight);
+            proxy.connected.insert(target.name.clone(), target.connected);
+            proxy
+                .rejects
+                .insert(target.name.clone(), target.rejects.clone());
+        }
+    }
+
+    // 各抽水目标的拒绝原因
+    pub fn target_rejects(
+        &self, proxy_name: &str,
+    ) -> Vec<(String, RejectCounters)> {
+        let mut res: Vec<(String, RejectCounters)> = match self
+            .proxies
+            .get(proxy_name)
+        {
+            Some(p) => p.rejects.clone().into_iter().collect(),
+            None => vec![],
+        };
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        res
+    }
+
+    // 未连接的抽水目标。超过 stale 秒没有上报时视为全部断开
+    pub fn disconnected_targets(
+        &self, proxy_name: &str, now: i64, stale: i64,
+    ) -> Vec<String> {
+        let proxy = match self.proxies.get(proxy_name) {
+            Some(p) => p,
+            None => return vec![],
+        };
+
+        let mut res: Vec<String> = proxy
+            .connected
+            .iter()
+            .filter(|(_, connected)| {
+                !**connected || proxy.reported_at < now - stale
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        res.sort();
+        res
+    }
+
+    // 按中转配置的抽水比例对账
+    pub fn rows(
+        &self, proxy_name: &str, config: &Settings, window: FeeWindow,
+        threshold: f64,
+    ) -> Vec<FeeReportRow> {
+        self.rows_at(proxy_name, config, window, threshold, current_hour())
+    }
+
+    fn rows_at(
+        &self, proxy_name: &str, config: &Settings, window: FeeWindow,
+        threshold: f64, now: i64,
+    ) -> Vec<FeeReportRow> {
+        let mut rows = vec![];
+        let proxy = match self.proxies.get(proxy_name) {
+            Some(p) => p,
+            None => return rows,
+        };
+
+        // 统一钱包模式不单独抽水 配置了抽水规则时每个钱包费率不同
+        // 这两种情况无法按 share_rate 对账 只展示不标记偏差
+        let check_drift =
+            !config.is_unified_wallet() && config.fee_rules.is_empty();
+        let threshold = if check_drift { threshold } else { f64::INFINITY };
+        let share_rate = if config.is_unified_wallet() {
+            0.0
+        } else {
+            config.share_rate as f64 * 100.0
+        };
+        let total_weight: u32 = proxy.weights.values().sum();
+        let targets: BTreeMap<&String, BTreeMap<String, FeeCounters>> = proxy
+            .targets
+            .iter()
+            .map(|(name, hourly)| (name, hourly.group(window, now)))
+            .collect();
+
+        for (period, total) in proxy.total.group(window, now) {
+            let sample = total.shares + total.fee_shares;
+            rows.push(FeeReportRow::new(
+                proxy_name, "", &period, &total, share_rate, sample, threshold,
+            ));
+
+            for (name, periods) in &targets {
+                let counters = match periods.get(&period) {
+                    Some(c) => *c,
+                    None => FeeCounters::default(),
+                };
+                let configured = match proxy.weights.get(*name) {
+                    Some(w) if total_weight > 0 => {
+                        share_rate * *w as f64 / total_weight as f64
+                    }
+                    _ => 0.0,
+                };
+                rows.push(FeeReportRow::new(
+                    proxy_name, name, &period, &counters, configured, sample,
+                    threshold,
+                ));
+            }
+        }
+
+        rows
+    }
+}
+
+impl FeeReportRow {
+    fn new(
+        proxy: &str, target: &str, period: &str, counters: &FeeCounters,
+        configured_rate: f64, sample: u64, threshold: f64,
+    ) -> Self {
+        let actual_rate = if sample > 0 {
+            counters.fee_shares as f64 / sample as f64 * 100.0
+        } else {
+            0.0
+        };
+
+        let drift = if configured_rate > 0.0 {
+            (actual_rate - configured_rate) / configured_rate * 100.0
+        } else if actual_rate > 0.0 {
+            100.0
+        } else {
+            0.0
+        };
+
+        Self {
+            proxy: proxy.to_string(),
+            target: target.to_string(),
+            period: period.to_string(),
+            jobs: counters.jobs,
+            fee_jobs: counters.fee_jobs,
+            shares: counters.shares,
+            fee_shares: counters.fee_shares,
+            accepted: counters.accepted,
+            rejected: counters.rejected,
+            dropped: counters.dropped,
+            configured_rate: round(configured_rate),
+            actual_rate: round(actual_rate),
+            drift: round(drift),
+            flagged: sample >= MIN_SAMPLE && drift.abs() > threshold,
+        }
+    }
+
+    pub fn csv_header() -> &'static str {
+        "proxy,target,period,jobs,fee_jobs,shares,fee_shares,accepted,\
+         rejected,dropped,configured_rate,actual_rate,drift,flagged\n"
+    }
+
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+            csv_field(&self.proxy),
+            csv_field(&self.target),
+            self.period,
+            self.jobs,
+            self.fee_jobs,
+            self.shares,
+            self.fee_shares,
+            self.accepted,
+            self.rejected,
+            self.dropped,
+            self.configured_rate,
+            self.actual_rate,
+            self.drift,
+            self.flagged
+        )
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn round(value: f64) -> f64 { (value * 100.0).round() / 100.0 }
+
+fn current_hour() -> i64 { chrono::Utc::now().timestamp() / 3600 }
+
+#[test]
+fn test_fee_report_drift() {
+    let mut report = FeeReport::default();
+    let mut w =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    w.total_send_idx = 1000;
+    w.total_fee_idx = 10;
+    w.share_index = 990;
+    w.fee_share_index = 10;
+    report.record_worker_at("proxy", &w, 100);
+
+    let target = FeeTargetReport {
+        name: "0xfee.fee".into(),
+        weight: 1,
+        counters: FeeCounters {
+            fee_jobs: 10,
+            fee_shares: 8,
+            accepted: 8,
+            dropped: 2,
+            ..Default::default()
+        },
+        connected: true,
+        rejects: RejectCounters::default(),
+    };
+    report.record_targets_at("proxy", &[target], 100);
+
+    let mut config = Settings::default();
+    config.share = 1;
+    config.share_rate = 0.01;
+    let rows = report.rows_at("proxy", &config, FeeWindow::Hour, 20.0, 100);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].actual_rate, 1.0);
+    assert!(!rows[0].flagged);
+    assert_eq!(rows[1].target, "0xfee.fee");
+    assert_eq!(rows[1].actual_rate, 0.8);
+    assert!(!rows[1].flagged);
+
+    config.share_rate = 0.02;
+    let rows = report.rows_at("proxy", &config, FeeWindow::Day, 20.0, 100);
+    assert!(rows[0].flagged);
+    assert!(rows[1].flagged);
+
+    // 配置了抽水规则时不按 share_rate 标记
+    config.fee_rules = vec![crate::util::fee_rule::FeeRule {
+        kind: crate::util::fee_rule::FeeRuleKind::Wallet,
+        pattern: "0xabc".into(),
+        rate: 0.01,
+    }];
+    let rows = report.rows_at("proxy", &config, FeeWindow::Day, 20.0, 100);
+    assert!(rows.iter().all(|r| !r.flagged));
+
+    // 统一钱包模式实际费率为0
+    config.fee_rules.clear();
+    config.share = 2;
+    let rows = report.rows_at("proxy", &config, FeeWindow::Day, 20.0, 100);
+    assert_eq!(rows[0].configured_rate, 0.0);
+    assert!(rows.iter().all(|r| !r.flagged));
+}
@@ -0,0 +1,158 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::state::Worker;
+
+// 最多保留7天的小时统计 与公开页面展示的范围一致
+const MAX_HOURS: i64 = 24 * 7;
+// 会话超过此时长没有上报时视为已断开 丢弃其累计值
+const SESSION_HOURS: i64 = 24;
+
+pub type FeeStatsState = std::sync::Arc<std::sync::Mutex<FeeStats>>;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HourStat {
+    pub hour: i64,
+    pub share: u64,
+    pub fee_share: u64,
+}
+
+// 按钱包统计每小时的普通份额及抽水份额。
+// 代理进程上报的是每个连接的累计值，这里记录两次上报之间的增量。
+#[derive(Debug, Default)]
+pub struct FeeStats {
+    wallets: HashMap<String, VecDeque<HourStat>>,
+    // 每个会话上次上报的累计值 及上报时所在的小时
+    last: HashMap<(String, u64), (u64, u64, i64)>,
+    // 上次清理过期数据的小时
+    pruned_at: i64,
+}
+
+impl FeeStats {
+    pub fn record(&mut self, proxy_name: &str, worker: &Worker) {
+        self.record_at(
+            proxy_name,
+            worker,
+            chrono::Utc::now().timestamp() / 3600,
+        )
+    }
+
+    fn record_at(&mut self, proxy_name: &str, worker: &Worker, hour: i64) {
+        if hour != self.pruned_at {
+            self.prune(hour);
+        }
+
+        let wallet = worker.wallet().to_lowercase();
+        if wallet.is_empty() {
+            return;
+        }
+
+        let key = (proxy_name.to_string(), worker.session_id);
+        let (last_share, last_fee, _) =
+            self.last.get(&key).cloned().unwrap_or((0, 0, hour));
+
+        // 矿工重新登录后累计值会从0开始
+        let share = if worker.share_index >= last_share {
+            worker.share_index - last_share
+        } else {
+            worker.share_index
+        };
+        let fee_share = if worker.fee_share_index >= last_fee {
+            worker.fee_share_index - last_fee
+        } else {
+            worker.fee_share_index
+        };
+
+        if worker.is_online() {
+            self.last.insert(
+                key,
+                (worker.share_index, worker.fee_share_index, hour),
+            );
+        } else {
+            self.last.remove(&key);
+        }
+
+        if share == 0 && fee_share == 0 {
+            return;
+        }
+
+        let hours = self.wallets.entry(wallet).or_default();
+        match hours.back_mut() {
+            Some(h) if h.hour == hour => {
+                h.share += share;
+                h.fee_share += fee_share;
+            }
+            _ => hours.push_back(HourStat {
+                hour,
+                share,
+                fee_share,
+            }),
+        }
+    }
+
+    // 每小时清理一次 删除过期的小时统计 没有数据的钱包和已断开的会话
+    fn prune(&mut self, hour: i64) {
+        self.pruned_at = hour;
+        self.wallets.retain(|_, hours| {
+            while let Some(h) = hours.front() {
+                if h.hour > hour - MAX_HOURS {
+                    break;
+                }
+                hours.pop_front();
+            }
+            !hours.is_empty()
+        });
+        self.last.retain(|_, (_, _, h)| *h > hour - SESSION_HOURS);
+    }
+
+    // 最近 hours 小时内 (普通份额, 抽水份额)
+    pub fn total(&self, wallet: &str, hours: i64) -> (u64, u64) {
+        self.total_at(wallet, hours, chrono::Utc::now().timestamp() / 3600)
+    }
+
+    fn total_at(&self, wallet: &str, hours: i64, now: i64) -> (u64, u64) {
+        match self.wallets.get(&wallet.to_lowercase()) {
+            Some(stats) => stats
+                .iter()
+                .filter(|h| h.hour > now - hours)
+                .fold((0, 0), |(s, f), h| (s + h.share, f + h.fee_share)),
+            None => (0, 0),
+        }
+    }
+}
+
+#[test]
+fn test_fee_stats_delta() {
+    let mut stats = FeeStats::default();
+    let mut w =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xABC.w1".into(), true);
+
+    w.share_index = 10;
+    w.fee_share_index = 1;
+    stats.record_at("proxy", &w, 100);
+    w.share_index = 25;
+    w.fee_share_index = 2;
+    stats.record_at("proxy", &w, 101);
+    assert_eq!(stats.total_at("0xabc", 24, 101), (25, 2));
+    assert_eq!(stats.total_at("0xabc", 1, 101), (15, 1));
+
+    // 重新登录 累计值归零
+    w.share_index = 4;
+    w.fee_share_index = 0;
+    stats.record_at("proxy", &w, 101);
+    assert_eq!(stats.total_at("0xabc", 24, 101), (29, 2));
+
+    assert_eq!(stats.total_at("0xabc", 24, 101 + MAX_HOURS), (0, 0));
+    assert_eq!(stats.total_at("0xdef", 24, 101), (0, 0));
+
+    // 过期的钱包和不再上报的会话被清理
+    let mut other =
+        Worker::new("0xdef.w1".into(), "w1".into(), "0xdef.w1".into(), true);
+    other.session_id = w.session_id + 1;
+    other.share_index = 1;
+    stats.record_at("proxy", &other, 101 + SESSION_HOURS);
+    assert_eq!(stats.last.len(), 1);
+    assert_eq!(stats.wallets.len(), 2);
+    stats.record_at("proxy", &other, 101 + MAX_HOURS);
+    assert_eq!(stats.wallets.len(), 1);
+    assert!(stats.wallets.contains_key("0xdef"));
+}
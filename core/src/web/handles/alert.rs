@@ -0,0 +1,104 @@
+use actix_web::{get, post, web, Responder};
+use actix_web_grants::proc_macro::{has_any_permission, has_permissions};
+
+use crate::web::{
+    alert::{deliver, test_event, Alert, AlertRule, AlertState, Webhook},
+    data::*,
+};
+
+fn result<T: serde::Serialize + Default>(
+    res: anyhow::Result<T>,
+) -> web::Json<Response<T>> {
+    match res {
+        Ok(data) => web::Json(Response::<T> {
+            code: 20000,
+            message: "".into(),
+            data,
+        }),
+        Err(e) => web::Json(Response::<T> {
+            code: 40000,
+            message: e.to_string(),
+            data: T::default(),
+        }),
+    }
+}
+
+#[get("/user/alerts/rules")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn alert_rules(
+    alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let rules = alerts.lock().unwrap().config.rules.clone();
+    Ok(result::<Vec<AlertRule>>(Ok(rules)))
+}
+
+// 新增告警规则 返回规则编号
+#[post("/user/alerts/rules")]
+#[has_permissions("ROLE_OPERATOR")]
+async fn add_alert_rule(
+    req: web::Json<AlertRule>, alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let res = alerts.lock().unwrap().add_rule(req.into_inner());
+    Ok(result::<u64>(res))
+}
+
+#[post("/user/alerts/rules/{id}")]
+#[has_permissions("ROLE_OPERATOR")]
+async fn update_alert_rule(
+    id: web::Path<u64>, req: web::Json<AlertRule>,
+    alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let res = alerts.lock().unwrap().update_rule(*id, req.into_inner());
+    Ok(result::<()>(res))
+}
+
+#[post("/user/alerts/rules/{id}/delete")]
+#[has_permissions("ROLE_OPERATOR")]
+async fn delete_alert_rule(
+    id: web::Path<u64>, alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let res = alerts.lock().unwrap().delete_rule(*id);
+    Ok(result::<()>(res))
+}
+
+#[get("/user/alerts/webhooks")]
+#[has_permissions("ROLE_OPERATOR")]
+async fn alert_webhooks(
+    alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let webhooks = alerts.lock().unwrap().config.webhooks.clone();
+    Ok(result::<Vec<Webhook>>(Ok(webhooks)))
+}
+
+// 整体替换webhook列表
+#[post("/user/alerts/webhooks")]
+#[has_permissions("ROLE_OPERATOR")]
+async fn update_alert_webhooks(
+    req: web::Json<Vec<Webhook>>, alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let res = alerts.lock().unwrap().set_webhooks(req.into_inner());
+    Ok(result::<()>(res))
+}
+
+// 当前未恢复的告警
+#[get("/user/alerts/active")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn active_alerts(
+    alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let active = alerts.lock().unwrap().active();
+    Ok(result::<Vec<Alert>>(Ok(active)))
+}
+
+// 向所有webhook发送一条测试告警
+#[post("/user/alerts/test")]
+#[has_permissions("ROLE_OPERATOR")]
+async fn test_alert(
+    alerts: web::Data<AlertState>,
+) -> actix_web::Result<impl Responder> {
+    let webhooks = alerts.lock().unwrap().config.webhooks.clone();
+    let res =
+        deliver(&webhooks, &[test_event()], std::time::Duration::from_secs(1))
+            .await;
+    Ok(result::<()>(res))
+}
@@ -0,0 +1,69 @@
+use actix_web::{get, post, web, Responder};
+use actix_web_grants::proc_macro::has_permissions;
+use chrono::Utc;
+
+use crate::web::{
+    api_token::{ApiTokenInfo, ApiTokenState},
+    data::*,
+    handles::auth::CurrentUser,
+};
+
+#[get("/user/api_tokens")]
+#[has_permissions("ROLE_ADMIN")]
+async fn api_tokens(
+    tokens: web::Data<ApiTokenState>,
+) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(Response::<Vec<ApiTokenInfo>> {
+        code: 20000,
+        message: "".into(),
+        data: tokens.lock().unwrap().list(Utc::now().timestamp()),
+    }))
+}
+
+// 创建后只返回一次令牌明文
+#[post("/user/api_tokens")]
+#[has_permissions("ROLE_ADMIN")]
+async fn create_api_token(
+    req: web::Json<CreateApiTokenRequest>, user: CurrentUser,
+    tokens: web::Data<ApiTokenState>,
+) -> actix_web::Result<impl Responder> {
+    let req = req.into_inner();
+    match tokens.lock().unwrap().create(
+        &req.name,
+        req.scopes,
+        req.expires_at,
+        &user.name,
+    ) {
+        Ok((token, info)) => {
+            Ok(web::Json(Response::<Option<CreateApiTokenResponse>> {
+                code: 20000,
+                message: "".into(),
+                data: Some(CreateApiTokenResponse { token, info }),
+            }))
+        }
+        Err(e) => Ok(web::Json(Response::<Option<CreateApiTokenResponse>> {
+            code: 40000,
+            message: e.to_string(),
+            data: None,
+        })),
+    }
+}
+
+#[post("/user/api_tokens/{id}/revoke")]
+#[has_permissions("ROLE_ADMIN")]
+async fn revoke_api_token(
+    id: web::Path<String>, tokens: web::Data<ApiTokenState>,
+) -> actix_web::Result<impl Responder> {
+    match tokens.lock().unwrap().revoke(&id) {
+        Ok(()) => Ok(web::Json(Response::<String> {
+            code: 20000,
+            message: "".into(),
+            data: String::default(),
+        })),
+        Err(e) => Ok(web::Json(Response::<String> {
+            code: 40000,
+            message: e.to_string(),
+            data: String::default(),
+        })),
+    }
+}
@@ -1,27 +1,46 @@
+use actix_web::{
+    dev::Payload, http::header::HeaderMap, FromRequest, HttpMessage,
+    HttpRequest,
+};
 use chrono::prelude::*;
-use jsonwebtoken::{encode, EncodingKey, Header};
+use jsonwebtoken::{
+    decode, encode, DecodingKey, EncodingKey, Header, Validation,
+};
 
 use serde::{Deserialize, Serialize};
 
-use crate::JWT_SECRET;
+use crate::{web::api_token::ApiTokenGrant, JWT_SECRET};
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub username: String,
+    // 登录会话编号 注销后 token 失效
+    #[serde(default)]
+    pub jti: String,
     #[serde(with = "jwt_numeric_date")]
     exp: DateTime<Utc>,
 }
 
 impl Claims {
-    pub fn new(username: String, exp: DateTime<Utc>) -> Self {
+    pub fn new(username: String, jti: String, exp: DateTime<Utc>) -> Self {
         let exp =
             exp.date()
                 .and_hms_milli(exp.hour(), exp.minute(), exp.second(), 0);
 
-        Self { username, exp }
+        Self { username, jti, exp }
     }
 }
 
+pub fn decode_jwt(token: &str) -> Option<Claims> {
+    decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(JWT_SECRET.as_bytes()),
+        &Validation::default(),
+    )
+    .ok()
+    .map(|data| data.claims)
+}
+
 pub fn generate_jwt(claims: Claims) -> anyhow::Result<String> {
     encode(
         &Header::default(),
@@ -31,6 +50,50 @@ pub fn generate_jwt(claims: Claims) -> anyhow::Result<String> {
     .map_err(|e| anyhow::anyhow!(e))
 }
 
+// 请求携带的 token。后台使用 token 头 脚本也可以用 Authorization: Bearer
+pub fn request_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(token) = headers.get("token") {
+        return token.to_str().ok();
+    }
+    headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+// 当前登录的账号 从请求的 token 中读取
+pub struct CurrentUser {
+    pub name: String,
+    // 登录会话编号
+    pub session: String,
+}
+
+impl FromRequest for CurrentUser {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // 接口令牌没有登录会话 记录为令牌名称
+        if let Some(grant) = req.extensions().get::<ApiTokenGrant>() {
+            return std::future::ready(Ok(CurrentUser {
+                name: format!("token:{}", grant.name),
+                session: String::new(),
+            }));
+        }
+        let user = match request_token(req.headers()).and_then(decode_jwt) {
+            Some(claims) => CurrentUser {
+                name: claims.username,
+                session: claims.jti,
+            },
+            None => CurrentUser {
+                name: "unknown".into(),
+                session: String::new(),
+            },
+        };
+        std::future::ready(Ok(user))
+    }
+}
+
 mod jwt_numeric_date {
     //! Custom serialization of DateTime<Utc> to conform with the JWT spec (RFC
     //! 7519 section 2, "Numeric Date")
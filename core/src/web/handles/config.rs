@@ -0,0 +1,91 @@
+use actix_web::{get, post, web, Responder};
+use actix_web_grants::proc_macro::{has_any_permission, has_permissions};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ipc::parent::ParentState,
+    web::{
+        config_store::{ConfigState, ConfigVersion, DiffLine},
+        data::*,
+        handles::auth::CurrentUser,
+        supervisor,
+    },
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DiffQuery {
+    pub from: u64,
+    // 为空时和当前配置比较
+    pub to: Option<u64>,
+}
+
+// configs.yaml 的历史版本 最新的在前
+#[get("/user/configs/versions")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn config_versions(
+    store: web::Data<ConfigState>,
+) -> actix_web::Result<impl Responder> {
+    match store.versions() {
+        Ok(versions) => Ok(web::Json(Response::<Vec<ConfigVersion>> {
+            code: 20000,
+            message: "".into(),
+            data: versions,
+        })),
+        Err(e) => Ok(web::Json(Response::<Vec<ConfigVersion>> {
+            code: 40000,
+            message: e.to_string(),
+            data: vec![],
+        })),
+    }
+}
+
+#[get("/user/configs/diff")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn config_diff(
+    query: web::Query<DiffQuery>, store: web::Data<ConfigState>,
+) -> actix_web::Result<impl Responder> {
+    match store.diff(query.from, query.to) {
+        Ok(lines) => Ok(web::Json(Response::<Vec<DiffLine>> {
+            code: 20000,
+            message: "".into(),
+            data: lines,
+        })),
+        Err(e) => Ok(web::Json(Response::<Vec<DiffLine>> {
+            code: 40000,
+            message: e.to_string(),
+            data: vec![],
+        })),
+    }
+}
+
+// 回滚到历史版本 并按回滚后的配置添加 删除或更新中转
+#[post("/user/configs/rollback/{version}")]
+#[has_permissions("ROLE_ADMIN")]
+async fn config_rollback(
+    version: web::Path<u64>, state: web::Data<ParentState>,
+    store: web::Data<ConfigState>, user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let (configs, meta) = match store.rollback(*version, &user.name) {
+        Ok(res) => res,
+        Err(e) => {
+            return Ok(web::Json(Response::<ConfigVersion> {
+                code: 40000,
+                message: e.to_string(),
+                data: ConfigVersion::default(),
+            }))
+        }
+    };
+
+    match supervisor::apply_all(&state, configs).await {
+        Ok(()) => Ok(web::Json(Response::<ConfigVersion> {
+            code: 20000,
+            message: "".into(),
+            data: meta,
+        })),
+        Err(e) => Ok(web::Json(Response::<ConfigVersion> {
+            code: 40000,
+            message: format!("配置已回滚 部分中转应用失败 {}", e),
+            data: meta,
+        })),
+    }
+}
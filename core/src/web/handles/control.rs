@@ -0,0 +1,143 @@
+use actix_web::{get, post, web, Responder};
+use actix_web_grants::proc_macro::has_any_permission;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ipc::parent::send_command,
+    proxy::{drain_timeout, reload::ReloadReport},
+    state::Worker,
+    web::{config_store::ConfigState, data::*, AppState, ChildCommand},
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DrainQuery {
+    // 等待会话结束的秒数 默认见 drain_timeout
+    pub timeout: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LogLevelRequest {
+    pub level: String,
+}
+
+// 下发命令并把执行结果转换为接口返回
+async fn execute(
+    app: &AppState, name: &str, command: ChildCommand,
+) -> Response<Vec<Worker>> {
+    match send_command(app, name, command).await {
+        Ok(reply) if reply.ok => Response {
+            code: 20000,
+            message: reply.message,
+            data: reply.workers,
+        },
+        Ok(reply) => Response {
+            code: 40000,
+            message: reply.message,
+            data: vec![],
+        },
+        Err(e) => Response {
+            code: 40000,
+            message: e.to_string(),
+            data: vec![],
+        },
+    }
+}
+
+// 代理进程中所有在线会话的实时状态
+#[get("/user/server/{name}/sessions")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn sessions(
+    name: web::Path<String>, app: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(
+        execute(&app, &name, ChildCommand::Snapshot).await,
+    ))
+}
+
+#[post("/user/server/{name}/sessions/{session_id}/disconnect")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn disconnect_session(
+    path: web::Path<(String, u64)>, app: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let (name, session_id) = path.into_inner();
+    Ok(web::Json(
+        execute(&app, &name, ChildCommand::Disconnect(session_id)).await,
+    ))
+}
+
+// 停止接受新连接并通知矿机重连。会话结束或超时后代理退出 不再自动重启
+#[post("/user/server/{name}/drain")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn drain(
+    name: web::Path<String>, query: web::Query<DrainQuery>,
+    app: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let timeout = query.timeout.unwrap_or_else(|| drain_timeout().as_secs());
+    Ok(web::Json(
+        execute(&app, &name, ChildCommand::Drain(timeout)).await,
+    ))
+}
+
+#[post("/user/server/{name}/log_level")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn set_log_level(
+    name: web::Path<String>, req: web::Json<LogLevelRequest>,
+    app: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let command = ChildCommand::SetLogLevel(req.level.clone());
+    Ok(web::Json(execute(&app, &name, command).await))
+}
+
+// 按 configs.yaml 中保存的配置重新加载。无需重启的修改立即生效
+#[post("/user/server/{name}/reload")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn reload(
+    name: web::Path<String>, app: web::Data<AppState>,
+    store: web::Data<ConfigState>,
+) -> actix_web::Result<impl Responder> {
+    let config = match store.load() {
+        Ok(configs) => configs.into_iter().find(|c| c.name == *name),
+        Err(e) => {
+            return Ok(web::Json(Response::<ReloadReport> {
+                code: 40000,
+                message: e.to_string(),
+                data: ReloadReport::default(),
+            }))
+        }
+    };
+    let config = match config {
+        Some(config) => config,
+        None => {
+            return Ok(web::Json(Response::<ReloadReport> {
+                code: 40000,
+                message: "未找到此中转".into(),
+                data: ReloadReport::default(),
+            }))
+        }
+    };
+
+    let command = ChildCommand::Reload(Box::new(config.clone()));
+    match send_command(&app, &name, command).await {
+        Ok(reply) if reply.ok => {
+            // 需要重启的修改在代理进程重启后生效
+            if let Some(server) = app.lock().unwrap().get_mut(&*name) {
+                server.config = config;
+            }
+            Ok(web::Json(Response::<ReloadReport> {
+                code: 20000,
+                message: reply.message,
+                data: reply.reload.unwrap_or_default(),
+            }))
+        }
+        Ok(reply) => Ok(web::Json(Response::<ReloadReport> {
+            code: 40000,
+            message: reply.message,
+            data: ReloadReport::default(),
+        })),
+        Err(e) => Ok(web::Json(Response::<ReloadReport> {
+            code: 40000,
+            message: e.to_string(),
+            data: ReloadReport::default(),
+        })),
+    }
+}
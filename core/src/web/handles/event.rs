@@ -0,0 +1,20 @@
+use actix_web::{get, web, Responder};
+use actix_web_grants::proc_macro::has_any_permission;
+
+use crate::web::{
+    data::*,
+    event_log::{EventLogState, EventQuery, ProxyEvent},
+};
+
+// 矿机连接事件 可按中转 矿工 钱包 IP 时间过滤
+#[get("/user/events")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn events(
+    query: web::Query<EventQuery>, events: web::Data<EventLogState>,
+) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(Response::<Vec<ProxyEvent>> {
+        code: 20000,
+        message: "".into(),
+        data: events.lock().unwrap().query(&query),
+    }))
+}
@@ -0,0 +1,85 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use actix_web_grants::proc_macro::has_any_permission;
+use serde::Deserialize;
+
+use crate::{
+    util::config::Settings,
+    web::{
+        data::*,
+        fee_report::{FeeReportRow, FeeReportState, FeeWindow},
+        AppState,
+    },
+};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct FeeReportQuery {
+    #[serde(default)]
+    pub window: FeeWindow,
+    // 只看某个中转。为空返回全部
+    #[serde(default)]
+    pub name: String,
+}
+
+// 偏差超过此百分比时标记。可通过环境变量 MINING_PROXY_FEE_DRIFT 修改
+fn drift_threshold() -> f64 {
+    std::env::var("MINING_PROXY_FEE_DRIFT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20.0)
+}
+
+fn report_rows(
+    query: &FeeReportQuery, app: &AppState, report: &FeeReportState,
+) -> Vec<FeeReportRow> {
+    let threshold = drift_threshold();
+    let mut proxies: Vec<(String, Settings)> = app
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(name, _)| query.name.is_empty() || **name == query.name)
+        .map(|(name, server)| (name.clone(), server.config.clone()))
+        .collect();
+    proxies.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let report = report.lock().unwrap();
+    proxies
+        .iter()
+        .flat_map(|(name, config)| {
+            report.rows(name, config, query.window, threshold)
+        })
+        .collect()
+}
+
+// 抽水对账 实际抽水比例与配置比例对比
+#[get("/user/fee_report")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn fee_report(
+    query: web::Query<FeeReportQuery>, app: web::Data<AppState>,
+    report: web::Data<FeeReportState>,
+) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(Response::<Vec<FeeReportRow>> {
+        code: 20000,
+        message: "".into(),
+        data: report_rows(&query, &app, &report),
+    }))
+}
+
+#[get("/user/fee_report.csv")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn fee_report_csv(
+    query: web::Query<FeeReportQuery>, app: web::Data<AppState>,
+    report: web::Data<FeeReportState>,
+) -> actix_web::Result<impl Responder> {
+    let mut csv = FeeReportRow::csv_header().to_string();
+    for row in report_rows(&query, &app, &report) {
+        csv.push_str(&row.to_csv());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"fee_report.csv\"",
+        ))
+        .body(csv))
+}
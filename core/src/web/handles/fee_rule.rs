@@ -0,0 +1,106 @@
+use actix_web::{get, post, web, Responder};
+use actix_web_grants::proc_macro::has_any_permission;
+
+use anyhow::bail;
+
+use crate::{
+    ipc::parent::send_command,
+    util::fee_rule::{FeeRule, FeeRules},
+    web::{
+        config_store::ConfigState, data::*, handles::auth::CurrentUser, AppState,
+        ChildCommand,
+    },
+};
+
+#[get("/user/fee_rules/{name}")]
+#[has_any_permission(
+    "ROLE_VIEWER",
+    "SCOPE_READ_STATS",
+    "SCOPE_MANAGE_FEE_RULES"
+)]
+async fn fee_rules(
+    proxy_server_name: web::Path<String>, app: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let proxy_server = app.lock().unwrap();
+    match proxy_server.get(&proxy_server_name.to_string()) {
+        Some(server) => {
+            let rules = server
+                .config
+                .fee_rules
+                .iter()
+                .map(|r| FeeRule {
+                    rate: r.rate * 100.0,
+                    ..r.clone()
+                })
+                .collect();
+
+            Ok(web::Json(Response::<FeeRulesRequest> {
+                code: 20000,
+                message: "".into(),
+                data: FeeRulesRequest { rules },
+            }))
+        }
+        None => Ok(web::Json(Response::<FeeRulesRequest> {
+            code: 40000,
+            message: "未找到此中转".into(),
+            data: FeeRulesRequest::default(),
+        })),
+    }
+}
+
+// 整体替换抽水规则。保存到配置文件并下发到正在运行的代理进程
+#[post("/user/fee_rules/{name}")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_FEE_RULES")]
+async fn update_fee_rules(
+    proxy_server_name: web::Path<String>, req: web::Json<FeeRulesRequest>,
+    app: web::Data<AppState>, store: web::Data<ConfigState>, user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let name = proxy_server_name.to_string();
+    let rules: Vec<FeeRule> = req
+        .rules
+        .iter()
+        .map(|r| FeeRule {
+            rate: r.rate / 100.0,
+            ..r.clone()
+        })
+        .collect();
+
+    if let Err(e) = FeeRules::new(&rules) {
+        return Ok(web::Json(Response::<String> {
+            code: 40000,
+            message: format!("配置错误 {}", e),
+            data: String::default(),
+        }));
+    }
+
+    let action = format!("修改抽水规则 {}", name);
+    let res = store.update(&user.name, &action, |configs| {
+        match configs.iter_mut().find(|c| c.name == name) {
+            Some(c) => c.fee_rules = rules.clone(),
+            None => bail!("未找到此中转"),
+        }
+        Ok(())
+    });
+    if let Err(e) = res {
+        return Ok(web::Json(Response::<String> {
+            code: 40000,
+            message: e.to_string(),
+            data: String::default(),
+        }));
+    }
+
+    if let Some(server) = app.lock().unwrap().get_mut(&name) {
+        server.config.fee_rules = rules.clone();
+    }
+    if let Err(e) =
+        send_command(&app, &name, ChildCommand::FeeRules(rules)).await
+    {
+        tracing::warn!("下发抽水规则失败 重启后生效 {}", e);
+    }
+
+    Ok(web::Json(Response::<String> {
+        code: 20000,
+        message: "".into(),
+        data: String::default(),
+    }))
+}
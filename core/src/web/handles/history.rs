@@ -0,0 +1,58 @@
+use actix_web::{get, web, Responder};
+use actix_web_grants::proc_macro::has_any_permission;
+use serde::Deserialize;
+
+use crate::web::{
+    data::*,
+    history::{HistoryPoint, HistoryState},
+};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct HistoryQuery {
+    pub name: String,
+    // 为空返回整个中转的合计
+    #[serde(default)]
+    pub worker: String,
+    // 秒级时间戳。默认最近24小时
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+// 算力及份额历史
+#[get("/user/history")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn history(
+    query: web::Query<HistoryQuery>, history: web::Data<HistoryState>,
+) -> actix_web::Result<impl Responder> {
+    let to = query.to.unwrap_or_else(|| chrono::Utc::now().timestamp());
+    let from = query.from.unwrap_or(to - 86400);
+
+    let res =
+        query_history(history.get_ref().clone(), query.into_inner(), from, to)
+            .await;
+    match res {
+        Ok(points) => Ok(web::Json(Response::<Vec<HistoryPoint>> {
+            code: 20000,
+            message: "".into(),
+            data: points,
+        })),
+        Err(e) => Ok(web::Json(Response::<Vec<HistoryPoint>> {
+            code: 40000,
+            message: e.to_string(),
+            data: vec![],
+        })),
+    }
+}
+
+// sqlite 查询会阻塞 放到线程池中执行
+async fn query_history(
+    store: HistoryState, query: HistoryQuery, from: i64, to: i64,
+) -> anyhow::Result<Vec<HistoryPoint>> {
+    web::block(move || {
+        store
+            .lock()
+            .unwrap()
+            .query(&query.name, &query.worker, from, to)
+    })
+    .await?
+}
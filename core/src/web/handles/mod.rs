@@ -1,3 +1,15 @@
+pub mod alert;
+pub mod api_token;
 pub mod auth;
+pub mod config;
+pub mod control;
+pub mod event;
+pub mod fee_report;
+pub mod fee_rule;
+pub mod history;
+pub mod public;
+pub mod reject;
 pub mod server;
+pub mod upgrade;
 pub mod user;
+pub mod worker;
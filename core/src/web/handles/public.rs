@@ -0,0 +1,134 @@
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util::human_bytes,
+    web::{
+        data::*, fee_stats::FeeStatsState, handles::server::floor,
+        rate_limit::RateLimiter, AppState,
+    },
+};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PublicWorker {
+    pub worker_name: String,
+    pub online: bool,
+    pub hash: String,
+    pub share_index: u64,
+    pub accept_index: u64,
+    pub invalid_index: u64,
+    pub fee_share_index: u64,
+    pub fee_accept_index: u64,
+    pub share_rate: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ObservedRate {
+    pub share_index: u64,
+    pub fee_share_index: u64,
+    pub rate: f64,
+}
+
+impl ObservedRate {
+    fn new((share_index, fee_share_index): (u64, u64)) -> Self {
+        let total = share_index + fee_share_index;
+        let rate = if total > 0 {
+            floor(fee_share_index as f64 / total as f64 * 100.0, 2)
+        } else {
+            0.0
+        };
+
+        Self {
+            share_index,
+            fee_share_index,
+            rate,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct FeeTransparencyResult {
+    pub wallet: String,
+    pub workers: Vec<PublicWorker>,
+    pub day: ObservedRate,
+    pub week: ObservedRate,
+}
+
+// 矿工自助查询抽水情况。无需登录，只返回与钱包地址完全一致的矿工
+#[get("/public/fee/{wallet}")]
+pub async fn fee_transparency(
+    wallet: web::Path<String>, req: HttpRequest, app: web::Data<AppState>,
+    stats: web::Data<FeeStatsState>, limiter: web::Data<RateLimiter>,
+) -> actix_web::Result<impl Responder> {
+    let ip = match req.peer_addr() {
+        Some(addr) => addr.ip(),
+        None => std::net::IpAddr::from([0, 0, 0, 0]),
+    };
+
+    if !limiter.check(ip) {
+        return Ok(web::Json(Response::<FeeTransparencyResult> {
+            code: 42900,
+            message: "请求过于频繁，请稍后再试".into(),
+            data: FeeTransparencyResult::default(),
+        }));
+    }
+
+    let wallet = wallet.trim().to_lowercase();
+    if wallet.len() < 10 {
+        return Ok(web::Json(Response::<FeeTransparencyResult> {
+            code: 40000,
+            message: "钱包地址不正确".into(),
+            data: FeeTransparencyResult::default(),
+        }));
+    }
+
+    let mut res = FeeTransparencyResult {
+        wallet: wallet.clone(),
+        ..Default::default()
+    };
+
+    {
+        let proxy_server = app.lock().unwrap();
+        for server in proxy_server.values() {
+            for r in &server.workers {
+                if r.wallet().to_lowercase() != wallet {
+                    continue;
+                }
+
+                res.workers.push(PublicWorker {
+                    worker_name: r.worker_name.clone(),
+                    online: r.is_online(),
+                    hash: human_bytes(r.hash as f64),
+                    share_index: r.share_index,
+                    accept_index: r.accept_index,
+                    invalid_index: r.invalid_index,
+                    fee_share_index: r.fee_share_index,
+                    fee_accept_index: r.fee_accept_index,
+                    share_rate: floor(
+                        server.config.get_share_rate(r) as f64 * 100.0,
+                        2,
+                    ),
+                });
+            }
+        }
+    }
+
+    {
+        let stats = stats.lock().unwrap();
+        res.day = ObservedRate::new(stats.total(&wallet, 24));
+        res.week = ObservedRate::new(stats.total(&wallet, 24 * 7));
+    }
+
+    Ok(web::Json(Response::<FeeTransparencyResult> {
+        code: 20000,
+        message: "".into(),
+        data: res,
+    }))
+}
+
+#[get("/fee")]
+pub async fn fee_page() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("../static/fee.html"))
+}
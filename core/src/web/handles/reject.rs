@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use actix_web::{get, web, Responder};
+use actix_web_grants::proc_macro::has_any_permission;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    state::reject::RejectCounters,
+    web::{data::*, fee_report::FeeReportState, AppState},
+};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct RejectQuery {
+    // 只看某个中转。为空返回全部
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WorkerRejects {
+    pub proxy: String,
+    pub worker: String,
+    pub rejects: RejectCounters,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PoolRejects {
+    pub proxy: String,
+    pub pool: String,
+    // 是否为抽水矿池
+    pub fee: bool,
+    pub rejects: RejectCounters,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RejectReport {
+    pub workers: Vec<WorkerRejects>,
+    pub pools: Vec<PoolRejects>,
+}
+
+// 拒绝份额原因统计。按矿工及矿池汇总当前保留的会话
+#[get("/user/rejects")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn rejects(
+    query: web::Query<RejectQuery>, app: web::Data<AppState>,
+    fee_report: web::Data<FeeReportState>,
+) -> actix_web::Result<impl Responder> {
+    let mut workers: BTreeMap<(String, String), RejectCounters> =
+        BTreeMap::new();
+    let mut pools: BTreeMap<(String, String), RejectCounters> = BTreeMap::new();
+    let mut names = vec![];
+    for (name, server) in app.lock().unwrap().iter() {
+        if !query.name.is_empty() && *name != query.name {
+            continue;
+        }
+        names.push(name.clone());
+
+        for w in &server.workers {
+            workers
+                .entry((name.clone(), w.worker.clone()))
+                .or_default()
+                .merge(&w.rejects);
+            if !w.pool.is_empty() {
+                pools
+                    .entry((name.clone(), w.pool.clone()))
+                    .or_default()
+                    .merge(&w.rejects);
+            }
+        }
+    }
+
+    let mut res = RejectReport {
+        workers: workers
+            .into_iter()
+            .filter(|(_, r)| r.total() > 0)
+            .map(|((proxy, worker), rejects)| WorkerRejects {
+                proxy,
+                worker,
+                rejects,
+            })
+            .collect(),
+        pools: pools
+            .into_iter()
+            .map(|((proxy, pool), rejects)| PoolRejects {
+                proxy,
+                pool,
+                fee: false,
+                rejects,
+            })
+            .collect(),
+    };
+
+    names.sort();
+    let fee_report = fee_report.lock().unwrap();
+    for name in names {
+        for (target, rejects) in fee_report.target_rejects(&name) {
+            res.pools.push(PoolRejects {
+                proxy: name.clone(),
+                pool: target,
+                fee: true,
+                rejects,
+            });
+        }
+    }
+
+    Ok(web::Json(Response::<RejectReport> {
+        code: 20000,
+        message: "".into(),
+        data: res,
+    }))
+}
@@ -1,8 +1,4 @@
-use actix_web_grants::proc_macro::has_permissions;
-use std::{
-    fs::OpenOptions,
-    io::{Read, Write},
-};
+use actix_web_grants::proc_macro::has_any_permission;
 
 use clap::crate_version;
 
@@ -10,299 +6,321 @@ use actix_web::{get, post, web, Responder};
 
 use serde::{Deserialize, Serialize};
 
+use anyhow::bail;
+
 use crate::{
+    ipc::parent::ParentState,
     util::{config::Settings, human_bytes, time_to_string},
-    web::{data::*, AppState, OnlineWorker},
+    web::{
+        config_store::{ConfigState, ConfigStore},
+        data::*,
+        handles::auth::CurrentUser,
+        session::logical_workers,
+        supervisor::{self, ChildExit, ProxyStatus},
+        AppState, OnlineWorker,
+    },
 };
 
-#[post("/crate/app")]
-#[has_permissions("ROLE_ADMIN")]
-pub async fn crate_app(
-    req: web::Json<CreateRequest>, app: web::Data<AppState>,
-) -> actix_web::Result<impl Responder> {
-    //dbg!(req);
+// 校验后台提交的中转配置。修改中转时 old 为原配置
+async fn request_to_settings(
+    req: &CreateRequest, old: Option<&Settings>,
+) -> anyhow::Result<Settings> {
     let mut config = Settings::default();
-    if req.name == "" {
-        return Ok(web::Json(Response::<String> {
-            code: 40000,
-            message: "中转名称必须填写".into(),
-            data: String::default(),
-        }));
+    if req.name.is_empty() {
+        bail!("中转名称必须填写");
     }
 
     if req.tcp_port == 0 && req.ssl_port == 0 && req.encrypt_port == 0 {
-        return Ok(web::Json(Response::<String> {
-            code: 40000,
-            message: "未开启端口。请至少开启一个端口".into(),
-            data: String::default(),
-        }));
+        bail!("未开启端口。请至少开启一个端口");
     }
 
     if req.pool_address.is_empty() {
-        //println!("中转矿池必须填写");
-        return Ok(web::Json(Response::<String> {
-            code: 40000,
-            message: "中转矿池必须填写".into(),
-            data: String::default(),
-        }));
+        bail!("中转矿池必须填写");
     }
 
     if req.share != 0 {
-        if req.share_address.is_empty() {
-            //println!("抽水矿池必须填写");
-            return Ok(web::Json(Response::<String> {
-                code: 40000,
-                message: "抽水矿池必须填写".into(),
-                data: String::default(),
-            }));
+        if req.share_address.is_empty() && req.fee_destinations.is_empty() {
+            bail!("抽水矿池必须填写");
         }
 
-        if req.share_wallet.is_empty() {
-            //println!("抽水钱包必须填写");
-            return Ok(web::Json(Response::<String> {
-                code: 40000,
-                message: "抽水钱包必须填写".into(),
-                data: String::default(),
-            }));
+        if req.share_wallet.is_empty() && req.fee_destinations.is_empty() {
+            bail!("抽水钱包必须填写");
         }
 
         if req.share_rate <= 0.0 {
-            //println!("抽水比例必须填写");
-            return Ok(web::Json(Response::<String> {
-                code: 40000,
-                message: "抽水比例必须填写".into(),
-                data: String::default(),
-            }));
+            bail!("抽水比例必须填写");
         }
     }
 
     config.share_name = req.name.clone();
     config.coin = req.coin.clone();
     config.log_level = "DEBUG".into();
-    //config.log_path = "".into();
     config.name = req.name.clone();
     config.pool_address = vec![req.pool_address.clone()];
     config.share_address = vec![req.share_address.clone()];
+    config.fee_destinations = req.fee_destinations.clone();
     config.tcp_port = req.tcp_port;
     config.ssl_port = req.ssl_port;
     config.encrypt_port = req.encrypt_port;
     config.share = req.share;
-    config.share_rate = req.share_rate as f32 / 100.0;
+    config.share_rate = req.share_rate / 100.0;
     config.share_alg = req.share_alg;
     config.hash_rate = 100;
     config.share_wallet = req.share_wallet.clone();
 
-    match config.check().await {
-        Ok(_) => {}
-        Err(err) => {
-            tracing::error!("配置错误 {}", err);
-            return Ok(web::Json(Response::<String> {
-                code: 40000,
-                message: format!("配置错误 {}", err),
-                data: String::default(),
-            }));
-            //std::process::exit(1);
-        }
+    if let Err(err) = config.check().await {
+        tracing::error!("配置错误 {}", err);
+        bail!("配置错误 {}", err);
+    }
+
+    let res = match config.check_pools().await {
+        Ok(()) => config.check_ports(old),
+        Err(e) => Err(e),
     };
+    if let Err(err) = res {
+        tracing::error!("网络错误 {}", err);
+        bail!("网络错误 {}", err);
+    }
+    Ok(config)
+}
 
-    match config.check_net_work().await {
-        Ok(_) => {}
-        Err(err) => {
-            tracing::error!("网络错误 {}", err);
+#[post("/crate/app")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+pub async fn crate_app(
+    req: web::Json<CreateRequest>, state: web::Data<ParentState>,
+    store: web::Data<ConfigState>, user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let config = match request_to_settings(&req, None).await {
+        Ok(config) => config,
+        Err(e) => {
             return Ok(web::Json(Response::<String> {
                 code: 40000,
-                message: format!("网络错误 {}", err),
+                message: e.to_string(),
                 data: String::default(),
-            }));
-            //std::process::exit(1);
+            }))
         }
     };
 
-    use std::fs::File;
-
-    let mut cfgs = match OpenOptions::new()
-        //.append(false)
-        .write(true)
-        .read(true)
-        //.create(true)
-        //.truncate(true)
-        .open("configs.yaml")
-    {
-        Ok(f) => f,
-        Err(_) => match File::create("configs.yaml") {
-            Ok(t) => t,
-            Err(e) => std::panic::panic_any(e),
-        },
-    };
+    let name = config.name.clone();
+    let res = store.update(&user.name, &format!("添加中转 {}", name), |c| {
+        if c.iter().any(|c| c.name == name) {
+            bail!("配置错误 服务器名: {} 已经存在，请修改后重新添加。", name);
+        }
+        c.push(config.clone());
+        Ok(())
+    });
+    if let Err(e) = res.and_then(|_| supervisor::add(&state, config)) {
+        return Ok(web::Json(Response::<String> {
+            code: 40000,
+            message: e.to_string(),
+            data: String::default(),
+        }));
+    }
 
-    let mut configs = String::new();
-    match cfgs.read_to_string(&mut configs) {
-        Ok(_) => {
-            let mut configs: Vec<Settings> =
-                match serde_yaml::from_str(&configs) {
-                    Ok(s) => s,
-                    Err(e) => {
-                        tracing::error!("{}", e);
-                        vec![]
-                    }
-                };
-
-            // 去重
-            for c in &configs {
-                if config.name == c.name {
-                    return Ok(web::Json(Response::<String> {
-                        code: 40000,
-                        message: format!("配置错误 服务器名: {} 已经存在，请修改后重新添加。",config.name),
-                        data: String::default(),
-                    }));
-                }
-            }
-            configs.push(config.clone());
-            match serde_yaml::to_string(&configs) {
-                Ok(mut c_str) => {
-                    c_str = c_str[4..c_str.len()].to_string();
-                    drop(cfgs);
-                    std::fs::remove_file("configs.yaml")?;
-                    let mut cfgs = match OpenOptions::new()
-                        //.append(false)
-                        .write(true)
-                        .read(true)
-                        //.create(true)
-                        //.truncate(true)
-                        .open("configs.yaml")
-                    {
-                        Ok(f) => f,
-                        Err(_) => match File::create("configs.yaml") {
-                            Ok(t) => t,
-                            Err(e) => std::panic::panic_any(e),
-                        },
-                    };
-
-                    match cfgs.write_all(c_str.as_bytes()) {
-                        Ok(()) => {}
-                        Err(e) => {
-                            return Ok(web::Json(Response::<String> {
-                                code: 40000,
-                                message: e.to_string(),
-                                data: String::default(),
-                            }))
-                        }
-                    }
-                }
-                Err(e) => {
-                    return Ok(web::Json(Response::<String> {
-                        code: 40000,
-                        message: e.to_string(),
-                        data: String::default(),
-                    }))
-                }
-            };
-
-            match crate::util::run_server(&config) {
-                Ok(child) => {
-                    let online = OnlineWorker {
-                        child,
-                        config: config.clone(),
-                        workers: vec![],
-                        online: 0,
-                    };
-                    app.lock().unwrap().insert(config.name, online);
-                }
-                Err(e) => {
-                    return Ok(web::Json(Response::<String> {
-                        code: 40000,
-                        message: e.to_string(),
-                        data: String::default(),
-                    }))
-                }
-            }
+    Ok(web::Json(Response::<String> {
+        code: 20000,
+        message: "".into(),
+        data: String::default(),
+    }))
+}
 
-            return Ok(web::Json(Response::<String> {
-                code: 20000,
-                message: "".into(),
-                data: String::default(),
-            }));
-        }
-        Err(_) => {
-            let mut configs: Vec<Settings> = vec![];
-
-            configs.push(config.clone());
-
-            match serde_yaml::to_string(&configs) {
-                Ok(mut c_str) => {
-                    c_str = c_str[4..c_str.len()].to_string();
-                    match cfgs.write_all(c_str.as_bytes()) {
-                        Ok(()) => {}
-                        Err(e) => {
-                            return Ok(web::Json(Response::<String> {
-                                code: 40000,
-                                message: e.to_string(),
-                                data: String::default(),
-                            }))
-                        }
-                    }
-                }
-                Err(e) => {
-                    return Ok(web::Json(Response::<String> {
-                        code: 40000,
-                        message: e.to_string(),
-                        data: String::default(),
-                    }))
-                }
-            };
-
-            match crate::util::run_server(&config) {
-                Ok(child) => {
-                    let online = OnlineWorker {
-                        child,
-                        config: config.clone(),
-                        workers: vec![],
-                        online: 0,
-                    };
-                    app.lock().unwrap().insert(config.name, online);
-                }
-                Err(e) => {
-                    return Ok(web::Json(Response::<String> {
-                        code: 40000,
-                        message: e.to_string(),
-                        data: String::default(),
-                    }))
-                }
-            }
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ServerSummary {
+    pub name: String,
+    pub status: ProxyStatus,
+    // 自动重启次数
+    pub restarts: u32,
+    pub started_at: i64,
+    // 下次重启时间 仅重启中有效
+    pub next_restart: i64,
+    pub last_exit: Option<ChildExit>,
+}
 
-            return Ok(web::Json(Response::<String> {
-                code: 20000,
-                message: "".into(),
-                data: String::default(),
-            }));
-        }
-    };
+fn summary(name: &str, proxy: &OnlineWorker) -> ServerSummary {
+    ServerSummary {
+        name: name.to_string(),
+        status: proxy.supervision.status,
+        restarts: proxy.supervision.restarts,
+        started_at: proxy.started_at,
+        next_restart: proxy.supervision.next_restart,
+        last_exit: proxy.supervision.last_exit.clone(),
+    }
 }
 
 #[get("/user/server_list")]
-#[has_permissions("ROLE_ADMIN")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
 async fn server_list(
     app: web::Data<AppState>,
 ) -> actix_web::Result<impl Responder> {
     let mut v = vec![];
     {
         let proxy_server = app.lock().unwrap();
-        for (s, _) in &*proxy_server {
-            v.push(s.to_string());
+        for (s, proxy) in &*proxy_server {
+            v.push(summary(s, proxy));
         }
     }
+    v.sort_by(|a, b| a.name.cmp(&b.name));
 
-    Ok(web::Json(Response::<Vec<String>> {
+    Ok(web::Json(Response::<Vec<ServerSummary>> {
         code: 20000,
         message: "".into(),
         data: v,
     }))
 }
 
+// 操作完成后返回中转的当前状态
+fn lifecycle_response(
+    app: &AppState, name: &str, res: anyhow::Result<()>,
+) -> web::Json<Response<ServerSummary>> {
+    let data = match app.lock().unwrap().get(name) {
+        Some(proxy) => summary(name, proxy),
+        None => ServerSummary::default(),
+    };
+    match res {
+        Ok(()) => web::Json(Response {
+            code: 20000,
+            message: "".into(),
+            data,
+        }),
+        Err(e) => web::Json(Response {
+            code: 40000,
+            message: e.to_string(),
+            data,
+        }),
+    }
+}
+
+// 修改中转配置。无需重启的修改直接生效 否则只重启此中转
+#[post("/user/server/{name}/update")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn update_server(
+    name: web::Path<String>, req: web::Json<CreateRequest>,
+    app: web::Data<AppState>, state: web::Data<ParentState>,
+    store: web::Data<ConfigState>, user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let res = update(&name, &req, &state, &store, &user.name).await;
+    Ok(lifecycle_response(&app, &name, res))
+}
+
+async fn update(
+    name: &str, req: &CreateRequest, state: &ParentState, store: &ConfigStore,
+    admin: &str,
+) -> anyhow::Result<()> {
+    if req.name != name {
+        bail!("不能修改中转名称");
+    }
+    let old = match state.app.lock().unwrap().get(name) {
+        Some(proxy) => proxy.config.clone(),
+        None => bail!("未找到此中转"),
+    };
+    let mut config = request_to_settings(req, Some(&old)).await?;
+    // 后台不能修改的配置项保持原值
+    config.fee_rules = old.fee_rules.clone();
+    config.log_level = old.log_level.clone();
+    config.hash_rate = old.hash_rate;
+    config.pem_path = old.pem_path.clone();
+    config.key_path = old.key_path.clone();
+    config.stopped = old.stopped;
+
+    store.update(admin, &format!("修改中转 {}", name), |configs| {
+        for c in configs.iter_mut().filter(|c| c.name == name) {
+            *c = config.clone();
+        }
+        Ok(())
+    })?;
+    supervisor::apply_config(state, config).await
+}
+
+// 停止并删除中转
+#[post("/user/server/{name}/delete")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn delete_server(
+    name: web::Path<String>, app: web::Data<AppState>,
+    store: web::Data<ConfigState>, user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let res = match supervisor::stop(&app, &name).await {
+        Ok(()) => store
+            .update(&user.name, &format!("删除中转 {}", name), |configs| {
+                configs.retain(|c| c.name != *name);
+                Ok(())
+            })
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+    let response = lifecycle_response(&app, &name, res);
+    if response.code == 20000 {
+        app.lock().unwrap().remove(&*name);
+    }
+    Ok(response)
+}
+
+#[post("/user/server/{name}/stop")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn stop_server(
+    name: web::Path<String>, app: web::Data<AppState>,
+    store: web::Data<ConfigState>, user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let res = match supervisor::stop(&app, &name).await {
+        Ok(()) => set_stopped(&app, &store, &user.name, &name, true),
+        Err(e) => Err(e),
+    };
+    Ok(lifecycle_response(&app, &name, res))
+}
+
+#[post("/user/server/{name}/start")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn start_server(
+    name: web::Path<String>, app: web::Data<AppState>,
+    state: web::Data<ParentState>, store: web::Data<ConfigState>,
+    user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let res = match supervisor::start(&state, &name) {
+        Ok(()) => set_stopped(&app, &store, &user.name, &name, false),
+        Err(e) => Err(e),
+    };
+    Ok(lifecycle_response(&app, &name, res))
+}
+
+#[post("/user/server/{name}/restart")]
+#[has_any_permission("ROLE_OPERATOR", "SCOPE_MANAGE_PROXIES")]
+async fn restart_server(
+    name: web::Path<String>, app: web::Data<AppState>,
+    state: web::Data<ParentState>, store: web::Data<ConfigState>,
+    user: CurrentUser,
+) -> actix_web::Result<impl Responder> {
+    let res = match supervisor::restart(&state, &name).await {
+        Ok(()) => set_stopped(&app, &store, &user.name, &name, false),
+        Err(e) => Err(e),
+    };
+    Ok(lifecycle_response(&app, &name, res))
+}
+
+// 保存停止状态 主控端重启后保持
+fn set_stopped(
+    app: &AppState, store: &ConfigStore, admin: &str, name: &str,
+    stopped: bool,
+) -> anyhow::Result<()> {
+    if let Some(proxy) = app.lock().unwrap().get_mut(name) {
+        proxy.config.stopped = stopped;
+    }
+    let action = if stopped { "停止中转" } else { "启动中转" };
+    store.update(admin, &format!("{} {}", action, name), |configs| {
+        for c in configs.iter_mut().filter(|c| c.name == name) {
+            c.stopped = stopped;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct ResWorker {
+    pub session_id: u64,
+    pub ip: String,
     pub worker_name: String,
     pub worker_wallet: String,
+    // 统一钱包模式下实际登录矿池的钱包
+    pub pool_wallet: String,
     pub hash: String,
     pub last_subwork_time: String,
     pub online_time: String,
@@ -312,10 +330,29 @@ pub struct ResWorker {
     pub invalid_index: u64,
 }
 
+// 逻辑矿工 同一钱包+矿工名的所有连接合并
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ResLogicalWorker {
+    pub worker_name: String,
+    pub worker_wallet: String,
+    pub online: bool,
+    pub connections: u32,
+    pub sessions: Vec<u64>,
+    pub hash: String,
+    pub share_index: u64,
+    pub accept_index: u64,
+    pub invalid_index: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct OnlineWorkerResult {
+    // 每个连接一条
     pub workers: Vec<ResWorker>,
+    pub logical_workers: Vec<ResLogicalWorker>,
+    // 在线连接数
     pub online: u32,
+    // 在线的逻辑矿工数
+    pub logical_online: u32,
     pub online_time: String,
     pub config: Settings,
     pub fee_hash: String,
@@ -332,10 +369,12 @@ pub struct OnlineWorkerResult {
 
 // 展示选中的数据信息。以json格式返回
 #[get("/user/server/{name}")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
 async fn server(
     proxy_server_name: web::Path<String>, app: web::Data<AppState>,
 ) -> actix_web::Result<impl Responder> {
     let mut total_hash: f64 = 0.0;
+    let now = chrono::Utc::now().timestamp();
 
     let mut res: OnlineWorkerResult = OnlineWorkerResult::default();
     {
@@ -350,34 +389,54 @@ async fn server(
 
         for (name, server) in &*proxy_server {
             if *name == proxy_server_name.to_string() {
+                res.online_time = time_to_string(
+                    (now - server.started_at).max(0) as u64,
+                );
                 for r in &server.workers {
                     if r.is_online() {
                         online += 1;
                         total_hash += r.hash as f64;
                         res.workers.push(ResWorker {
+                            session_id: r.session_id,
+                            ip: r.ip.clone(),
                             worker_name: r.worker_name.clone(),
                             worker_wallet: r.worker_wallet.clone(),
+                            pool_wallet: r.pool_wallet.clone(),
                             hash: human_bytes(r.hash as f64),
                             share_index: r.share_index,
                             accept_index: r.accept_index,
                             invalid_index: r.invalid_index,
                             fee_accept_index: r.fee_accept_index,
-                            online_time: time_to_string(
-                                r.login_time.elapsed().as_secs(),
-                            ),
+                            online_time: time_to_string(r.online_secs(now)),
                             last_subwork_time: time_to_string(
-                                r.last_subwork_time.elapsed().as_secs(),
+                                r.idle_secs(now),
                             ),
                         });
 
                         share_index += r.share_index;
                         accept_index += r.accept_index;
                         reject_index += r.invalid_index;
-                        fee_accept_index += r.fee_share_index;
-                        fee_share_index += r.fee_accept_index;
+                        fee_accept_index += r.fee_accept_index;
+                        fee_share_index += r.fee_share_index;
                         fee_reject_index += r.fee_invalid_index;
                     }
                 }
+                for l in logical_workers(&server.workers) {
+                    if l.online {
+                        res.logical_online += 1;
+                    }
+                    res.logical_workers.push(ResLogicalWorker {
+                        worker_name: l.worker_name,
+                        worker_wallet: l.worker_wallet,
+                        online: l.online,
+                        connections: l.connections,
+                        sessions: l.sessions,
+                        hash: human_bytes(l.hash as f64),
+                        share_index: l.share_index,
+                        accept_index: l.accept_index,
+                        invalid_index: l.invalid_index,
+                    });
+                }
                 res.config = server.config.clone();
             }
         }
@@ -396,7 +455,7 @@ async fn server(
                 2,
             );
             res.share_rate = floor(
-                res.fee_share_index as f64 / res.accept_index as f64 * 100.0,
+                res.fee_share_index as f64 / res.share_index as f64 * 100.0,
                 2,
             );
         }
@@ -443,6 +502,7 @@ pub struct DashboardResult {
 
 // 展示选中的数据信息。以json格式返回
 #[post("/user/dashboard")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
 async fn dashboard(
     app: web::Data<AppState>,
 ) -> actix_web::Result<impl Responder> {
@@ -460,21 +520,22 @@ async fn dashboard(
         let mut fee_reject_index: u64 = 0;
 
         for (_, other_server) in &*proxy_server {
+            let mut server_hash: f64 = 0.0;
             for r in &other_server.workers {
                 if r.is_online() {
                     online += 1;
-                    total_hash += r.hash as f64;
+                    server_hash += r.hash as f64;
                     share_index += r.share_index;
                     accept_index += r.accept_index;
                     reject_index += r.invalid_index;
-                    fee_accept_index += r.fee_share_index;
-                    fee_share_index += r.fee_accept_index;
+                    fee_accept_index += r.fee_accept_index;
+                    fee_share_index += r.fee_share_index;
                     fee_reject_index += r.fee_invalid_index;
                 }
             }
 
-            fee_hash +=
-                total_hash as f64 * other_server.config.share_rate as f64;
+            total_hash += server_hash;
+            fee_hash += server_hash * other_server.config.share_rate as f64;
         }
 
         res.share_index += share_index;
@@ -494,7 +555,9 @@ async fn dashboard(
         res.rate =
             floor(res.accept_index as f64 / res.share_index as f64 * 100.0, 2);
         res.share_rate = floor(
-            res.fee_accept_index as f64 / res.accept_index as f64 * 100.0,
+            res.fee_share_index as f64
+                / (res.share_index + res.fee_share_index) as f64
+                * 100.0,
             2,
         );
     } else {
@@ -512,3 +575,56 @@ async fn dashboard(
         data: res,
     }))
 }
+
+#[tokio::test]
+async fn test_update_running_proxy_ports() {
+    use std::net::TcpListener;
+
+    use crate::web::{supervisor::ProxyRunner, OnlineWorker};
+
+    let pool = TcpListener::bind("127.0.0.1:0").unwrap();
+    let free_port = || {
+        TcpListener::bind("0.0.0.0:0").unwrap().local_addr().unwrap().port()
+    };
+    let mut req = CreateRequest {
+        name: "p".into(),
+        coin: "ETH".into(),
+        tcp_port: free_port() as u32,
+        pool_address: format!("tcp://{}", pool.local_addr().unwrap()),
+        ..Default::default()
+    };
+    let old = request_to_settings(&req, None).await.unwrap();
+
+    let dir = std::env::temp_dir()
+        .join(format!("update-server-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("configs.yaml");
+    std::fs::write(&path, serde_yaml::to_string(&vec![&old]).unwrap())
+        .unwrap();
+    let store = ConfigStore::new(&path, 2);
+    let state = ParentState {
+        app: Default::default(),
+        fee_stats: Default::default(),
+        fee_report: Default::default(),
+        events: std::sync::Arc::new(std::sync::Mutex::new(
+            crate::web::event_log::EventLog::new(10),
+        )),
+    };
+    state.app.lock().unwrap().insert(
+        "p".into(),
+        OnlineWorker::new(ProxyRunner::Stopped, old.clone()),
+    );
+
+    // 运行中的中转占用着自己的端口 端口不变时可以修改
+    let _running =
+        TcpListener::bind(("0.0.0.0", old.tcp_port as u16)).unwrap();
+    update("p", &req, &state, &store, "admin").await.unwrap();
+    assert!(request_to_settings(&req, None).await.is_err());
+
+    // 修改为其他被占用的端口时仍然检查
+    let other = TcpListener::bind("0.0.0.0:0").unwrap();
+    req.tcp_port = other.local_addr().unwrap().port() as u32;
+    assert!(update("p", &req, &state, &store, "admin").await.is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
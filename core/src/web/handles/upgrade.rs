@@ -0,0 +1,24 @@
+use actix_web::{post, web, Responder};
+use actix_web_grants::proc_macro::has_permissions;
+
+use crate::web::{data::*, upgrade::UpgradeState};
+
+// 替换可执行文件后调用。新进程就绪后当前进程排空全部中转并退出
+#[post("/user/upgrade")]
+#[has_permissions("ROLE_ADMIN")]
+async fn upgrade(
+    upgrade: web::Data<UpgradeState>,
+) -> actix_web::Result<impl Responder> {
+    match upgrade.start().await {
+        Ok(()) => Ok(web::Json(Response::<String> {
+            code: 20000,
+            message: "新进程已就绪 旧进程正在排空".into(),
+            data: String::default(),
+        })),
+        Err(e) => Ok(web::Json(Response::<String> {
+            code: 40000,
+            message: e.to_string(),
+            data: String::default(),
+        })),
+    }
+}
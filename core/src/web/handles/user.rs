@@ -1,36 +1,117 @@
-use actix_web::{get, post, web, Responder};
-use actix_web_grants::proc_macro::has_permissions;
+use actix_web::{get, post, web, HttpRequest, Responder};
+use actix_web_grants::proc_macro::{has_any_permission, has_permissions};
 use chrono::Utc;
+use serde::{Deserialize, Serialize};
 
 use crate::web::{
     data::*,
-    handles::auth::{generate_jwt, Claims},
+    handles::auth::{generate_jwt, Claims, CurrentUser},
+    login_session::{LoginSession, SessionState},
+    rate_limit::LoginThrottle,
+    totp,
+    user_store::{self, Role, UserInfo, UserState},
 };
 
 #[post("/user/login")]
 async fn login(
-    req: web::Json<LoginRequest>,
+    http: HttpRequest, req: web::Json<LoginRequest>,
+    users: web::Data<UserState>, sessions: web::Data<SessionState>,
+    throttle: web::Data<LoginThrottle>,
 ) -> actix_web::Result<impl Responder> {
-    let password = match std::env::var("MINING_PROXY_WEB_PASSWORD") {
-        Ok(t) => t,
-        Err(_) => "admin123".into(),
+    let username = if req.username.is_empty() {
+        "admin"
+    } else {
+        req.username.as_str()
+    };
+    let ip = match http.peer_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => String::new(),
     };
 
-    if password != req.password {
+    if let Err(wait) = throttle.check(&ip, username) {
+        return Ok(web::Json(Response::<TokenDataResponse> {
+            code: 40000,
+            message: format!(
+                "登录失败次数过多 请{}分钟后再试",
+                wait.as_secs() / 60 + 1
+            ),
+            data: TokenDataResponse::default(),
+        }));
+    }
+    let verified = match check_password(&users, username, &req.password).await
+    {
+        Ok(verified) => verified,
+        Err(e) => {
+            return Ok(web::Json(Response::<TokenDataResponse> {
+                code: 40000,
+                message: e.to_string(),
+                data: TokenDataResponse::default(),
+            }))
+        }
+    };
+    if !verified {
+        throttle.failed(&ip, username);
+        tracing::warn!("登录失败 {} {}", username, ip);
         return Ok(web::Json(Response::<TokenDataResponse> {
             code: 40000,
-            message: "密码不正确".into(),
+            message: "用户名或密码不正确".into(),
             data: TokenDataResponse::default(),
         }));
     }
+    // 开启两步验证的账号还需要验证码或恢复码
+    let totp_enabled = users.lock().unwrap().totp_enabled(username);
+    if totp_enabled {
+        let message = if req.code.is_empty() {
+            "请输入两步验证码".to_string()
+        } else {
+            match check_totp(&users, username, &req.code).await {
+                Ok(true) => String::new(),
+                Ok(false) => {
+                    throttle.failed(&ip, username);
+                    tracing::warn!("两步验证失败 {} {}", username, ip);
+                    "两步验证码不正确".to_string()
+                }
+                Err(e) => e.to_string(),
+            }
+        };
+        if !message.is_empty() {
+            return Ok(web::Json(Response::<TokenDataResponse> {
+                code: 40000,
+                message,
+                data: TokenDataResponse {
+                    token: String::new(),
+                    totp: true,
+                },
+            }));
+        }
+    }
+    throttle.succeeded(username);
+
     let iat = Utc::now();
     let exp = iat + chrono::Duration::days(1);
-    if let Ok(jwt_token) = generate_jwt(Claims::new("mining_proxy".into(), exp))
+    let jti = match sessions.lock().unwrap().create(
+        username,
+        &ip,
+        exp.timestamp(),
+    ) {
+        Ok(jti) => jti,
+        Err(e) => {
+            return Ok(web::Json(Response::<TokenDataResponse> {
+                code: 40000,
+                message: e.to_string(),
+                data: TokenDataResponse::default(),
+            }))
+        }
+    };
+    if let Ok(jwt_token) = generate_jwt(Claims::new(username.into(), jti, exp))
     {
         Ok(web::Json(Response::<TokenDataResponse> {
             code: 20000,
             message: "".into(),
-            data: TokenDataResponse { token: jwt_token },
+            data: TokenDataResponse {
+                token: jwt_token,
+                totp: false,
+            },
         }))
     } else {
         Ok(web::Json(Response::<TokenDataResponse> {
@@ -42,26 +123,376 @@ async fn login(
 }
 
 #[get("/user/info")]
-#[has_permissions("ROLE_ADMIN")]
-async fn info() -> actix_web::Result<impl Responder> {
+#[has_any_permission("ROLE_VIEWER", "ROLE_TOTP_SETUP")]
+async fn info(
+    user: CurrentUser, users: web::Data<UserState>,
+) -> actix_web::Result<impl Responder> {
+    let roles = match users.lock().unwrap().role(&user.name) {
+        Some(role) => vec![role.as_str().into()],
+        None => vec![],
+    };
     Ok(web::Json(Response::<InfoResponse> {
         code: 20000,
         message: "".into(),
         data: InfoResponse {
-            roles: vec!["admin".into()],
+            roles,
             introduction: "".into(),
             avatar: "".into(),
-            name: "admin".into(),
+            name: user.name,
         },
     }))
 }
 
+// 注销当前 token
 #[post("/user/logout")]
+#[has_any_permission("ROLE_VIEWER", "ROLE_TOTP_SETUP")]
+async fn logout(
+    user: CurrentUser, sessions: web::Data<SessionState>,
+) -> actix_web::Result<impl Responder> {
+    let res = sessions.lock().unwrap().revoke(&user.session).map(|_| ());
+    Ok(web::Json(user_response(res)))
+}
+
+// 注销当前账号在所有设备上的登录
+#[post("/user/logout_all")]
+#[has_permissions("ROLE_VIEWER")]
+async fn logout_all(
+    user: CurrentUser, sessions: web::Data<SessionState>,
+) -> actix_web::Result<impl Responder> {
+    let res = sessions.lock().unwrap().revoke_user(&user.name, None);
+    Ok(web::Json(user_response(res.map(|_| ()))))
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionInfo {
+    #[serde(flatten)]
+    pub session: LoginSession,
+    // 是否为发起请求的会话
+    pub current: bool,
+}
+
+// 有效的登录会话。管理员可查看全部账号的
+#[get("/user/sessions")]
+#[has_permissions("ROLE_VIEWER")]
+async fn login_sessions(
+    user: CurrentUser, users: web::Data<UserState>,
+    sessions: web::Data<SessionState>,
+) -> actix_web::Result<impl Responder> {
+    let filter = if is_admin(&users, &user.name) {
+        None
+    } else {
+        Some(user.name.as_str())
+    };
+    let data = sessions
+        .lock()
+        .unwrap()
+        .list(filter, Utc::now().timestamp())
+        .into_iter()
+        .map(|session| SessionInfo {
+            current: session.id == user.session,
+            session,
+        })
+        .collect();
+    Ok(web::Json(Response::<Vec<SessionInfo>> {
+        code: 20000,
+        message: "".into(),
+        data,
+    }))
+}
+
+// 注销指定会话。非管理员只能注销自己的
+#[post("/user/sessions/{id}/revoke")]
+#[has_permissions("ROLE_VIEWER")]
+async fn revoke_session(
+    id: web::Path<String>, user: CurrentUser, users: web::Data<UserState>,
+    sessions: web::Data<SessionState>,
+) -> actix_web::Result<impl Responder> {
+    let is_admin = is_admin(&users, &user.name);
+    let mut sessions = sessions.lock().unwrap();
+    let res = match sessions.get(&id) {
+        Some(s) if is_admin || s.username == user.name => {
+            sessions.revoke(&id).map(|_| ())
+        }
+        _ => Err(anyhow::anyhow!("未找到此会话")),
+    };
+    Ok(web::Json(user_response(res)))
+}
+
+// 修改自己的密码
+#[post("/user/password")]
+#[has_permissions("ROLE_VIEWER")]
+async fn change_password(
+    req: web::Json<ChangePasswordRequest>, user: CurrentUser,
+    users: web::Data<UserState>, sessions: web::Data<SessionState>,
+) -> actix_web::Result<impl Responder> {
+    let res = match check_password(&users, &user.name, &req.old_password).await
+    {
+        Ok(true) => match new_password(&req.new_password).await {
+            Ok(hash) => {
+                users.lock().unwrap().update(&user.name, Some(hash), None)
+            }
+            Err(e) => Err(e),
+        },
+        Ok(false) => Err(anyhow::anyhow!("原密码不正确")),
+        Err(e) => Err(e),
+    };
+    // 其他设备需要用新密码重新登录
+    let res = res.and_then(|_| {
+        sessions
+            .lock()
+            .unwrap()
+            .revoke_user(&user.name, Some(&user.session))
+            .map(|_| ())
+    });
+    Ok(web::Json(user_response(res)))
+}
+
+// 生成新的验证器密钥 需要再调用 confirm 才生效
+#[post("/user/totp/enroll")]
+#[has_any_permission("ROLE_VIEWER", "ROLE_TOTP_SETUP")]
+async fn totp_enroll(
+    user: CurrentUser, users: web::Data<UserState>,
+) -> actix_web::Result<impl Responder> {
+    match users.lock().unwrap().enroll_totp(&user.name) {
+        Ok(secret) => Ok(web::Json(Response::<TotpEnrollResponse> {
+            code: 20000,
+            message: "".into(),
+            data: TotpEnrollResponse {
+                uri: totp::otpauth_uri(&user.name, &secret),
+                secret,
+            },
+        })),
+        Err(e) => Ok(web::Json(Response::<TotpEnrollResponse> {
+            code: 40000,
+            message: e.to_string(),
+            data: TotpEnrollResponse::default(),
+        })),
+    }
+}
+
+// 验证码正确后开启两步验证 返回恢复码
+#[post("/user/totp/confirm")]
+#[has_any_permission("ROLE_VIEWER", "ROLE_TOTP_SETUP")]
+async fn totp_confirm(
+    req: web::Json<TotpCodeRequest>, user: CurrentUser,
+    users: web::Data<UserState>,
+) -> actix_web::Result<impl Responder> {
+    let res = match recovery_codes().await {
+        Ok((codes, hashes)) => users
+            .lock()
+            .unwrap()
+            .confirm_totp(&user.name, &req.code, Utc::now().timestamp(), hashes)
+            .map(|_| codes),
+        Err(e) => Err(e),
+    };
+    Ok(web::Json(codes_response(res)))
+}
+
+// 重新生成恢复码 需要当前的验证码
+#[post("/user/totp/recovery_codes")]
+#[has_permissions("ROLE_VIEWER")]
+async fn totp_recovery_codes(
+    req: web::Json<TotpCodeRequest>, user: CurrentUser,
+    users: web::Data<UserState>,
+) -> actix_web::Result<impl Responder> {
+    let res = match check_totp(&users, &user.name, &req.code).await {
+        Ok(true) => match recovery_codes().await {
+            Ok((codes, hashes)) => users
+                .lock()
+                .unwrap()
+                .set_recovery_codes(&user.name, hashes)
+                .map(|_| codes),
+            Err(e) => Err(e),
+        },
+        Ok(false) => Err(anyhow::anyhow!("验证码不正确")),
+        Err(e) => Err(e),
+    };
+    Ok(web::Json(codes_response(res)))
+}
+
+// 关闭两步验证 需要密码和验证码
+#[post("/user/totp/disable")]
+#[has_permissions("ROLE_VIEWER")]
+async fn totp_disable(
+    req: web::Json<DisableTotpRequest>, user: CurrentUser,
+    users: web::Data<UserState>,
+) -> actix_web::Result<impl Responder> {
+    let res = match check_password(&users, &user.name, &req.password).await {
+        Ok(true) => match check_totp(&users, &user.name, &req.code).await {
+            Ok(true) => users.lock().unwrap().disable_totp(&user.name),
+            Ok(false) => Err(anyhow::anyhow!("验证码不正确")),
+            Err(e) => Err(e),
+        },
+        Ok(false) => Err(anyhow::anyhow!("密码不正确")),
+        Err(e) => Err(e),
+    };
+    Ok(web::Json(user_response(res)))
+}
+
+#[get("/user/users")]
 #[has_permissions("ROLE_ADMIN")]
-async fn logout() -> actix_web::Result<impl Responder> {
-    Ok(web::Json(Response::<String> {
+async fn user_list(
+    users: web::Data<UserState>,
+) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(Response::<Vec<UserInfo>> {
         code: 20000,
         message: "".into(),
-        data: "".into(),
+        data: users.lock().unwrap().list(),
     }))
 }
+
+#[post("/user/users")]
+#[has_permissions("ROLE_ADMIN")]
+async fn add_user(
+    req: web::Json<AddUserRequest>, users: web::Data<UserState>,
+) -> actix_web::Result<impl Responder> {
+    let res = match new_password(&req.password).await {
+        Ok(hash) => users.lock().unwrap().add(&req.username, hash, req.role),
+        Err(e) => Err(e),
+    };
+    Ok(web::Json(user_response(res)))
+}
+
+// 修改密码 角色或两步验证设置
+#[post("/user/users/{username}")]
+#[has_permissions("ROLE_ADMIN")]
+async fn update_user(
+    username: web::Path<String>, req: web::Json<UpdateUserRequest>,
+    users: web::Data<UserState>, sessions: web::Data<SessionState>,
+) -> actix_web::Result<impl Responder> {
+    let password = match &req.password {
+        Some(password) => match new_password(password).await {
+            Ok(hash) => Some(hash),
+            Err(e) => return Ok(web::Json(user_response(Err(e)))),
+        },
+        None => None,
+    };
+    let res = {
+        let mut users = users.lock().unwrap();
+        users
+            .update(&username, password, req.role)
+            .and_then(|_| {
+                if req.totp_required.is_some() || req.reset_totp {
+                    users.update_totp(
+                        &username,
+                        req.totp_required,
+                        req.reset_totp,
+                    )
+                } else {
+                    Ok(())
+                }
+            })
+    };
+    // 重置密码或验证器后注销此账号的全部登录
+    let res = match res {
+        Ok(()) if req.password.is_some() || req.reset_totp => sessions
+            .lock()
+            .unwrap()
+            .revoke_user(&username, None)
+            .map(|_| ()),
+        res => res,
+    };
+    Ok(web::Json(user_response(res)))
+}
+
+#[post("/user/users/{username}/delete")]
+#[has_permissions("ROLE_ADMIN")]
+async fn delete_user(
+    username: web::Path<String>, user: CurrentUser, users: web::Data<UserState>,
+    sessions: web::Data<SessionState>,
+) -> actix_web::Result<impl Responder> {
+    let res = if *username == user.name {
+        Err(anyhow::anyhow!("不能删除当前登录的账号"))
+    } else {
+        users.lock().unwrap().delete(&username)
+    };
+    // 注销此账号的全部登录 重新创建同名账号时旧 token 不会恢复
+    let res = res.and_then(|_| {
+        sessions
+            .lock()
+            .unwrap()
+            .revoke_user(&username, None)
+            .map(|_| ())
+    });
+    Ok(web::Json(user_response(res)))
+}
+
+// argon2 较慢 复制出哈希后在线程池中校验 不占用账号锁和异步线程
+async fn check_password(
+    users: &UserState, username: &str, password: &str,
+) -> anyhow::Result<bool> {
+    let hash = users.lock().unwrap().password_hash(username);
+    let password = password.to_string();
+    // 用户不存在时同样校验一次 两种情况耗时相同
+    Ok(web::block(move || match hash {
+        Some(hash) => user_store::verify_password(&password, &hash),
+        None => {
+            user_store::verify_password(
+                &password,
+                &user_store::dummy_password_hash(),
+            );
+            false
+        }
+    })
+    .await?)
+}
+
+// 验证码或恢复码通过后记录 同一个只能使用一次
+async fn check_totp(
+    users: &UserState, username: &str, code: &str,
+) -> anyhow::Result<bool> {
+    let t = match users.lock().unwrap().totp(username) {
+        Some(t) => t,
+        None => return Ok(false),
+    };
+    let code = code.to_string();
+    let now = Utc::now().timestamp();
+    match web::block(move || user_store::check_totp(&t, &code, now)).await? {
+        Some(matched) => users.lock().unwrap().consume_totp(username, matched),
+        None => Ok(false),
+    }
+}
+
+async fn new_password(password: &str) -> anyhow::Result<String> {
+    let password = password.to_string();
+    web::block(move || user_store::new_password(&password)).await?
+}
+
+// 恢复码明文和哈希
+async fn recovery_codes() -> anyhow::Result<(Vec<String>, Vec<String>)> {
+    web::block(user_store::new_recovery_codes).await?
+}
+
+fn is_admin(users: &UserState, name: &str) -> bool {
+    users.lock().unwrap().role(name) == Some(Role::Admin)
+}
+
+fn codes_response(res: anyhow::Result<Vec<String>>) -> Response<Vec<String>> {
+    match res {
+        Ok(codes) => Response {
+            code: 20000,
+            message: "".into(),
+            data: codes,
+        },
+        Err(e) => Response {
+            code: 40000,
+            message: e.to_string(),
+            data: vec![],
+        },
+    }
+}
+
+fn user_response(res: anyhow::Result<()>) -> Response<String> {
+    match res {
+        Ok(()) => Response {
+            code: 20000,
+            message: "".into(),
+            data: String::default(),
+        },
+        Err(e) => Response {
+            code: 40000,
+            message: e.to_string(),
+            data: String::default(),
+        },
+    }
+}
@@ -0,0 +1,72 @@
+use actix_web::{get, web, Responder};
+use actix_web_grants::proc_macro::has_any_permission;
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util::{human_bytes, time_to_string},
+    web::{data::*, AppState},
+};
+
+#[derive(Deserialize, Debug, Default)]
+pub struct OfflineQuery {
+    // 只看某个中转。为空返回全部
+    #[serde(default)]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct OfflineWorker {
+    pub proxy: String,
+    pub session_id: u64,
+    pub worker_name: String,
+    pub worker_wallet: String,
+    pub ip: String,
+    // 下线前最后上报的算力
+    pub hash: String,
+    pub last_seen: i64,
+    pub last_seen_time: String,
+    pub offline_time: String,
+    pub reason: String,
+}
+
+// 离线矿工列表 最近下线的排在前面
+#[get("/user/offline_workers")]
+#[has_any_permission("ROLE_VIEWER", "SCOPE_READ_STATS")]
+async fn offline_workers(
+    query: web::Query<OfflineQuery>, app: web::Data<AppState>,
+) -> actix_web::Result<impl Responder> {
+    let now = chrono::Utc::now().timestamp();
+    let mut res = vec![];
+    for (name, server) in app.lock().unwrap().iter() {
+        if !query.name.is_empty() && *name != query.name {
+            continue;
+        }
+
+        for w in server.workers.iter().filter(|w| !w.is_online()) {
+            res.push(OfflineWorker {
+                proxy: name.clone(),
+                session_id: w.session_id,
+                worker_name: w.worker_name.clone(),
+                worker_wallet: w.worker_wallet.clone(),
+                ip: w.ip.clone(),
+                hash: human_bytes(w.hash as f64),
+                last_seen: w.offline_at,
+                last_seen_time: chrono::Local
+                    .timestamp_opt(w.offline_at, 0)
+                    .single()
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+                offline_time: time_to_string((now - w.offline_at).max(0) as u64),
+                reason: w.disconnect_reason.clone(),
+            });
+        }
+    }
+    res.sort_by_key(|w| std::cmp::Reverse(w.last_seen));
+
+    Ok(web::Json(Response::<Vec<OfflineWorker>> {
+        code: 20000,
+        message: "".into(),
+        data: res,
+    }))
+}
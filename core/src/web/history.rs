@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Result};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::{state::Worker, web::AppState};
+
+pub type HistoryState = std::sync::Arc<std::sync::Mutex<HistoryStore>>;
+
+// 历史数据保存设置。通过环境变量修改
+#[derive(Debug, Clone)]
+pub struct HistoryConfig {
+    pub path: String,
+    // 分钟数据保留时长(小时) 之后合并为小时数据
+    pub minute_hours: i64,
+    // 小时数据保留天数
+    pub retention_days: i64,
+    // 矿池份额难度 用于计算有效算力
+    pub share_difficulty: u64,
+}
+
+impl HistoryConfig {
+    pub fn from_env() -> Self {
+        fn env<T: std::str::FromStr>(key: &str, default: T) -> T {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            path: env("MINING_PROXY_HISTORY_DB", "history.db".to_string()),
+            minute_hours: env("MINING_PROXY_HISTORY_MINUTE_HOURS", 48),
+            retention_days: env("MINING_PROXY_HISTORY_DAYS", 30),
+            share_difficulty: env("MINING_PROXY_SHARE_DIFF", 4_000_000_000),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryPoint {
+    pub ts: i64,
+    // 矿机上报算力
+    pub hash: u64,
+    // 按有效份额计算的算力
+    pub effective: u64,
+    pub shares: u64,
+    pub accepted: u64,
+    pub rejects: u64,
+    pub fee_shares: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Cumulative {
+    shares: u64,
+    accepted: u64,
+    rejects: u64,
+    fee_shares: u64,
+}
+
+impl Cumulative {
+    fn from_worker(worker: &Worker) -> Self {
+        Self {
+            shares: worker.share_index,
+            accepted: worker.accept_index,
+            rejects: worker.invalid_index,
+            fee_shares: worker.fee_share_index,
+        }
+    }
+}
+
+// 矿工历史数据。按分钟记录，过期后合并为小时数据
+pub struct HistoryStore {
+    conn: Connection,
+    config: HistoryConfig,
+    // 每个会话上一分钟的累计值
+    last: HashMap<(String, u64), Cumulative>,
+}
+
+const TABLES: [&str; 2] = ["worker_minute", "worker_hour"];
+// 单次查询最多返回的数据点
+const MAX_POINTS: i64 = 5000;
+
+impl HistoryStore {
+    pub fn open(config: HistoryConfig) -> Result<Self> {
+        let conn = Connection::open(&config.path)?;
+        Self::with_connection(conn, config)
+    }
+
+    fn with_connection(
+        conn: Connection, config: HistoryConfig,
+    ) -> Result<Self> {
+        for table in TABLES {
+            conn.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    proxy TEXT NOT NULL,
+                    worker TEXT NOT NULL,
+                    ts INTEGER NOT NULL,
+                    hash INTEGER NOT NULL,
+                    effective INTEGER NOT NULL,
+                    shares INTEGER NOT NULL,
+                    accepted INTEGER NOT NULL,
+                    rejects INTEGER NOT NULL,
+                    fee_shares INTEGER NOT NULL,
+                    PRIMARY KEY (proxy, worker, ts)
+                );
+                CREATE INDEX IF NOT EXISTS {table}_ts ON {table} (ts);",
+                table = table
+            ))?;
+        }
+
+        Ok(Self {
+            conn,
+            config,
+            last: HashMap::new(),
+        })
+    }
+
+    // 每分钟调用一次 记录所有在线矿工
+    pub fn sample(&mut self, app: &AppState) -> Result<()> {
+        let ts = chrono::Utc::now().timestamp() / 60 * 60;
+        let workers: Vec<(String, Worker)> = {
+            let servers = app.lock().unwrap();
+            servers
+                .iter()
+                .flat_map(|(name, server)| {
+                    server
+                        .workers
+                        .iter()
+                        .map(move |w| (name.clone(), w.clone()))
+                })
+                .collect()
+        };
+
+        self.record_at(ts, &workers)?;
+        self.compact(ts)
+    }
+
+    fn record_at(
+        &mut self, ts: i64, workers: &[(String, Worker)],
+    ) -> Result<()> {
+        fn delta(now: u64, last: u64) -> u64 {
+            if now >= last {
+                now - last
+            } else {
+                now
+            }
+        }
+
+        // 按会话计算增量 再合并到逻辑矿工
+        let mut last = HashMap::new();
+        let mut points: BTreeMap<(&str, &str), HistoryPoint> = BTreeMap::new();
+        for (proxy, worker) in workers {
+            if !worker.is_online() {
+                continue;
+            }
+
+            let now = Cumulative::from_worker(worker);
+            // 第一次记录时没有上一分钟的累计值 只记录算力
+            let prev = self
+                .last
+                .get(&(proxy.clone(), worker.session_id))
+                .cloned()
+                .unwrap_or(now);
+            last.insert((proxy.clone(), worker.session_id), now);
+
+            let p = points
+                .entry((proxy.as_str(), worker.worker.as_str()))
+                .or_default();
+            p.hash += worker.hash;
+            p.shares += delta(now.shares, prev.shares);
+            p.accepted += delta(now.accepted, prev.accepted);
+            p.rejects += delta(now.rejects, prev.rejects);
+            p.fee_shares += delta(now.fee_shares, prev.fee_shares);
+        }
+        self.last = last;
+
+        let tx = self.conn.transaction()?;
+        for ((proxy, worker), p) in points {
+            tx.execute(
+                "INSERT OR REPLACE INTO worker_minute
+                 (proxy, worker, ts, hash, effective, shares, accepted, rejects, fee_shares)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    proxy,
+                    worker,
+                    ts,
+                    p.hash as i64,
+                    (p.accepted * self.config.share_difficulty / 60) as i64,
+                    p.shares as i64,
+                    p.accepted as i64,
+                    p.rejects as i64,
+                    p.fee_shares as i64,
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // 过期的分钟数据合并为小时数据 删除超过保留天数的数据
+    fn compact(&mut self, now: i64) -> Result<()> {
+        let minute_before =
+            (now - self.config.minute_hours * 3600) / 3600 * 3600;
+        let hour_before = now - self.config.retention_days * 86400;
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO worker_hour
+             SELECT proxy, worker, ts / 3600 * 3600,
+                    CAST(AVG(hash) AS INTEGER), CAST(AVG(effective) AS INTEGER),
+                    SUM(shares), SUM(accepted), SUM(rejects), SUM(fee_shares)
+             FROM worker_minute WHERE ts < ?1
+             GROUP BY proxy, worker, ts / 3600",
+            params![minute_before],
+        )?;
+        tx.execute(
+            "DELETE FROM worker_minute WHERE ts < ?1",
+            params![minute_before],
+        )?;
+        tx.execute(
+            "DELETE FROM worker_hour WHERE ts < ?1",
+            params![hour_before],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // 查询历史。worker 为空时返回整个中转的合计
+    // 查询范围超出分钟数据时按小时返回 早于保留天数的部分不查询
+    pub fn query(
+        &self, proxy: &str, worker: &str, from: i64, to: i64,
+    ) -> Result<Vec<HistoryPoint>> {
+        if from > to {
+            bail!("开始时间不能晚于结束时间");
+        }
+        let now = chrono::Utc::now().timestamp();
+        let from = from.max(now - self.config.retention_days * 86400);
+        let hourly = from < now - self.config.minute_hours * 3600;
+        self.query_range(hourly, proxy, worker, from, to)
+    }
+
+    fn query_range(
+        &self, hourly: bool, proxy: &str, worker: &str, from: i64, to: i64,
+    ) -> Result<Vec<HistoryPoint>> {
+        const FILTER: &str = "proxy = ?1 AND (?2 = '' OR worker = ?2) \
+                              AND ts >= ?3 AND ts <= ?4";
+        // 按小时查询时 尚未合并的分钟数据临时按小时合并
+        let source = if hourly {
+            format!(
+                "SELECT ts, worker, hash, effective, shares, accepted,
+                        rejects, fee_shares
+                 FROM worker_hour WHERE {filter}
+                 UNION ALL
+                 SELECT ts / 3600 * 3600, worker,
+                        CAST(AVG(hash) AS INTEGER),
+                        CAST(AVG(effective) AS INTEGER), SUM(shares),
+                        SUM(accepted), SUM(rejects), SUM(fee_shares)
+                 FROM worker_minute WHERE {filter}
+                 GROUP BY worker, ts / 3600",
+                filter = FILTER
+            )
+        } else {
+            format!("SELECT * FROM worker_minute WHERE {}", FILTER)
+        };
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT ts, SUM(hash), SUM(effective), SUM(shares), SUM(accepted),
+                    SUM(rejects), SUM(fee_shares)
+             FROM ({}) GROUP BY ts ORDER BY ts LIMIT {}",
+            source, MAX_POINTS
+        ))?;
+
+        let rows = stmt.query_map(params![proxy, worker, from, to], |row| {
+            Ok(HistoryPoint {
+                ts: row.get(0)?,
+                hash: row.get::<_, i64>(1)? as u64,
+                effective: row.get::<_, i64>(2)? as u64,
+                shares: row.get::<_, i64>(3)? as u64,
+                accepted: row.get::<_, i64>(4)? as u64,
+                rejects: row.get::<_, i64>(5)? as u64,
+                fee_shares: row.get::<_, i64>(6)? as u64,
+            })
+        })?;
+
+        let mut points = vec![];
+        for row in rows {
+            points.push(row?);
+        }
+        Ok(points)
+    }
+}
+
+// 后台每分钟采样一次
+pub async fn run_sampler(app: AppState, history: HistoryState) {
+    let mut interval =
+        tokio::time::interval(tokio::time::Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let app = app.clone();
+        let history = history.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            history.lock().unwrap().sample(&app)
+        })
+        .await;
+
+        match res {
+            Ok(Err(e)) => tracing::error!("保存历史数据失败 {}", e),
+            Err(e) => tracing::error!("保存历史数据失败 {}", e),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_history_downsample() {
+    let config = HistoryConfig {
+        path: "".into(),
+        minute_hours: 1,
+        retention_days: 1,
+        share_difficulty: 60,
+    };
+    let mut store = HistoryStore::with_connection(
+        Connection::open_in_memory().unwrap(),
+        config,
+    )
+    .unwrap();
+
+    let mut w =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    w.hash = 100;
+    w.accept_index = 10;
+    store.record_at(0, &[("proxy".into(), w.clone())]).unwrap();
+    w.hash = 300;
+    w.accept_index = 15;
+    w.share_index = 16;
+    store.record_at(60, &[("proxy".into(), w.clone())]).unwrap();
+
+    let points = store.query_range(false, "proxy", "", 0, 60).unwrap();
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[1].accepted, 5);
+    assert_eq!(points[1].effective, 5);
+    let hours = store.query_range(true, "proxy", "", 0, 3600).unwrap();
+    assert_eq!(hours[0].hash, 200);
+
+    // 两小时后分钟数据合并为小时数据
+    store.compact(7200).unwrap();
+    assert!(store
+        .query_range(false, "proxy", "", 0, 60)
+        .unwrap()
+        .is_empty());
+    let hours = store.query_range(true, "proxy", "w", 0, 0).unwrap();
+    assert!(hours.is_empty());
+    let hours = store.query_range(true, "proxy", "0xabc.w1", 0, 0).unwrap();
+    assert_eq!(hours[0].hash, 200);
+    assert_eq!(hours[0].shares, 16);
+
+    assert!(store.query("proxy", "", 60, 0).is_err());
+
+    // 超过保留天数删除
+    store.compact(86400 * 2).unwrap();
+    assert!(store
+        .query_range(true, "proxy", "", 0, 86400)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::util::{write_atomic, PRIVATE_FILE_MODE};
+
+// 默认空闲超过2小时需要重新登录
+const IDLE_MINUTES: i64 = 120;
+// 最后使用时间最多间隔多久保存一次
+const SAVE_INTERVAL_SECS: i64 = 60;
+
+pub type SessionState = Arc<Mutex<LoginSessions>>;
+
+// 一次登录。token 中的 jti 为会话编号
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoginSession {
+    pub id: String,
+    pub username: String,
+    // 最后一次使用的IP
+    pub ip: String,
+    pub created_at: i64,
+    pub last_used: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct SessionFile {
+    #[serde(default)]
+    sessions: Vec<LoginSession>,
+}
+
+// 已登录的会话 注销后 token 立即失效。保存到文件 重启和升级后保持登录
+pub struct LoginSessions {
+    path: String,
+    // 空闲超过此秒数失效
+    idle: i64,
+    sessions: HashMap<String, LoginSession>,
+    saved_at: i64,
+}
+
+impl LoginSessions {
+    pub fn new(path: &str, idle: i64, sessions: Vec<LoginSession>) -> Self {
+        Self {
+            path: path.to_string(),
+            idle,
+            sessions: sessions.into_iter().map(|s| (s.id.clone(), s)).collect(),
+            saved_at: 0,
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var("MINING_PROXY_SESSIONS")
+            .unwrap_or_else(|_| "sessions.yaml".into());
+        let idle = std::env::var("MINING_PROXY_SESSION_IDLE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(IDLE_MINUTES);
+        Self::load(&path, idle * 60)
+    }
+
+    pub fn load(path: &str, idle: i64) -> Result<Self> {
+        let file: SessionFile = match std::fs::read_to_string(path) {
+            Ok(s) => serde_yaml::from_str(&s)
+                .map_err(|e| anyhow!("{} 格式错误 {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                SessionFile::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self::new(path, idle, file.sessions))
+    }
+
+    fn save(&mut self, now: i64) -> Result<()> {
+        self.saved_at = now;
+        if self.path.is_empty() {
+            return Ok(());
+        }
+        let file = SessionFile {
+            sessions: self.sessions.values().cloned().collect(),
+        };
+        write_atomic(
+            std::path::Path::new(&self.path),
+            serde_yaml::to_string(&file)?.as_bytes(),
+            Some(PRIVATE_FILE_MODE),
+        )
+    }
+
+    // 登录成功后创建 返回会话编号
+    pub fn create(
+        &mut self, username: &str, ip: &str, expires_at: i64,
+    ) -> Result<String> {
+        let now = chrono::Utc::now().timestamp();
+        self.purge(now);
+
+        let mut id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut id);
+        let id = hex::encode(id);
+        self.sessions.insert(
+            id.clone(),
+            LoginSession {
+                id: id.clone(),
+                username: username.to_string(),
+                ip: ip.to_string(),
+                created_at: now,
+                last_used: now,
+                expires_at,
+            },
+        );
+        self.save(now)?;
+        Ok(id)
+    }
+
+    // 会话有效时记录本次使用
+    pub fn touch(
+        &mut self, id: &str, username: &str, ip: &str, now: i64,
+    ) -> bool {
+        let idle = self.idle;
+        let session = match self.sessions.get_mut(id) {
+            Some(s) if s.username == username => s,
+            _ => return false,
+        };
+        if session.expires_at <= now || session.last_used + idle <= now {
+            return false;
+        }
+        session.last_used = now;
+        if session.ip != ip {
+            session.ip = ip.to_string();
+        }
+        if now - self.saved_at >= SAVE_INTERVAL_SECS {
+            self.purge(now);
+            if let Err(e) = self.save(now) {
+                tracing::warn!("无法保存登录会话 {}", e);
+            }
+        }
+        true
+    }
+
+    pub fn get(&self, id: &str) -> Option<&LoginSession> {
+        self.sessions.get(id)
+    }
+
+    pub fn revoke(&mut self, id: &str) -> Result<bool> {
+        let removed = self.sessions.remove(id).is_some();
+        if removed {
+            self.save(chrono::Utc::now().timestamp())?;
+        }
+        Ok(removed)
+    }
+
+    // 注销账号的全部会话 keep 除外。返回注销的数量
+    pub fn revoke_user(
+        &mut self, username: &str, keep: Option<&str>,
+    ) -> Result<usize> {
+        let before = self.sessions.len();
+        self.sessions.retain(|id, s| {
+            s.username != username || Some(id.as_str()) == keep
+        });
+        let removed = before - self.sessions.len();
+        if removed > 0 {
+            self.save(chrono::Utc::now().timestamp())?;
+        }
+        Ok(removed)
+    }
+
+    // 未过期的会话 最近使用的在前。username 为空时返回全部账号的
+    pub fn list(&self, username: Option<&str>, now: i64) -> Vec<LoginSession> {
+        let mut sessions: Vec<LoginSession> = self
+            .sessions
+            .values()
+            .filter(|s| self.alive(s, now))
+            .filter(|s| match username {
+                Some(u) => s.username == u,
+                None => true,
+            })
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.last_used));
+        sessions
+    }
+
+    fn alive(&self, s: &LoginSession, now: i64) -> bool {
+        s.expires_at > now && s.last_used + self.idle > now
+    }
+
+    fn purge(&mut self, now: i64) {
+        let idle = self.idle;
+        self.sessions
+            .retain(|_, s| s.expires_at > now && s.last_used + idle > now);
+    }
+}
+
+#[test]
+fn test_login_sessions() {
+    let mut sessions = LoginSessions::new("", 600, vec![]);
+    let now = chrono::Utc::now().timestamp();
+    let a = sessions.create("admin", "1.1.1.1", now + 3600).unwrap();
+    let b = sessions.create("admin", "2.2.2.2", now + 3600).unwrap();
+    let c = sessions.create("tech", "3.3.3.3", now + 3600).unwrap();
+    assert_ne!(a, b);
+
+    // 账号不符或空闲超时无效
+    assert!(sessions.touch(&a, "admin", "4.4.4.4", now + 10));
+    assert!(!sessions.touch(&a, "tech", "4.4.4.4", now + 10));
+    assert!(!sessions.touch(&b, "admin", "2.2.2.2", now + 700));
+    assert_eq!(sessions.get(&a).unwrap().ip, "4.4.4.4");
+
+    let list = sessions.list(Some("admin"), now + 20);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].id, a);
+
+    // 退出全部 只保留当前会话
+    assert_eq!(sessions.revoke_user("admin", Some(&a)).unwrap(), 1);
+    assert!(sessions.touch(&a, "admin", "4.4.4.4", now + 30));
+    assert!(sessions.revoke(&a).unwrap());
+    assert!(!sessions.touch(&a, "admin", "4.4.4.4", now + 40));
+    assert!(sessions.touch(&c, "tech", "3.3.3.3", now + 40));
+}
@@ -1,7 +1,30 @@
-use crate::{state::Worker, util::config::Settings};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc::UnboundedSender, oneshot};
 
+use crate::{
+    ipc::CommandReply,
+    state::Worker,
+    util::{config::Settings, fee_rule::FeeRule},
+};
+
+use self::supervisor::{ProxyRunner, ProxyStatus, Supervision};
+
+pub mod alert;
+pub mod api_token;
+pub mod config_store;
 pub mod data;
+pub mod event_log;
+pub mod fee_report;
+pub mod fee_stats;
 pub mod handles;
+pub mod history;
+pub mod login_session;
+pub mod rate_limit;
+pub mod session;
+pub mod supervisor;
+pub mod totp;
+pub mod upgrade;
+pub mod user_store;
 // pub struct AppState {
 //     pub global_count: std::sync::Arc<
 //         std::sync::Mutex<std::collections::HashMap<String, OnlineWorker>>,
@@ -13,8 +36,58 @@ pub type AppState = std::sync::Arc<
 >;
 
 pub struct OnlineWorker {
-    pub child: tokio::process::Child,
+    pub child: ProxyRunner,
     pub workers: Vec<Worker>,
     pub online: u32,
     pub config: Settings,
+    // 下发命令到代理进程。代理进程连接主控端后才会建立
+    pub child_tx: Option<UnboundedSender<ChildRequest>>,
+    // 代理进程启动时间 秒级时间戳
+    pub started_at: i64,
+    // 进程退出后自动重启
+    pub supervision: Supervision,
+}
+
+impl OnlineWorker {
+    pub fn new(mut child: ProxyRunner, config: Settings) -> Self {
+        Self {
+            supervision: Supervision::new(&mut child),
+            child,
+            workers: vec![],
+            online: 0,
+            config,
+            child_tx: None,
+            started_at: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    // 在后台停止的中转 主控端启动时不运行
+    pub fn stopped(config: Settings) -> Self {
+        let mut server = Self::new(ProxyRunner::Stopped, config);
+        server.supervision.status = ProxyStatus::Stopped;
+        server
+    }
+}
+
+// 主控端下发给代理进程的消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ChildCommand {
+    FeeRules(Vec<FeeRule>),
+    // 查询所有在线会话的实时状态
+    Snapshot,
+    // 按会话编号断开矿机
+    Disconnect(u64),
+    // 排空后代理退出。参数为等待会话结束的秒数
+    Drain(u64),
+    // 修改日志级别 如 debug info warn
+    SetLogLevel(String),
+    // 重新加载配置 无法直接生效的修改会在结果中列出
+    Reload(Box<Settings>),
+}
+
+// 下发的命令及执行结果的回传通道
+#[derive(Debug)]
+pub struct ChildRequest {
+    pub command: ChildCommand,
+    pub reply: oneshot::Sender<CommandReply>,
 }
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// 固定窗口限流。每个IP在一个窗口内最多请求 max 次
+pub struct RateLimiter {
+    max: u32,
+    window: Duration,
+    hits: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(max: u32, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().unwrap();
+
+        // 顺便清理过期的记录
+        if hits.len() > 10000 {
+            let window = self.window;
+            hits.retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+
+        let (start, count) = hits.entry(ip).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+
+        if *count >= self.max {
+            return false;
+        }
+
+        *count += 1;
+        true
+    }
+}
+
+struct Failures {
+    start: Instant,
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+// 登录失败锁定。同一IP或同一账号在窗口内失败次数过多时锁定一段时间
+pub struct LoginThrottle {
+    ip_max: u32,
+    user_max: u32,
+    window: Duration,
+    lockout: Duration,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl LoginThrottle {
+    pub fn new(
+        ip_max: u32, user_max: u32, window: Duration, lockout: Duration,
+    ) -> Self {
+        Self {
+            ip_max,
+            user_max,
+            window,
+            lockout,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 15分钟内同一IP失败20次或同一账号失败5次后锁定。
+    // 锁定时间由 MINING_PROXY_LOGIN_LOCKOUT 设置 默认15分钟
+    pub fn from_env() -> Self {
+        let lockout: u64 = std::env::var("MINING_PROXY_LOGIN_LOCKOUT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15);
+        Self::new(
+            20,
+            5,
+            Duration::from_secs(15 * 60),
+            Duration::from_secs(lockout * 60),
+        )
+    }
+
+    // 锁定中返回剩余时间
+    pub fn check(&self, ip: &str, username: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let failures = self.failures.lock().unwrap();
+        for key in [ip_key(ip), user_key(username)] {
+            if let Some(Failures {
+                locked_until: Some(until),
+                ..
+            }) = failures.get(&key)
+            {
+                if *until > now {
+                    return Err(*until - now);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn failed(&self, ip: &str, username: &str) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+
+        // 顺便清理过期的记录
+        if failures.len() > 10000 {
+            let window = self.window;
+            failures.retain(|_, f| match f.locked_until {
+                Some(until) => until > now,
+                None => now.duration_since(f.start) < window,
+            });
+        }
+
+        for (key, max) in
+            [(ip_key(ip), self.ip_max), (user_key(username), self.user_max)]
+        {
+            let f = failures.entry(key).or_insert(Failures {
+                start: now,
+                count: 0,
+                locked_until: None,
+            });
+            let expired = match f.locked_until {
+                Some(until) => until <= now,
+                None => now.duration_since(f.start) >= self.window,
+            };
+            if expired {
+                f.start = now;
+                f.count = 0;
+                f.locked_until = None;
+            }
+            f.count += 1;
+            if f.count >= max {
+                f.locked_until = Some(now + self.lockout);
+            }
+        }
+    }
+
+    // 登录成功后清除账号的失败记录。IP的记录保留
+    pub fn succeeded(&self, username: &str) {
+        self.failures.lock().unwrap().remove(&user_key(username));
+    }
+}
+
+fn ip_key(ip: &str) -> String { format!("ip:{}", ip) }
+
+fn user_key(username: &str) -> String { format!("user:{}", username) }
+
+#[test]
+fn test_rate_limiter() {
+    let limiter = RateLimiter::new(2, Duration::from_secs(60));
+    let a: IpAddr = "1.1.1.1".parse().unwrap();
+    let b: IpAddr = "2.2.2.2".parse().unwrap();
+    assert!(limiter.check(a));
+    assert!(limiter.check(a));
+    assert!(!limiter.check(a));
+    assert!(limiter.check(b));
+}
+
+#[test]
+fn test_login_throttle() {
+    let throttle = LoginThrottle::new(
+        3,
+        2,
+        Duration::from_secs(60),
+        Duration::from_secs(60),
+    );
+    throttle.failed("1.1.1.1", "admin");
+    assert!(throttle.check("1.1.1.1", "admin").is_ok());
+    throttle.failed("1.1.1.1", "admin");
+    // 账号锁定 其他IP也不能登录
+    assert!(throttle.check("2.2.2.2", "admin").is_err());
+    assert!(throttle.check("1.1.1.1", "tech").is_ok());
+
+    // IP锁定 其他账号也不能登录
+    throttle.failed("1.1.1.1", "tech");
+    assert!(throttle.check("1.1.1.1", "other").is_err());
+    assert!(throttle.check("2.2.2.2", "tech").is_ok());
+
+    throttle.succeeded("admin");
+    assert!(throttle.check("2.2.2.2", "admin").is_ok());
+}
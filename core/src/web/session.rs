@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{state::Worker, web::AppState};
+
+// 逻辑矿工 钱包+矿工名 相同的所有连接
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogicalWorker {
+    pub worker: String,
+    pub worker_name: String,
+    pub worker_wallet: String,
+    pub online: bool,
+    // 当前在线连接数
+    pub connections: u32,
+    pub sessions: Vec<u64>,
+    pub hash: u64,
+    pub share_index: u64,
+    pub accept_index: u64,
+    pub invalid_index: u64,
+    pub fee_share_index: u64,
+}
+
+// 按会话更新矿工状态。
+// 同一逻辑矿工有在线连接时不保留离线记录，全部离线时只保留最后一条。
+pub fn update_session(workers: &mut Vec<Worker>, worker: Worker) {
+    let session_id = worker.session_id;
+    let key = worker.worker.clone();
+
+    match workers.iter_mut().find(|w| w.session_id == session_id) {
+        Some(w) => *w = worker,
+        None => workers.push(worker),
+    }
+
+    let online = workers.iter().any(|w| w.worker == key && w.is_online());
+    workers.retain(|w| {
+        w.worker != key
+            || w.is_online()
+            || (!online && w.session_id == session_id)
+    });
+}
+
+pub fn logical_workers(workers: &[Worker]) -> Vec<LogicalWorker> {
+    let mut res: Vec<LogicalWorker> = vec![];
+    for w in workers {
+        let idx = match res.iter().position(|l| l.worker == w.worker) {
+            Some(idx) => idx,
+            None => {
+                res.push(LogicalWorker {
+                    worker: w.worker.clone(),
+                    worker_name: w.worker_name.clone(),
+                    worker_wallet: w.worker_wallet.clone(),
+                    ..Default::default()
+                });
+                res.len() - 1
+            }
+        };
+
+        let l = &mut res[idx];
+        l.sessions.push(w.session_id);
+        if w.is_online() {
+            l.online = true;
+            l.connections += 1;
+            l.hash += w.hash;
+        }
+        l.share_index += w.share_index;
+        l.accept_index += w.accept_index;
+        l.invalid_index += w.invalid_index;
+        l.fee_share_index += w.fee_share_index;
+    }
+    res
+}
+
+// 删除下线时间早于 before 的离线矿工
+pub fn purge_offline(workers: &mut Vec<Worker>, before: i64) -> usize {
+    let len = workers.len();
+    workers.retain(|w| w.is_online() || w.offline_at >= before);
+    len - workers.len()
+}
+
+// 离线矿工保留时长(小时)。可通过环境变量 MINING_PROXY_OFFLINE_HOURS 修改
+pub fn offline_retention_hours() -> i64 {
+    std::env::var("MINING_PROXY_OFFLINE_HOURS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(72)
+}
+
+// 每10分钟清理一次过期的离线矿工
+pub async fn run_offline_purge(app: AppState) {
+    let mut interval =
+        tokio::time::interval(tokio::time::Duration::from_secs(600));
+    loop {
+        interval.tick().await;
+        let before =
+            chrono::Utc::now().timestamp() - offline_retention_hours() * 3600;
+        for (name, server) in app.lock().unwrap().iter_mut() {
+            let n = purge_offline(&mut server.workers, before);
+            if n > 0 {
+                tracing::info!("中转 {} 清理离线矿工 {} 个", name, n);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_update_session() {
+    let mut workers = vec![];
+    let a =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    let mut b =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    assert_ne!(a.session_id, b.session_id);
+
+    update_session(&mut workers, a.clone());
+    update_session(&mut workers, b.clone());
+    assert_eq!(workers.len(), 2);
+    assert_eq!(logical_workers(&workers)[0].connections, 2);
+
+    // 还有在线连接时 离线的连接直接移除
+    b.online = false;
+    update_session(&mut workers, b.clone());
+    assert_eq!(workers.len(), 1);
+    assert_eq!(workers[0].session_id, a.session_id);
+
+    // 全部离线后保留最后一条
+    let mut a = a;
+    a.online = false;
+    update_session(&mut workers, a.clone());
+    assert_eq!(workers.len(), 1);
+    assert!(!logical_workers(&workers)[0].online);
+
+    // 重新上线后删除离线记录
+    let c =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    update_session(&mut workers, c.clone());
+    assert_eq!(workers.len(), 1);
+    assert_eq!(workers[0].session_id, c.session_id);
+}
+
+#[test]
+fn test_purge_offline() {
+    let mut online =
+        Worker::new("0xabc.w1".into(), "w1".into(), "0xabc.w1".into(), true);
+    online.offline_at = 0;
+    let mut old =
+        Worker::new("0xabc.w2".into(), "w2".into(), "0xabc.w2".into(), false);
+    old.offline_at = 100;
+    let mut recent =
+        Worker::new("0xabc.w3".into(), "w3".into(), "0xabc.w3".into(), false);
+    recent.offline_at = 300;
+
+    let mut workers = vec![online, old, recent];
+    assert_eq!(purge_offline(&mut workers, 200), 1);
+    assert_eq!(workers.len(), 2);
+    assert!(workers.iter().all(|w| w.worker != "0xabc.w2"));
+}
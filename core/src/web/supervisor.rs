@@ -0,0 +1,546 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Child,
+    sync::oneshot,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    ipc::{
+        local,
+        parent::{send_command, ParentState},
+    },
+    proxy::{reload::plan, runtime},
+    util::config::Settings,
+    web::{AppState, ChildCommand, OnlineWorker},
+};
+
+// 保留的stderr行数
+const STDERR_LINES: usize = 50;
+// 重启等待时间 每次崩溃翻倍
+const BACKOFF_BASE_SECS: i64 = 1;
+const BACKOFF_MAX_SECS: i64 = 300;
+// 时间窗口内崩溃次数达到上限 视为反复崩溃 不再自动重启
+const CRASH_WINDOW_SECS: i64 = 600;
+const CRASH_LIMIT: usize = 5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyStatus {
+    #[default]
+    Running,
+    // 已退出 等待重启
+    Restarting,
+    // 反复崩溃 需要人工处理
+    Failed,
+    // 排空后正常退出或在后台停止 不再自动重启
+    Stopped,
+}
+
+// 代理进程退出信息
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChildExit {
+    // 秒级时间戳
+    pub time: i64,
+    pub code: Option<i32>,
+    pub status: String,
+    // 退出前的stderr输出
+    pub stderr: Vec<String>,
+}
+
+pub type StderrTail = Arc<Mutex<VecDeque<String>>>;
+// (退出码, 退出原因)
+pub type ProxyExit = (Option<i32>, String);
+
+// 中转运行方式
+pub enum ProxyRunner {
+    // 独立的代理进程
+    Process(Child),
+    // 单进程模式 在主控进程内作为任务运行。结束后记录退出码及原因
+    Task {
+        stop: Option<oneshot::Sender<()>>,
+        exit: Arc<Mutex<Option<ProxyExit>>>,
+    },
+    // 已在后台停止 没有运行
+    Stopped,
+}
+
+impl ProxyRunner {
+    // 已退出时返回退出码及原因
+    pub fn try_wait(&mut self) -> std::io::Result<Option<ProxyExit>> {
+        match self {
+            ProxyRunner::Process(child) => {
+                Ok(child.try_wait()?.map(|s| (s.code(), s.to_string())))
+            }
+            ProxyRunner::Task { exit, .. } => Ok(exit.lock().unwrap().clone()),
+            ProxyRunner::Stopped => Ok(Some((Some(0), "已停止".into()))),
+        }
+    }
+
+    pub fn start_kill(&mut self) -> std::io::Result<()> {
+        match self {
+            ProxyRunner::Process(child) => child.start_kill(),
+            ProxyRunner::Task { stop, .. } => {
+                if let Some(stop) = stop.take() {
+                    let _ = stop.send(());
+                }
+                Ok(())
+            }
+            ProxyRunner::Stopped => Ok(()),
+        }
+    }
+
+    pub async fn kill(&mut self) -> std::io::Result<()> {
+        match self {
+            ProxyRunner::Process(child) => child.kill().await,
+            ProxyRunner::Task { .. } | ProxyRunner::Stopped => {
+                self.start_kill()
+            }
+        }
+    }
+}
+
+// 单进程模式。设置环境变量 MINING_PROXY_MODE=single 开启
+pub fn single_process() -> bool {
+    matches!(std::env::var("MINING_PROXY_MODE").as_deref(), Ok("single"))
+}
+
+// 按运行模式启动中转
+pub fn start_proxy(
+    config: &Settings, state: &ParentState,
+) -> Result<ProxyRunner> {
+    if !single_process() {
+        return Ok(ProxyRunner::Process(crate::util::run_server(config)?));
+    }
+
+    let cert = runtime::tls_config(config)?;
+    let exit = Arc::new(Mutex::new(None));
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let mut task = tokio::spawn(local::run(config.clone(), cert, state.clone()));
+    // 等待中转任务结束并记录原因。panic 同样记录
+    tokio::spawn({
+        let exit = exit.clone();
+        async move {
+            let reason = tokio::select! {
+                // 排空完成后正常结束
+                res = &mut task => match res {
+                    Ok(Ok(())) => (Some(0), "已排空".to_string()),
+                    Ok(Err(e)) => (None, e.to_string()),
+                    Err(e) => (None, e.to_string()),
+                },
+                _ = stop_rx => {
+                    task.abort();
+                    (None, "已停止".to_string())
+                },
+            };
+            *exit.lock().unwrap() = Some(reason);
+        }
+    });
+    Ok(ProxyRunner::Task {
+        stop: Some(stop_tx),
+        exit,
+    })
+}
+
+pub struct Supervision {
+    pub status: ProxyStatus,
+    pub restarts: u32,
+    pub last_exit: Option<ChildExit>,
+    // 下次重启时间
+    pub next_restart: i64,
+    // 时间窗口内的崩溃时间
+    crashes: VecDeque<i64>,
+    stderr: StderrTail,
+}
+
+impl Supervision {
+    pub fn new(child: &mut ProxyRunner) -> Self {
+        let mut s = Self {
+            status: ProxyStatus::Running,
+            restarts: 0,
+            last_exit: None,
+            next_restart: 0,
+            crashes: VecDeque::new(),
+            stderr: Arc::new(Mutex::new(VecDeque::new())),
+        };
+        s.attach(child);
+        s
+    }
+
+    // 读取新进程的stderr。只保留最后几行
+    pub fn attach(&mut self, child: &mut ProxyRunner) {
+        let stderr = match child {
+            ProxyRunner::Process(child) => match child.stderr.take() {
+                Some(stderr) => stderr,
+                None => return,
+            },
+            ProxyRunner::Task { .. } | ProxyRunner::Stopped => return,
+        };
+
+        let tail = Arc::new(Mutex::new(VecDeque::new()));
+        self.stderr = tail.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let mut tail = tail.lock().unwrap();
+                tail.push_back(line);
+                if tail.len() > STDERR_LINES {
+                    tail.pop_front();
+                }
+            }
+        });
+    }
+
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.stderr.lock().unwrap().iter().cloned().collect()
+    }
+
+    // 记录退出 计算下次重启时间
+    pub fn exited(&mut self, exit: ChildExit) {
+        let now = exit.time;
+        self.crashes.push_back(now);
+        while let Some(t) = self.crashes.front() {
+            if *t > now - CRASH_WINDOW_SECS {
+                break;
+            }
+            self.crashes.pop_front();
+        }
+        self.last_exit = Some(exit);
+
+        if self.crashes.len() >= CRASH_LIMIT {
+            self.status = ProxyStatus::Failed;
+        } else {
+            let shift = self.crashes.len() as u32 - 1;
+            let backoff = BACKOFF_BASE_SECS
+                .saturating_mul(1 << shift)
+                .min(BACKOFF_MAX_SECS);
+            self.status = ProxyStatus::Restarting;
+            self.next_restart = now + backoff;
+        }
+    }
+
+    // 排空后正常退出
+    pub fn stopped(&mut self, exit: ChildExit) {
+        self.last_exit = Some(exit);
+        self.status = ProxyStatus::Stopped;
+    }
+
+    // 在后台手动启动 清除崩溃记录
+    pub fn started(&mut self, child: &mut ProxyRunner) {
+        self.attach(child);
+        self.crashes.clear();
+        self.next_restart = 0;
+        self.status = ProxyStatus::Running;
+    }
+
+    pub fn restarted(&mut self, child: &mut ProxyRunner) {
+        self.attach(child);
+        self.restarts += 1;
+        self.status = ProxyStatus::Running;
+    }
+}
+
+fn offline(server: &mut OnlineWorker, reason: &str) {
+    server.child_tx = None;
+    server.online = 0;
+    for w in server.workers.iter_mut().filter(|w| w.is_online()) {
+        w.disconnect(reason.into());
+    }
+}
+
+// 在后台停止中转 不再自动重启。配置中的 stopped 由调用方保存
+pub async fn stop(app: &AppState, name: &str) -> Result<()> {
+    let mut child = {
+        let mut app = app.lock().unwrap();
+        let server = match app.get_mut(name) {
+            Some(server) => server,
+            None => bail!("未找到此中转"),
+        };
+        offline(server, "中转已停止");
+        let exit = ChildExit {
+            time: chrono::Utc::now().timestamp(),
+            code: None,
+            status: "已停止".into(),
+            stderr: server.supervision.stderr_tail(),
+        };
+        server.supervision.stopped(exit);
+        std::mem::replace(&mut server.child, ProxyRunner::Stopped)
+    };
+    // 已退出的进程不能再结束
+    if let Ok(None) = child.try_wait() {
+        child.kill().await?;
+    }
+    tracing::info!("中转 {} 已停止", name);
+    Ok(())
+}
+
+// 按当前配置启动已停止或反复崩溃的中转
+pub fn start(state: &ParentState, name: &str) -> Result<()> {
+    let mut app = state.app.lock().unwrap();
+    let server = match app.get_mut(name) {
+        Some(server) => server,
+        None => bail!("未找到此中转"),
+    };
+    if server.supervision.status == ProxyStatus::Running {
+        bail!("中转 {} 正在运行", name);
+    }
+
+    let mut child = start_proxy(&server.config, state)?;
+    server.supervision.started(&mut child);
+    server.child = child;
+    server.started_at = chrono::Utc::now().timestamp();
+    tracing::info!("中转 {} 已启动", name);
+    Ok(())
+}
+
+pub async fn restart(state: &ParentState, name: &str) -> Result<()> {
+    stop(&state.app, name).await?;
+    start(state, name)
+}
+
+// 添加新中转 配置为停止时只记录不启动
+pub fn add(state: &ParentState, config: Settings) -> Result<()> {
+    let online = if config.stopped {
+        OnlineWorker::stopped(config.clone())
+    } else {
+        OnlineWorker::new(start_proxy(&config, state)?, config.clone())
+    };
+    state.app.lock().unwrap().insert(config.name, online);
+    Ok(())
+}
+
+// 应用修改后的配置。无需重启的修改直接热更新 否则只重启此中转
+pub async fn apply_config(state: &ParentState, config: Settings) -> Result<()> {
+    let name = config.name.clone();
+    let (old, status) = match state.app.lock().unwrap().get_mut(&name) {
+        Some(proxy) => (
+            std::mem::replace(&mut proxy.config, config.clone()),
+            proxy.supervision.status,
+        ),
+        None => bail!("未找到此中转"),
+    };
+    if old == config {
+        return Ok(());
+    }
+    if config.stopped {
+        if status != ProxyStatus::Stopped {
+            stop(&state.app, &name).await?;
+        }
+        return Ok(());
+    }
+    if old.stopped && status == ProxyStatus::Stopped {
+        return start(state, &name);
+    }
+    if status != ProxyStatus::Running {
+        return Ok(());
+    }
+
+    let (_, report) = plan(&old, &config);
+    if report.restart_required.is_empty() {
+        let command = ChildCommand::Reload(Box::new(config));
+        match send_command(&state.app, &name, command).await {
+            Ok(reply) if reply.ok => return Ok(()),
+            Ok(reply) => {
+                tracing::warn!("中转 {} 热更新失败 {}", name, reply.message)
+            }
+            Err(e) => tracing::warn!("中转 {} 热更新失败 {}", name, e),
+        }
+    }
+    restart(state, &name).await
+}
+
+// 按整份配置调整中转。新增的启动 删除的停止 其余按修改应用
+pub async fn apply_all(
+    state: &ParentState, configs: Vec<Settings>,
+) -> Result<()> {
+    let mut errors = vec![];
+    let removed: Vec<String> = state
+        .app
+        .lock()
+        .unwrap()
+        .keys()
+        .filter(|name| configs.iter().all(|c| c.name != **name))
+        .cloned()
+        .collect();
+    for name in removed {
+        match stop(&state.app, &name).await {
+            Ok(()) => {
+                state.app.lock().unwrap().remove(&name);
+            }
+            Err(e) => errors.push(format!("{} {}", name, e)),
+        }
+    }
+
+    for config in configs {
+        let name = config.name.clone();
+        let exists = state.app.lock().unwrap().contains_key(&name);
+        let res = if exists {
+            apply_config(state, config).await
+        } else {
+            add(state, config)
+        };
+        if let Err(e) = res {
+            errors.push(format!("{} {}", name, e));
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("{}", errors.join("; "));
+    }
+    Ok(())
+}
+
+fn supervise(
+    name: &str, server: &mut OnlineWorker, now: i64, state: &ParentState,
+) {
+    match server.supervision.status {
+        ProxyStatus::Running => {
+            let (code, status) = match server.child.try_wait() {
+                Ok(Some(exit)) => exit,
+                Ok(None) => return,
+                Err(e) => {
+                    tracing::warn!("无法获取中转 {} 进程状态 {}", name, e);
+                    return;
+                }
+            };
+
+            let exit = ChildExit {
+                time: now,
+                code,
+                status,
+                stderr: server.supervision.stderr_tail(),
+            };
+
+            // 进程已退出 矿工全部下线
+            offline(server, "代理进程退出");
+            if exit.code == Some(0) {
+                tracing::info!("中转 {} 已排空退出", name);
+                server.supervision.stopped(exit);
+                return;
+            }
+            tracing::error!("中转 {} 进程退出 {}", name, exit.status);
+            server.supervision.exited(exit);
+            match server.supervision.status {
+                ProxyStatus::Failed => {
+                    tracing::error!("中转 {} 反复崩溃 停止自动重启", name)
+                }
+                _ => tracing::info!(
+                    "中转 {} 将在 {} 秒后重启",
+                    name,
+                    server.supervision.next_restart - now
+                ),
+            }
+        }
+        ProxyStatus::Restarting if now >= server.supervision.next_restart => {
+            match start_proxy(&server.config, state) {
+                Ok(mut child) => {
+                    server.supervision.restarted(&mut child);
+                    server.child = child;
+                    server.started_at = now;
+                    tracing::info!(
+                        "中转 {} 已重启 第 {} 次",
+                        name,
+                        server.supervision.restarts
+                    );
+                }
+                Err(e) => {
+                    tracing::error!("中转 {} 重启失败 {}", name, e);
+                    server.supervision.exited(ChildExit {
+                        time: now,
+                        code: None,
+                        status: e.to_string(),
+                        stderr: vec![],
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// 每秒检查一次代理进程 退出后自动重启
+pub async fn run_supervisor(state: ParentState) {
+    let mut interval =
+        tokio::time::interval(tokio::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let now = chrono::Utc::now().timestamp();
+        for (name, server) in state.app.lock().unwrap().iter_mut() {
+            supervise(name, server, now, &state);
+        }
+    }
+}
+
+// 主控端退出前排空所有中转 等待代理上报最终状态后退出。超时后强制结束
+pub async fn shutdown(app: AppState, timeout: Duration) {
+    let names: Vec<String> = app.lock().unwrap().keys().cloned().collect();
+    for name in &names {
+        let command = ChildCommand::Drain(timeout.as_secs());
+        if let Err(e) = send_command(&app, name, command).await {
+            tracing::warn!("中转 {} 排空失败 {}", name, e);
+        }
+    }
+
+    // 多等几秒 留给代理上报最终状态
+    let deadline = Instant::now() + timeout + Duration::from_secs(10);
+    loop {
+        let running = app
+            .lock()
+            .unwrap()
+            .values_mut()
+            .map(|s| s.child.try_wait())
+            .filter(|exit| matches!(exit, Ok(None)))
+            .count();
+        if running == 0 {
+            break;
+        }
+        if Instant::now() >= deadline {
+            tracing::warn!("{} 个中转排空超时 强制结束", running);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    for server in app.lock().unwrap().values_mut() {
+        let _ = server.child.start_kill();
+    }
+}
+
+#[tokio::test]
+async fn test_backoff_and_crash_loop() {
+    let child = tokio::process::Command::new("true").spawn().unwrap();
+    let mut s = Supervision::new(&mut ProxyRunner::Process(child));
+    let exit = |time| ChildExit {
+        time,
+        ..Default::default()
+    };
+
+    s.exited(exit(100));
+    assert_eq!(s.status, ProxyStatus::Restarting);
+    assert_eq!(s.next_restart, 101);
+    s.exited(exit(101));
+    s.exited(exit(103));
+    assert_eq!(s.next_restart, 107);
+
+    // 超出时间窗口的崩溃不再计入
+    s.exited(exit(1000));
+    assert_eq!(s.next_restart, 1001);
+
+    for t in 1001..1005 {
+        s.exited(exit(t));
+    }
+    assert_eq!(s.status, ProxyStatus::Failed);
+
+    // 手动启动后重新计算
+    s.started(&mut ProxyRunner::Stopped);
+    assert_eq!(s.status, ProxyStatus::Running);
+    s.exited(exit(1006));
+    assert_eq!(s.next_restart, 1007);
+}
@@ -0,0 +1,133 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::{bail, Result};
+use tokio::{
+    sync::Notify,
+    time::{Duration, Instant},
+};
+
+use crate::web::AppState;
+
+// 新进程就绪后创建此文件 通知旧进程退出
+const READY_ENV: &str = "MINING_PROXY_UPGRADE_READY";
+// 等待新进程就绪的时间
+const READY_TIMEOUT_SECS: u64 = 60;
+// 新进程等待中转连接的时间。超时后同样通知旧进程
+const CONNECT_TIMEOUT_SECS: u64 = 30;
+
+// 不停机升级。新版本主控进程通过 SO_REUSEPORT 监听同一端口
+// 就绪后旧进程停止接受连接 排空全部中转后退出
+#[derive(Default)]
+pub struct Upgrade {
+    running: AtomicBool,
+    done: Notify,
+}
+
+pub type UpgradeState = Arc<Upgrade>;
+
+impl Upgrade {
+    // 启动新进程并等待就绪。成功后当前进程应停止
+    pub async fn start(&self) -> Result<()> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            bail!("正在升级");
+        }
+        match spawn().await {
+            Ok(()) => {
+                self.done.notify_one();
+                Ok(())
+            }
+            Err(e) => {
+                self.running.store(false, Ordering::SeqCst);
+                Err(e)
+            }
+        }
+    }
+
+    // 新进程就绪后返回
+    pub async fn finished(&self) { self.done.notified().await }
+}
+
+async fn spawn() -> Result<()> {
+    let ready = std::env::temp_dir()
+        .join(format!("mining_proxy-upgrade-{}.ready", std::process::id()));
+    let _ = std::fs::remove_file(&ready);
+
+    tracing::info!("启动新进程 {}", crate::CURRENT_EXE.display());
+    let mut child = tokio::process::Command::new(&*crate::CURRENT_EXE)
+        .args(std::env::args_os().skip(1))
+        .env(READY_ENV, &ready)
+        // 使用新进程自己的通信地址 避免和旧进程冲突
+        .env_remove("MINING_PROXY_IPC_SOCKET")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+
+    let deadline = Instant::now() + Duration::from_secs(READY_TIMEOUT_SECS);
+    loop {
+        if ready.exists() {
+            let _ = std::fs::remove_file(&ready);
+            tracing::info!("新进程 {:?} 已就绪", child.id());
+            return Ok(());
+        }
+        if let Some(status) = child.try_wait()? {
+            bail!("新进程启动失败 {}", status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.start_kill();
+            bail!("等待新进程就绪超时");
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+// 由升级启动的新进程及其中转 在交接完成前可以加入旧进程的监听
+pub fn handover() -> bool { std::env::var(READY_ENV).is_ok() }
+
+// 交接完成 之后监听端口和启动的中转按普通方式检查端口占用
+pub fn end_handover() { std::env::remove_var(READY_ENV) }
+
+// 升级启动的新进程。全部中转连接主控端后通知旧进程
+pub async fn notify_ready(app: AppState) {
+    let path = match std::env::var(READY_ENV) {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(CONNECT_TIMEOUT_SECS);
+    while Instant::now() < deadline {
+        let waiting = app
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|s| s.child_tx.is_none())
+            .count();
+        if waiting == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+
+    match std::fs::write(&path, std::process::id().to_string()) {
+        Ok(()) => tracing::info!("已通知旧进程退出"),
+        Err(e) => tracing::error!("无法通知旧进程 {} {}", path, e),
+    }
+    end_handover();
+}
+
+// 收到 SIGUSR2 后升级
+pub async fn watch_signal(upgrade: UpgradeState) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut usr2 = signal(SignalKind::user_defined2())?;
+    loop {
+        usr2.recv().await;
+        tracing::info!("收到 SIGUSR2 开始升级");
+        if let Err(e) = upgrade.start().await {
+            tracing::error!("升级失败 {}", e);
+        }
+    }
+}
@@ -0,0 +1,556 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+use argon2::{
+    password_hash::{
+        rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier,
+        SaltString,
+    },
+    Argon2,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    util::{write_atomic, PRIVATE_FILE_MODE},
+    web::totp,
+};
+
+pub const ROLE_ADMIN: &str = "ROLE_ADMIN";
+pub const ROLE_OPERATOR: &str = "ROLE_OPERATOR";
+pub const ROLE_VIEWER: &str = "ROLE_VIEWER";
+// 被要求开启两步验证但尚未绑定 只能绑定验证器
+pub const ROLE_TOTP_SETUP: &str = "ROLE_TOTP_SETUP";
+
+// 密码最短长度
+const MIN_PASSWORD_LEN: usize = 8;
+
+pub type UserState = Arc<Mutex<UserStore>>;
+
+lazy_static! {
+    // 用户不存在时用此哈希校验 与校验真实密码耗时相同 避免枚举用户名
+    static ref DUMMY_HASH: String =
+        hash_password("mining_proxy").unwrap_or_default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // 全部权限 包括账号管理和升级
+    Admin,
+    // 管理中转 修改配置和抽水
+    Operator,
+    // 只能查看
+    Viewer,
+}
+
+impl Role {
+    // 高级角色包含低级角色的全部权限
+    pub fn permissions(self) -> Vec<String> {
+        let roles: &[&str] = match self {
+            Role::Admin => &[ROLE_ADMIN, ROLE_OPERATOR, ROLE_VIEWER],
+            Role::Operator => &[ROLE_OPERATOR, ROLE_VIEWER],
+            Role::Viewer => &[ROLE_VIEWER],
+        };
+        roles.iter().map(|r| r.to_string()).collect()
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+            Role::Viewer => "viewer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    // argon2 哈希 PHC 格式
+    pub password: String,
+    pub role: Role,
+    #[serde(default)]
+    pub created_at: i64,
+    // 两步验证 为空表示未绑定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<Totp>,
+    // 管理员要求此账号必须开启两步验证
+    #[serde(default)]
+    pub totp_required: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Totp {
+    // base32 编码的密钥
+    pub secret: String,
+    // 首次验证通过后才开启
+    #[serde(default)]
+    pub enabled: bool,
+    // 恢复码的 argon2 哈希 使用后删除
+    #[serde(default)]
+    pub recovery_codes: Vec<String>,
+    // 最后使用的时间窗口 同一验证码不能重复使用
+    #[serde(default)]
+    pub last_step: u64,
+}
+
+// 返回给后台的账号信息 不含密码
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub role: Role,
+    pub created_at: i64,
+    pub totp_enabled: bool,
+    pub totp_required: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct UserConfig {
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+// 后台登录账号 保存到 users.yaml
+pub struct UserStore {
+    path: String,
+    users: Vec<User>,
+}
+
+impl UserStore {
+    pub fn new(path: &str, users: Vec<User>) -> Self {
+        Self {
+            path: path.to_string(),
+            users,
+        }
+    }
+
+    pub fn from_env() -> Result<Self> {
+        let path = std::env::var("MINING_PROXY_USERS")
+            .unwrap_or_else(|_| "users.yaml".into());
+        Self::load(&path)
+    }
+
+    // 文件不存在时用 MINING_PROXY_WEB_PASSWORD 创建 admin 账号
+    pub fn load(path: &str) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => {
+                let config: UserConfig = serde_yaml::from_str(&s)
+                    .map_err(|e| anyhow!("{} 格式错误 {}", path, e))?;
+                Ok(Self::new(path, config.users))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let password = match std::env::var("MINING_PROXY_WEB_PASSWORD")
+                {
+                    Ok(p) => p,
+                    Err(_) => {
+                        tracing::warn!(
+                            "使用默认密码创建 admin 账号 请登录后修改"
+                        );
+                        "admin123".into()
+                    }
+                };
+                // 沿用旧版本的密码 不检查长度
+                let admin = User {
+                    username: "admin".into(),
+                    password: hash_password(&password)?,
+                    role: Role::Admin,
+                    created_at: chrono::Utc::now().timestamp(),
+                    totp: None,
+                    totp_required: false,
+                };
+                let store = Self::new(path, vec![admin]);
+                store.save()?;
+                Ok(store)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let config = UserConfig {
+            users: self.users.clone(),
+        };
+        write_atomic(
+            std::path::Path::new(&self.path),
+            serde_yaml::to_string(&config)?.as_bytes(),
+            Some(PRIVATE_FILE_MODE),
+        )
+    }
+
+    pub fn list(&self) -> Vec<UserInfo> {
+        self.users
+            .iter()
+            .map(|u| UserInfo {
+                username: u.username.clone(),
+                role: u.role,
+                created_at: u.created_at,
+                totp_enabled: totp_enabled(u),
+                totp_required: u.totp_required,
+            })
+            .collect()
+    }
+
+    pub fn role(&self, username: &str) -> Option<Role> {
+        self.get(username).map(|u| u.role)
+    }
+
+    // 密码哈希。argon2 校验较慢 复制出来后在锁外校验
+    pub fn password_hash(&self, username: &str) -> Option<String> {
+        self.get(username).map(|u| u.password.clone())
+    }
+
+    // password 为 new_password 生成的哈希
+    pub fn add(
+        &mut self, username: &str, password: String, role: Role,
+    ) -> Result<()> {
+        if username.is_empty() {
+            bail!("用户名不能为空");
+        }
+        if self.get(username).is_some() {
+            bail!("用户 {} 已存在", username);
+        }
+        self.users.push(User {
+            username: username.to_string(),
+            password,
+            role,
+            created_at: chrono::Utc::now().timestamp(),
+            totp: None,
+            totp_required: false,
+        });
+        self.save()
+    }
+
+    // 修改密码哈希或角色 为空的保持不变
+    pub fn update(
+        &mut self, username: &str, password: Option<String>, role: Option<Role>,
+    ) -> Result<()> {
+        if let Some(role) = role {
+            if role != Role::Admin {
+                self.check_last_admin(username)?;
+            }
+        }
+        let user = self.get_mut(username)?;
+        if let Some(password) = password {
+            user.password = password;
+        }
+        if let Some(role) = role {
+            user.role = role;
+        }
+        self.save()
+    }
+
+    pub fn delete(&mut self, username: &str) -> Result<()> {
+        if self.get(username).is_none() {
+            bail!("未找到用户 {}", username);
+        }
+        self.check_last_admin(username)?;
+        self.users.retain(|u| u.username != username);
+        self.save()
+    }
+
+    pub fn totp_enabled(&self, username: &str) -> bool {
+        self.get(username).map(totp_enabled).unwrap_or(false)
+    }
+
+    // 被要求开启两步验证但还未绑定
+    pub fn needs_totp_setup(&self, username: &str) -> bool {
+        match self.get(username) {
+            Some(u) => u.totp_required && !totp_enabled(u),
+            None => false,
+        }
+    }
+
+    // 生成新密钥 验证通过前不生效。返回密钥
+    pub fn enroll_totp(&mut self, username: &str) -> Result<String> {
+        let user = self.get_mut(username)?;
+        if totp_enabled(user) {
+            bail!("已开启两步验证 请先关闭");
+        }
+        let secret = totp::generate_secret();
+        user.totp = Some(Totp {
+            secret: secret.clone(),
+            enabled: false,
+            recovery_codes: vec![],
+            last_step: 0,
+        });
+        self.save()?;
+        Ok(secret)
+    }
+
+    // 用验证器上的验证码确认绑定 recovery_codes 为恢复码的哈希
+    pub fn confirm_totp(
+        &mut self, username: &str, code: &str, now: i64,
+        recovery_codes: Vec<String>,
+    ) -> Result<()> {
+        let user = self.get_mut(username)?;
+        let t = match user.totp.as_mut() {
+            Some(t) if !t.enabled => t,
+            Some(_) => bail!("已开启两步验证"),
+            None => bail!("请先绑定验证器"),
+        };
+        let step = match totp::verify(&t.secret, code, now) {
+            Some(step) => step,
+            None => bail!("验证码不正确"),
+        };
+        t.recovery_codes = recovery_codes;
+        t.enabled = true;
+        t.last_step = step;
+        self.save()
+    }
+
+    // 已开启的两步验证 复制出来后用 check_totp 在锁外校验
+    pub fn totp(&self, username: &str) -> Option<Totp> {
+        match self.get(username)?.totp.as_ref() {
+            Some(t) if t.enabled => Some(t.clone()),
+            _ => None,
+        }
+    }
+
+    // 记录 check_totp 通过的验证码或恢复码 已被使用时返回 false
+    pub fn consume_totp(
+        &mut self, username: &str, matched: TotpMatch,
+    ) -> Result<bool> {
+        let user = self.get_mut(username)?;
+        let t = match user.totp.as_mut() {
+            Some(t) if t.enabled => t,
+            _ => return Ok(false),
+        };
+        match matched {
+            TotpMatch::Step(step) => {
+                if step <= t.last_step {
+                    return Ok(false);
+                }
+                t.last_step = step;
+            }
+            TotpMatch::Recovery(hash) => {
+                match t.recovery_codes.iter().position(|h| *h == hash) {
+                    Some(i) => {
+                        t.recovery_codes.remove(i);
+                    }
+                    None => return Ok(false),
+                }
+            }
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    // 重新生成恢复码 旧的全部作废。recovery_codes 为哈希
+    pub fn set_recovery_codes(
+        &mut self, username: &str, recovery_codes: Vec<String>,
+    ) -> Result<()> {
+        let user = self.get_mut(username)?;
+        let t = match user.totp.as_mut() {
+            Some(t) if t.enabled => t,
+            _ => bail!("未开启两步验证"),
+        };
+        t.recovery_codes = recovery_codes;
+        self.save()
+    }
+
+    pub fn disable_totp(&mut self, username: &str) -> Result<()> {
+        let user = self.get_mut(username)?;
+        if user.totp_required {
+            bail!("管理员要求此账号开启两步验证");
+        }
+        user.totp = None;
+        self.save()
+    }
+
+    // 管理员设置是否强制两步验证 reset 清除已绑定的验证器
+    pub fn update_totp(
+        &mut self, username: &str, required: Option<bool>, reset: bool,
+    ) -> Result<()> {
+        let user = self.get_mut(username)?;
+        if let Some(required) = required {
+            user.totp_required = required;
+        }
+        if reset {
+            user.totp = None;
+        }
+        self.save()
+    }
+
+    fn get(&self, username: &str) -> Option<&User> {
+        self.users.iter().find(|u| u.username == username)
+    }
+
+    fn get_mut(&mut self, username: &str) -> Result<&mut User> {
+        match self.users.iter_mut().find(|u| u.username == username) {
+            Some(user) => Ok(user),
+            None => bail!("未找到用户 {}", username),
+        }
+    }
+
+    // 至少保留一个管理员
+    fn check_last_admin(&self, username: &str) -> Result<()> {
+        let others = self
+            .users
+            .iter()
+            .filter(|u| u.role == Role::Admin && u.username != username)
+            .count();
+        if self.role(username) == Some(Role::Admin) && others == 0 {
+            bail!("至少需要保留一个管理员");
+        }
+        Ok(())
+    }
+}
+
+fn totp_enabled(user: &User) -> bool {
+    matches!(&user.totp, Some(t) if t.enabled)
+}
+
+// 通过的验证码所在的时间窗口 或恢复码的哈希
+#[derive(Debug, Clone, PartialEq)]
+pub enum TotpMatch {
+    Step(u64),
+    Recovery(String),
+}
+
+// 校验验证码或恢复码。恢复码需要逐个 argon2 校验 不要在锁内调用
+pub fn check_totp(t: &Totp, code: &str, now: i64) -> Option<TotpMatch> {
+    let code = code.trim();
+    if let Some(step) = totp::verify(&t.secret, code, now) {
+        return Some(TotpMatch::Step(step));
+    }
+    let code = code.to_lowercase();
+    t.recovery_codes
+        .iter()
+        .find(|h| verify_password(&code, h))
+        .map(|h| TotpMatch::Recovery(h.clone()))
+}
+
+// 生成恢复码 返回明文和哈希
+pub fn new_recovery_codes() -> Result<(Vec<String>, Vec<String>)> {
+    let codes = totp::recovery_codes();
+    let hashes = codes
+        .iter()
+        .map(|c| hash_password(c))
+        .collect::<Result<_>>()?;
+    Ok((codes, hashes))
+}
+
+// 检查长度并生成哈希
+pub fn new_password(password: &str) -> Result<String> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        bail!("密码不能少于{}位", MIN_PASSWORD_LEN);
+    }
+    hash_password(password)
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => bail!("无法生成密码哈希 {}", e),
+    }
+}
+
+// 用户不存在时代替 password_hash 的结果
+pub fn dummy_password_hash() -> String { DUMMY_HASH.clone() }
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn test_user_store() {
+    let path = std::env::temp_dir()
+        .join(format!("users-{}.yaml", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let _ = std::fs::remove_file(&path);
+
+    let mut store = UserStore::new(&path, vec![]);
+    let hash = new_password("viewer123").unwrap();
+    store
+        .add("admin", new_password("admin123").unwrap(), Role::Admin)
+        .unwrap();
+    store.add("tech", hash.clone(), Role::Viewer).unwrap();
+    assert!(store.add("tech", hash, Role::Viewer).is_err());
+    assert!(new_password("123").is_err());
+
+    // 文件中只保存哈希
+    let saved = std::fs::read_to_string(&path).unwrap();
+    assert!(!saved.contains("viewer123"));
+    // 含两步验证密钥 只允许当前用户读写
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, PRIVATE_FILE_MODE);
+    }
+
+    let store = UserStore::load(&path).unwrap();
+    let hash = store.password_hash("tech").unwrap();
+    assert!(verify_password("viewer123", &hash));
+    assert!(!verify_password("admin123", &hash));
+    assert!(dummy_password_hash().starts_with("$argon2"));
+    assert_eq!(store.password_hash("nobody"), None);
+    assert!(!Role::Viewer
+        .permissions()
+        .contains(&ROLE_OPERATOR.to_string()));
+
+    // 不能删除或降级最后一个管理员
+    let mut store = store;
+    assert!(store.delete("admin").is_err());
+    assert!(store.update("admin", None, Some(Role::Operator)).is_err());
+    let hash = new_password("newpass123").unwrap();
+    store.update("tech", Some(hash), Some(Role::Admin)).unwrap();
+    store.delete("admin").unwrap();
+    assert_eq!(store.role("tech"), Some(Role::Admin));
+    let hash = store.password_hash("tech").unwrap();
+    assert!(verify_password("newpass123", &hash));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_user_totp() {
+    let path = std::env::temp_dir()
+        .join(format!("users-totp-{}.yaml", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    let mut store = UserStore::new(&path, vec![]);
+    let hash = new_password("viewer123").unwrap();
+    store.add("tech", hash, Role::Viewer).unwrap();
+    store.update_totp("tech", Some(true), false).unwrap();
+    assert!(store.needs_totp_setup("tech"));
+
+    // 绑定后需要验证通过才生效
+    let secret = store.enroll_totp("tech").unwrap();
+    assert!(!store.totp_enabled("tech"));
+    let now = 1_600_000_000;
+    let code = |now: i64| {
+        format!("{:06}", totp::code(&secret, now as u64 / 30).unwrap())
+    };
+    let (recovery, hashes) = new_recovery_codes().unwrap();
+    assert!(store
+        .confirm_totp("tech", "000000x", now, hashes.clone())
+        .is_err());
+    store.confirm_totp("tech", &code(now), now, hashes).unwrap();
+    assert!(store.totp_enabled("tech"));
+    assert!(!store.needs_totp_setup("tech"));
+
+    let verify = |store: &mut UserStore, code: &str, now: i64| {
+        let t = store.totp("tech").unwrap();
+        match check_totp(&t, code, now) {
+            Some(matched) => store.consume_totp("tech", matched).unwrap(),
+            None => false,
+        }
+    };
+    // 同一验证码不能重复使用
+    assert!(!verify(&mut store, &code(now), now));
+    assert!(verify(&mut store, &code(now + 30), now + 30));
+    assert!(verify(&mut store, &recovery[0], now + 30));
+    assert!(!verify(&mut store, &recovery[0], now + 30));
+
+    assert!(store.disable_totp("tech").is_err());
+    store.update_totp("tech", Some(false), true).unwrap();
+    assert!(!store.totp_enabled("tech"));
+    let _ = std::fs::remove_file(&path);
+}
@@ -4,37 +4,44 @@ mod version {
 
 include!(concat!(env!("OUT_DIR"), "/generated.rs"));
 
-use rustls_pemfile::{certs, rsa_private_keys};
-use tokio_rustls::rustls::{self, Certificate, PrivateKey};
-
-use std::{path::Path, sync::Arc, collections::VecDeque};
-use tracing::Level;
-
-use tokio::sync::{broadcast, RwLock, Mutex};
+use std::sync::Arc;
 
 use tracing_subscriber::{
     self,
+    filter::LevelFilter,
     fmt::{format::Writer, time::FormatTime},
+    prelude::*,
+    reload,
 };
 
 use dotenv::dotenv;
-use jsonwebtoken::{decode, DecodingKey, Validation};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::OpenOptions, io::Read};
+use std::collections::HashMap;
 
 
 
-use actix_web::{dev::ServiceRequest, web, App, Error, HttpServer};
+use actix_web::{
+    dev::ServiceRequest, web, App, Error, HttpMessage, HttpServer,
+};
 
 use core::{
-    client::{
-        encry::accept_en_tcp, tcp::accept_tcp, tls::accept_tcp_with_tls, SSL,
-        TCP,
-    },
-    proxy::Job,
-    state::Worker,
+    ipc::{parent::ParentState, PARENT_IPC},
     util::config::Settings,
-    web::{handles::auth::Claims, AppState, OnlineWorker},
+    web::{
+        alert::{AlertEngine, AlertState},
+        api_token::{ApiTokenGrant, ApiTokenState, ApiTokens, TOKEN_PREFIX},
+        config_store::{ConfigState, ConfigStore},
+        event_log::{EventLog, EventLogState},
+        fee_report::{FeeReport, FeeReportState},
+        fee_stats::{FeeStats, FeeStatsState},
+        history::{HistoryConfig, HistoryState, HistoryStore},
+        handles::auth::{decode_jwt, request_token},
+        login_session::{LoginSessions, SessionState},
+        rate_limit::{LoginThrottle, RateLimiter},
+        supervisor,
+        upgrade::{Upgrade, UpgradeState},
+        user_store::{Role, UserState, UserStore, ROLE_TOTP_SETUP},
+        AppState,
+    },
 };
 
 use anyhow::{bail, Result};
@@ -43,12 +50,6 @@ use clap::{crate_version, ArgMatches};
 //use crossbeam_channel::bounded;
 use human_panic::setup_panic;
 
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    select,
-    sync::mpsc::{self, UnboundedReceiver},
-};
-
 fn main() -> Result<()> {
     setup_panic!();
     dotenv().ok();
@@ -77,14 +78,19 @@ fn main() -> Result<()> {
         .with_source_location(true)
         .with_timer(LocalTimer);
 
-    // 初始化并设置日志格式(定制和筛选日志)
-    tracing_subscriber::fmt()
-        .with_max_level(Level::DEBUG)
-        //.with_writer(io::stdout) // 写入标准输出
-        .with_writer(non_blocking) // 写入文件，将覆盖上面的标准输出
-        .with_ansi(false) // 如果日志是写入文件，应将ansi的颜色输出功能关掉
-        .event_format(format)
+    // 初始化并设置日志格式(定制和筛选日志) 日志级别可由主控端修改
+    let (level, level_handle) = reload::Layer::new(LevelFilter::DEBUG);
+    tracing_subscriber::registry()
+        .with(level)
+        .with(
+            tracing_subscriber::fmt::layer()
+                //.with_writer(io::stdout) // 写入标准输出
+                .with_writer(non_blocking) // 写入文件，将覆盖上面的标准输出
+                .with_ansi(false) // 如果日志是写入文件，应将ansi的颜色输出功能关掉
+                .event_format(format),
+        )
         .init();
+    core::util::logger::set_level_handle(level_handle);
 
     core::init();
     let matches = core::util::get_app_command_matches()?;
@@ -115,81 +121,195 @@ fn main() -> Result<()> {
 async fn async_main(_matches: ArgMatches<'_>) -> Result<()> {
     let data: AppState = Arc::new(std::sync::Mutex::new(HashMap::new()));
 
-    match OpenOptions::new()
-        .write(true)
-        .read(true)
-        .open("configs.yaml")
-    {
-        Ok(mut f) => {
-            let mut configs = String::new();
-            if let Ok(len) = f.read_to_string(&mut configs) {
-                if len > 0 {
-                    let configs: Vec<Settings> =
-                        match serde_yaml::from_str(&configs) {
-                            Ok(s) => s,
-                            Err(e) => {
-                                tracing::error!("{}", e);
-                                vec![]
-                            }
-                        };
-                    for config in configs {
-                        match core::util::run_server(&config) {
-                            Ok(child) => {
-                                let online = OnlineWorker {
-                                    child,
-                                    config: config.clone(),
-                                    workers: vec![],
-                                    online: 0,
-                                };
-
-                                data.lock()
-                                    .unwrap()
-                                    .insert(config.name, online);
-                            }
-                            Err(e) => {
-                                tracing::error!("{}", e);
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        Err(_) => {}
+    let fee_stats: FeeStatsState =
+        Arc::new(std::sync::Mutex::new(FeeStats::default()));
+    let fee_report: FeeReportState =
+        Arc::new(std::sync::Mutex::new(FeeReport::default()));
+    // 矿机连接事件
+    let events: EventLogState =
+        Arc::new(std::sync::Mutex::new(EventLog::from_env()?));
+
+    // 接收代理进程上报
+    let ipc_state = ParentState {
+        app: data.clone(),
+        fee_stats: fee_stats.clone(),
+        fee_report: fee_report.clone(),
+        events: events.clone(),
     };
+    let parent_state = ipc_state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = core::ipc::parent::run(&PARENT_IPC, parent_state).await
+        {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    });
 
-    let tcp_data = data.clone();
+    // 启动前校验全部中转配置 有错误时不启动
+    let config_store: ConfigState = Arc::new(ConfigStore::from_env());
+    let configs = match config_store.load() {
+        Ok(configs) => configs,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return Err(e);
+        }
+    };
+    for config in configs {
+        if let Err(e) = supervisor::add(&ipc_state, config) {
+            tracing::error!("{}", e);
+        }
+    }
 
-    tokio::spawn(async move { recv_from_child(tcp_data).await });
+    // 矿工自助查询接口 每个IP每分钟最多30次
+    let public_limiter = web::Data::new(RateLimiter::new(
+        30,
+        std::time::Duration::from_secs(60),
+    ));
+
+    // 历史数据 保存到本地sqlite
+    let history: HistoryState = Arc::new(std::sync::Mutex::new(
+        HistoryStore::open(HistoryConfig::from_env())?,
+    ));
+    tokio::spawn(core::web::history::run_sampler(
+        data.clone(),
+        history.clone(),
+    ));
+
+    tokio::spawn(core::web::session::run_offline_purge(data.clone()));
+    // 代理进程退出后自动重启
+    tokio::spawn(core::web::supervisor::run_supervisor(ipc_state.clone()));
+
+    // 告警规则 保存到 alerts.yaml
+    let alerts: AlertState = Arc::new(std::sync::Mutex::new(
+        AlertEngine::load(
+            &std::env::var("MINING_PROXY_ALERTS")
+                .unwrap_or_else(|_| "alerts.yaml".into()),
+        )?,
+    ));
+    tokio::spawn(core::web::alert::run_alerts(
+        data.clone(),
+        fee_report.clone(),
+        alerts.clone(),
+    ));
 
     let port: i32 = match std::env::var("MINING_PROXY_WEB_PORT") {
         Ok(p) => p.parse().unwrap(),
         Err(_) => 8888,
     };
 
+    // 不停机升级 收到 SIGUSR2 或后台请求时启动新进程
+    let upgrade: UpgradeState = Arc::new(Upgrade::default());
+    tokio::spawn(core::web::upgrade::watch_signal(upgrade.clone()));
+
+    // 后台登录账号 首次启动时创建 admin
+    let users: UserState =
+        Arc::new(std::sync::Mutex::new(UserStore::from_env()?));
+    // 登录会话 注销后 token 立即失效
+    let sessions: SessionState =
+        Arc::new(std::sync::Mutex::new(LoginSessions::from_env()?));
+    let login_throttle = web::Data::new(LoginThrottle::from_env());
+    // 管理员创建的接口令牌
+    let api_tokens: ApiTokenState =
+        Arc::new(std::sync::Mutex::new(ApiTokens::from_env()?));
+
     let http_data = data.clone();
-    let web_sever = if let Ok(http) = HttpServer::new(move || {
-        let generated = generate();
-
-        use actix_web_grants::GrantsMiddleware;
-        let auth = GrantsMiddleware::with_extractor(extract);
-
-        App::new()
-            .wrap(auth)
-            .app_data(web::Data::new(http_data.clone()))
-            .service(
-                web::scope("/api")
-                    .service(core::web::handles::user::login)
-                    .service(core::web::handles::user::info)
-                    .service(core::web::handles::user::logout)
-                    .service(core::web::handles::server::crate_app)
-                    .service(core::web::handles::server::server_list)
-                    .service(core::web::handles::server::server)
-                    .service(core::web::handles::server::dashboard),
-            )
-            .service(actix_web_static_files::ResourceFiles::new("/", generated))
-    })
-    .bind(format!("0.0.0.0:{}", port))
-    {
+    let http_upgrade = upgrade.clone();
+    // 开启 SO_REUSEPORT 升级时新进程可监听同一端口
+    let web_listener = core::util::bind_reuse_port(
+        &format!("0.0.0.0:{}", port),
+        core::web::upgrade::handover(),
+    )
+    .and_then(|listener| Ok(listener.into_std()?));
+    let web_sever = if let Ok(http) = web_listener.and_then(|listener| {
+        Ok(HttpServer::new(move || {
+            let generated = generate();
+
+            use actix_web_grants::GrantsMiddleware;
+            let auth = GrantsMiddleware::with_extractor(extract);
+
+            App::new()
+                .wrap(auth)
+                .app_data(web::Data::new(http_data.clone()))
+                .app_data(web::Data::new(fee_stats.clone()))
+                .app_data(web::Data::new(fee_report.clone()))
+                .app_data(web::Data::new(history.clone()))
+                .app_data(web::Data::new(alerts.clone()))
+                .app_data(web::Data::new(events.clone()))
+                .app_data(web::Data::new(ipc_state.clone()))
+                .app_data(web::Data::new(http_upgrade.clone()))
+                .app_data(web::Data::new(config_store.clone()))
+                .app_data(web::Data::new(users.clone()))
+                .app_data(web::Data::new(sessions.clone()))
+                .app_data(web::Data::new(api_tokens.clone()))
+                .app_data(login_throttle.clone())
+                .app_data(public_limiter.clone())
+                .service(
+                    web::scope("/api")
+                        .service(core::web::handles::user::login)
+                        .service(core::web::handles::user::info)
+                        .service(core::web::handles::user::logout)
+                        .service(core::web::handles::user::logout_all)
+                        .service(core::web::handles::user::login_sessions)
+                        .service(core::web::handles::user::revoke_session)
+                        .service(core::web::handles::user::change_password)
+                        .service(core::web::handles::user::totp_enroll)
+                        .service(core::web::handles::user::totp_confirm)
+                        .service(
+                            core::web::handles::user::totp_recovery_codes,
+                        )
+                        .service(core::web::handles::user::totp_disable)
+                        .service(core::web::handles::user::user_list)
+                        .service(core::web::handles::user::add_user)
+                        .service(core::web::handles::user::update_user)
+                        .service(core::web::handles::user::delete_user)
+                        .service(core::web::handles::api_token::api_tokens)
+                        .service(
+                            core::web::handles::api_token::create_api_token,
+                        )
+                        .service(
+                            core::web::handles::api_token::revoke_api_token,
+                        )
+                        .service(core::web::handles::server::crate_app)
+                        .service(core::web::handles::server::server_list)
+                        .service(core::web::handles::server::server)
+                        .service(core::web::handles::server::dashboard)
+                        .service(core::web::handles::server::update_server)
+                        .service(core::web::handles::server::delete_server)
+                        .service(core::web::handles::server::stop_server)
+                        .service(core::web::handles::server::start_server)
+                        .service(core::web::handles::server::restart_server)
+                        .service(core::web::handles::fee_rule::fee_rules)
+                        .service(core::web::handles::fee_rule::update_fee_rules)
+                        .service(core::web::handles::fee_report::fee_report)
+                        .service(core::web::handles::fee_report::fee_report_csv)
+                        .service(core::web::handles::history::history)
+                        .service(core::web::handles::worker::offline_workers)
+                        .service(core::web::handles::alert::alert_rules)
+                        .service(core::web::handles::alert::add_alert_rule)
+                        .service(core::web::handles::alert::update_alert_rule)
+                        .service(core::web::handles::alert::delete_alert_rule)
+                        .service(core::web::handles::alert::alert_webhooks)
+                        .service(core::web::handles::alert::update_alert_webhooks)
+                        .service(core::web::handles::alert::active_alerts)
+                        .service(core::web::handles::alert::test_alert)
+                        .service(core::web::handles::event::events)
+                        .service(core::web::handles::reject::rejects)
+                        .service(core::web::handles::control::sessions)
+                        .service(core::web::handles::control::disconnect_session)
+                        .service(core::web::handles::control::drain)
+                        .service(core::web::handles::control::set_log_level)
+                        .service(core::web::handles::control::reload)
+                        .service(core::web::handles::upgrade::upgrade)
+                        .service(core::web::handles::config::config_versions)
+                        .service(core::web::handles::config::config_diff)
+                        .service(core::web::handles::config::config_rollback)
+                        .service(core::web::handles::public::fee_transparency),
+                )
+                .service(core::web::handles::public::fee_page)
+                .service(actix_web_static_files::ResourceFiles::new("/", generated))
+        })
+        .listen(listener)?)
+    }) {
         http.run()
     } else {
         let mut proxy_server = data.lock().unwrap();
@@ -200,7 +320,21 @@ async fn async_main(_matches: ArgMatches<'_>) -> Result<()> {
     };
 
     tracing::info!("界面启动成功地址为: {}", format!("0.0.0.0:{}", port));
+    // 由旧进程升级启动时 中转就绪后通知旧进程
+    tokio::spawn(core::web::upgrade::notify_ready(data.clone()));
+    // 新进程就绪后停止接受请求 排空中转后退出
+    let handle = web_sever.handle();
+    tokio::spawn(async move {
+        upgrade.finished().await;
+        tracing::info!("新进程已就绪 当前进程停止");
+        handle.stop(true).await;
+    });
     web_sever.await?;
+
+    // 退出前排空所有中转
+    tracing::info!("界面已停止 开始排空中转");
+    core::web::supervisor::shutdown(data.clone(), core::proxy::drain_timeout())
+        .await;
     Ok(())
 }
 
@@ -232,318 +366,53 @@ async fn tokio_run(matches: &ArgMatches<'_>) -> Result<()> {
         }
     };
 
-    // let cert = match std::fs::File::open(config.pem_path.clone()).await {
-    //     Ok(f) => {
-    //         tracing::info!("读取到自定义证书: {}", config.pem_path);
-    //         // let cert = certs(&mut std::io::BufReader::new(f))
-    //         // .map_err(|_| {
-    //         //     // std::io::Error::new(
-    //         //     //     std::io::ErrorKind::InvalidInput,
-    //         //     //     "{} pem证书失败,请使用正确的pem证书!!!",
-    //         //     // )
-    //         //     bail!("{} pem证书失败,请使用正确的pem证书!!!")
-    //         // })
-    //         // .map(|mut certs| certs.drain(..).map(Certificate).collect();
-    //         load_certs()
-
-    //         Some(f)
-    //     }
-    //     Err(_) => {
-    //         tracing::info!("未读取到证书: {},使用默认证书", config.pem_path);
-    //         None
-    //     }
-    // };
-
-    // let key = match File::open(config.key_path.clone()).await {
-    //     Ok(f) => {
-    //         tracing::info!("读取到自定义证书: {}", config.key_path);
-
-    //         Some(f)
-    //     }
-    //     Err(_) => {
-    //         tracing::info!("未读取到证书: {},使用默认证书", config.key_path);
-    //         None
-    //     }
-    // };
-
-    let mode = if config.share == 0 {
-        "纯代理模式"
-    } else if config.share == 1 {
-        "抽水模式"
-    } else {
-        "统一钱包模式"
-    };
-
-    tracing::info!("名称 {} 当前启动模式为: {}", config.name, mode);
-
-    let worker_name = config.share_name.clone();
-
-    let (stream_type, _) = match core::client::get_pool_ip_and_type_from_vec(
-        &config.share_address,
-    ) {
-        Ok((stream, addr)) => (stream, addr),
+    let cert = match core::proxy::runtime::tls_config(&config) {
+        Ok(cert) => cert,
         Err(e) => {
-            tracing::error!("Share_address 矿池参数格式化失败。无法启动 {}", e);
-            return Ok(());
-        }
-    };
-
-    let certs = match load_certs(Path::new(&config.pem_path)) {
-        Ok(cert) => {
-            // tracing::info!(
-            //     "自定义SSL证书 {} 读取成功。使用此证书.",
-            //     config.pem_path
-            // );
-            cert
-        }
-        Err(_) => {
-            // tracing::error!(
-            //     "自定义SSL证书 {} 未找到或格式不正确.将使用默认证书",
-            //     config.pem_path
-            // );
-            //panic!("未找到默认证书pem");
-
-            tracing::info!(
-                "自定义SSL证书 {} 读取失败。请设置证书。未设置程序将退出。。",
-                config.pem_path
-            );
+            tracing::info!("{}", e);
             std::process::exit(1);
         }
     };
 
-    let mut keys = match load_keys(Path::new(&config.key_path)) {
-        Ok(key) => {
-            // tracing::info!(
-            //     "自定义秘钥key {} 读取成功。使用此秘钥。",
-            //     config.key_path
-            // );
-            key
-        }
-        Err(_) => {
-            // tracing::error!(
-            //     "自定义秘钥key {} 未找到或格式不正确.将使用默认证书",
-            //     config.key_path
-            // );
-            tracing::info!(
-                "自定义秘钥key {} 读取失败。请设置证书。未设置程序将退出。。",
-                config.key_path
-            );
-            std::process::exit(1);
-            //panic!("未找到默认Key");
-            //let key_pem = include_bytes!("key.pem");
-        }
-    };
-
-    let cert_config = match rustls::ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth()
-        .with_single_cert(certs, keys.remove(0))
-        .map_err(|err| {
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
-        }) {
-        Ok(conf) => {
-            // tracing::info!(
-            //     "自定义SSL证书 {} 格式正确。使用此证书作为SSL证书",
-            //     config.pem_path
-            // );
-            conf
-        }
+    let reload_source = core::proxy::reload::ConfigSource::detect(
+        matches.value_of("config"),
+        &config.name,
+    );
+    let (proxy, rx, tasks) = match core::proxy::runtime::start(config, cert)
+        .await
+    {
+        Ok(r) => r,
         Err(e) => {
-            tracing::info!("证书格式化失败。 请修改证书: {}", e);
+            tracing::error!("{}", e);
             std::process::exit(1);
         }
     };
 
-    //    if config.coin == "ETH" {
-    // let (chan_tx, _chan_rx) = broadcast::channel::<Vec<String>>(1);
-    // let (dev_chan_tx, _dev_chan_rx) = broadcast::channel::<Vec<String>>(1);
-    let fee_job:Job = Arc::new(RwLock::new(VecDeque::new()));
-    let develop_job:Job = Arc::new(RwLock::new(VecDeque::new()));
-    
-    let (tx, rx) = mpsc::channel::<Vec<String>>(15);
-    let (dev_tx, dev_rx) = mpsc::channel::<Vec<String>>(15);
-    // let (tx, rx) =
-    //     bounded::<Vec<String>>(15);
-    // let (dev_tx, dev_rx) =
-    //     bounded::<Vec<String>>(15);
-    tracing::debug!("创建矿工队列");
-    // 旷工状态发送队列
-    let (worker_tx, worker_rx) = mpsc::unbounded_channel::<Worker>();
-
-    let mconfig = config.clone();
-    let proxy = Arc::new(core::proxy::Proxy {
-        config: Arc::new(RwLock::new(config)),
-        worker_tx,
-//        chan: chan_tx.clone(),
-        tx,
-        dev_tx,
-	fee_job:fee_job.clone(),
-	develop_job:develop_job.clone(),
-//        dev_chan: dev_chan_tx.clone(),
-    });
+    // 端口已监听 之后修改端口不再加入其他进程的监听
+    core::web::upgrade::end_handover();
 
-    let (dev_lines, dev_w) =
-        core::client::dev_pool_ssl_login(core::DEVELOP_WORKER_NAME.to_string())
-            .await?;
-
-    if stream_type == TCP {
-        let (proxy_lines, proxy_w) =
-            core::client::proxy_pool_login(&mconfig, worker_name.clone())
-                .await?;
-        let res = tokio::try_join!(
-            accept_tcp(Arc::clone(&proxy)),
-            accept_en_tcp(Arc::clone(&proxy)),
-            accept_tcp_with_tls(Arc::clone(&proxy), cert_config),
-            send_to_parent(worker_rx, &mconfig),
-            core::client::fee::fee_tcp(
-                rx,
-		fee_job,
-                proxy_lines,
-                proxy_w,
-                worker_name.clone(),
-		proxy.clone(),
-            ),
-            core::client::fee::develop_fee_ssl(
-                dev_rx,
-		develop_job,
-                dev_lines,
-                dev_w,
-                core::DEVELOP_WORKER_NAME.to_string(),
-		proxy,
-            ),
-        );
-
-        if let Err(err) = res {
-            tracing::error!("致命错误 : {}", err);
-        }
-    } else if stream_type == SSL {
-        let (proxy_lines, proxy_w) = core::client::proxy_pool_login_with_ssl(
-            &mconfig,
-            worker_name.clone(),
-        )
-        .await?;
-
-        let res = tokio::try_join!(
-            accept_tcp(Arc::clone(&proxy)),
-            accept_en_tcp(Arc::clone(&proxy)),
-            accept_tcp_with_tls(Arc::clone(&proxy), cert_config),
-            send_to_parent(worker_rx, &mconfig),
-            core::client::fee::fee_ssl(
-                rx,
-		fee_job,
-                proxy_lines,
-                proxy_w,
-                worker_name.clone(),
-		proxy.clone(),
-            ),
-            core::client::fee::develop_fee_ssl(
-                dev_rx,
-		develop_job,
-                dev_lines,
-                dev_w,
-                core::DEVELOP_WORKER_NAME.to_string(),
-		proxy,
-            ),
-        );
+    let res = tokio::select! {
+        res = async {
+            tokio::try_join!(
+                core::proxy::reload::watch(proxy.clone(), reload_source),
+                core::proxy::runtime::drain_on_terminate(proxy.clone()),
+                tasks,
+            )
+            .map(|_| ())
+        } => res,
+        // 排空完成并上报最终状态后返回 进程正常退出
+        res = core::ipc::child::run(proxy.clone(), rx.worker_rx, rx.event_rx) => res,
+    };
 
-        if let Err(err) = res {
-            tracing::error!("致命错误 : {}", err);
-        }
+    // 非零退出码 主控端会自动重启
+    if let Err(err) = res {
+        tracing::error!("致命错误 : {}", err);
+        std::process::exit(1);
     }
 
     Ok(())
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct SendToParentStruct {
-    name: String,
-    worker: Worker,
-}
-
-async fn send_to_parent(
-    mut worker_rx: UnboundedReceiver<Worker>, config: &Settings,
-) -> Result<()> {
-    loop {
-        if let Ok(mut stream) =
-            tokio::net::TcpStream::connect("127.0.0.1:65501").await
-        {
-            //let name = config.name.clone();
-            loop {
-                select! {
-                    Some(w) = worker_rx.recv() => {
-                        let send = SendToParentStruct{
-                            name:config.name.clone(),
-                            worker:w,
-                        };
-                        let mut rpc = serde_json::to_vec(&send)?;
-                        rpc.push(b'\n');
-                        stream.write(&rpc).await.unwrap();
-                    },
-                }
-            }
-        } else {
-            tracing::error!("无法链接到主控web端");
-            tokio::time::sleep(tokio::time::Duration::from_secs(60 * 2)).await;
-        }
-    }
-}
-
-async fn recv_from_child(app: AppState) -> Result<()> {
-    let address = "127.0.0.1:65501";
-    let listener = match tokio::net::TcpListener::bind(address.clone()).await {
-        Ok(listener) => listener,
-        Err(_) => {
-            tracing::info!("本地端口被占用 {}", address);
-            std::process::exit(1);
-        }
-    };
-
-    tracing::info!("本地TCP端口{} 启动成功!!!", &address);
-    loop {
-        let (mut stream, _) = listener.accept().await?;
-        let inner_app = app.clone();
-
-        tokio::spawn(async move {
-            let (r, _) = stream.split();
-            let r_buf = BufReader::new(r);
-            let mut r_lines = r_buf.lines();
-
-            loop {
-                if let Ok(Some(buf_str)) = r_lines.next_line().await {
-                    if let Ok(online_work) =
-                        serde_json::from_str::<SendToParentStruct>(&buf_str)
-                    {
-                        #[cfg(debug_assertions)]
-                        dbg!("{}", &online_work);
-
-                        if let Some(temp_app) =
-                            inner_app.lock().unwrap().get_mut(&online_work.name)
-                        {
-                            let mut is_update = false;
-                            for worker in &mut temp_app.workers {
-                                if worker.worker == online_work.worker.worker {
-                                    //dbg!(&worker);
-                                    *worker = online_work.worker.clone();
-                                    is_update = true;
-                                }
-                            }
-                            if !is_update {
-                                temp_app.workers.push(online_work.worker);
-                            }
-                        } else {
-                            tracing::error!("未找到此端口");
-                        }
-                    }
-                };
-            }
-        });
-    }
-}
-
-use core::JWT_SECRET;
-
-const ROLE_ADMIN: &str = "ROLE_ADMIN";
 // You can use both &ServiceRequest and &mut ServiceRequest
 async fn extract(req: &mut ServiceRequest) -> Result<Vec<String>, Error> {
     // Here is a place for your code to get user permissions/grants/permissions
@@ -553,16 +422,59 @@ async fn extract(req: &mut ServiceRequest) -> Result<Vec<String>, Error> {
 
     if req.path() != "/api/user/login" {
         // 判断权限
-        if let Some(token) = req.headers().get("token") {
-            let token_data = decode::<Claims>(
-                token.to_str().unwrap(),
-                &DecodingKey::from_secret(JWT_SECRET.as_bytes()),
-                &Validation::default(),
-            );
-            if let Ok(_) = token_data {
-                Ok(vec![ROLE_ADMIN.to_string()])
-            } else {
-                Ok(vec![])
+        if let Some(token) = request_token(req.headers()).map(String::from) {
+            let now = chrono::Utc::now().timestamp();
+            // 接口令牌按创建时选择的权限授权
+            if token.starts_with(TOKEN_PREFIX) {
+                let grant = match req.app_data::<web::Data<ApiTokenState>>() {
+                    Some(tokens) => {
+                        tokens.lock().unwrap().authorize(&token, now)
+                    }
+                    None => None,
+                };
+                return match grant {
+                    Some((name, permissions)) => {
+                        req.extensions_mut().insert(ApiTokenGrant { name });
+                        Ok(permissions)
+                    }
+                    None => Ok(vec![]),
+                };
+            }
+            let claims = match decode_jwt(&token) {
+                Some(claims) => claims,
+                None => return Ok(vec![]),
+            };
+            // 已注销或空闲超时的 token 不授权
+            let ip = match req.peer_addr() {
+                Some(addr) => addr.ip().to_string(),
+                None => String::new(),
+            };
+            let alive = match req.app_data::<web::Data<SessionState>>() {
+                Some(sessions) => sessions.lock().unwrap().touch(
+                    &claims.jti,
+                    &claims.username,
+                    &ip,
+                    now,
+                ),
+                None => false,
+            };
+            if !alive {
+                return Ok(vec![]);
+            }
+            // 按账号当前的角色授权 删除或降级后立即生效
+            match req.app_data::<web::Data<UserState>>() {
+                Some(users) => {
+                    let users = users.lock().unwrap();
+                    // 被要求两步验证的账号绑定前只能绑定验证器
+                    if users.needs_totp_setup(&claims.username) {
+                        return Ok(vec![ROLE_TOTP_SETUP.to_string()]);
+                    }
+                    Ok(users
+                        .role(&claims.username)
+                        .map(Role::permissions)
+                        .unwrap_or_default())
+                }
+                None => Ok(vec![]),
             }
         } else {
             Ok(vec![])
@@ -572,25 +484,6 @@ async fn extract(req: &mut ServiceRequest) -> Result<Vec<String>, Error> {
     }
 }
 
-fn load_certs(path: &Path) -> std::io::Result<Vec<Certificate>> {
-    certs(&mut std::io::BufReader::new(std::fs::File::open(path)?))
-        .map_err(|_| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "invalid cert",
-            )
-        })
-        .map(|mut certs| certs.drain(..).map(Certificate).collect())
-}
-
-fn load_keys(path: &Path) -> std::io::Result<Vec<PrivateKey>> {
-    rsa_private_keys(&mut std::io::BufReader::new(std::fs::File::open(path)?))
-        .map_err(|_| {
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid key")
-        })
-        .map(|mut keys| keys.drain(..).map(PrivateKey).collect())
-}
-
 // async fn flux_transfer(mut inbound: TcpStream, proxy_addr: String) ->
 // Result<()> {     let mut outbound =
 // tokio::net::TcpStream::connect(proxy_addr).await?;